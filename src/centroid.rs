@@ -0,0 +1,234 @@
+use super::core::Classifier;
+use super::util::{quantile, squared_distance, RunningStats};
+
+/// A Nearest Centroid classifier: each class is represented by the mean of
+/// its training examples, and a datapoint gets the label of the closest
+/// mean by Euclidean distance.
+///
+/// With a shrink threshold it becomes a nearest shrunken centroid
+/// classifier (Tibshirani et al., 2002), suited to many noisy features:
+/// each class mean's deviation from the overall mean is standardized
+/// feature by feature and soft-thresholded towards zero, so features that
+/// don't tell the classes apart drop out of the comparison.
+#[derive(Debug, Clone)]
+pub struct NearestCentroidClassifier<T> {
+    classes: Vec<T>,
+    // One running mean per feature, for each class in `classes`.
+    stats: Vec<Vec<RunningStats>>,
+    shrink_threshold: Option<f64>,
+}
+
+impl<T> Default for NearestCentroidClassifier<T> {
+    fn default() -> NearestCentroidClassifier<T> {
+        NearestCentroidClassifier { classes: Vec::new(), stats: Vec::new(), shrink_threshold: None }
+    }
+}
+
+impl<T> NearestCentroidClassifier<T> where T: PartialEq + Clone {
+    /// Construct a new NearestCentroidClassifier.
+    pub fn new() -> NearestCentroidClassifier<T> {
+        NearestCentroidClassifier::default()
+    }
+
+    /// Shrink the class centroids by `threshold`, or not at all with None
+    /// (the default). For class k and feature j, the deviation of the class
+    /// mean from the overall mean is divided by `m_k (s_j + s_0)` to give
+    /// `d_kj`, where `s_j` is the pooled within-class standard deviation of
+    /// the feature, `s_0` its median over the features, and
+    /// `m_k = sqrt(1/n_k - 1/n)`. The deviation is shrunk to
+    /// `sign(d_kj) max(|d_kj| - threshold, 0)`, and datapoints are compared
+    /// to the shrunken centroids with each feature scaled by
+    /// `1 / (s_j + s_0)`. Larger thresholds drop more features; a feature
+    /// whose deviations all shrink to zero no longer affects predictions.
+    /// Takes effect immediately, as the statistics are kept.
+    pub fn set_shrink_threshold(&mut self, threshold: Option<f64>) {
+        assert!(threshold.is_none_or(|t| t >= 0.0), "shrink threshold must be non-negative");
+        self.shrink_threshold = threshold;
+    }
+
+    /// Update the centroids with more examples, without revisiting earlier
+    /// ones. Each class's mean moves towards every new example by
+    /// `(x - mean) / count`, so fitting in batches gives the same centroids
+    /// as fitting everything at once. Classes not seen before are added.
+    pub fn partial_fit(&mut self, data: &[Vec<f64>], labels: &[T]) {
+        assert_eq!(data.len(), labels.len(), "data and labels differ in length");
+        for (x, label) in data.iter().zip(labels.iter()) {
+            let i = match self.classes.iter().position(|class| class == label) {
+                Some(i) => i,
+                None    => {
+                    self.classes.push(label.clone());
+                    self.stats.push(vec![RunningStats::new(); x.len()]);
+                    self.classes.len() - 1
+                },
+            };
+            assert_eq!(self.stats[i].len(), x.len(), "examples differ in dimension");
+            for (stats, &v) in self.stats[i].iter_mut().zip(x.iter()) {
+                stats.push(v);
+            }
+        }
+    }
+
+    /// Get each class with its centroid and number of examples, in order of
+    /// first appearance in the training labels. Return None if called
+    /// before `fit()`.
+    pub fn centroids(&self) -> Option<Vec<(T, Vec<f64>, u64)>> {
+        if self.classes.is_empty() {
+            return None;
+        }
+        Some(self.classes.iter().zip(self.stats.iter()).map(|(class, stats)| {
+            let count = stats.first().map_or(0, |s| s.count());
+            (class.clone(), stats.iter().map(|s| s.mean()).collect(), count)
+        }).collect())
+    }
+
+    /// Get the centroids predictions compare against, in the order of
+    /// `classes`, along with each feature's scale in the distance if
+    /// shrinking.
+    fn model(&self) -> (Vec<Vec<f64>>, Option<Vec<f64>>) {
+        let means: Vec<Vec<f64>> = self.stats.iter().map(|stats| stats.iter().map(|s| s.mean()).collect()).collect();
+        let threshold = match self.shrink_threshold {
+            Some(threshold) if !self.stats.is_empty() => threshold,
+            _                                           => return (means, None),
+        };
+        let counts: Vec<f64> = self.stats.iter().map(|stats| stats.first().map_or(0, |s| s.count()) as f64).collect();
+        let n: f64 = counts.iter().sum();
+        let dim = means[0].len();
+        let overall: Vec<f64> = (0..dim)
+            .map(|j| means.iter().zip(counts.iter()).map(|(m, c)| m[j] * c).sum::<f64>() / n)
+            .collect();
+        // Pooled within-class standard deviations, with n - K degrees of
+        // freedom.
+        let dof = (n - self.classes.len() as f64).max(1.0);
+        let sd: Vec<f64> = (0..dim).map(|j| {
+            let within: f64 = self.stats.iter().map(|stats| stats[j].variance() * stats[j].count() as f64).sum();
+            (within / dof).sqrt()
+        }).collect();
+        let s0 = quantile(&sd, 0.5);
+        let scale: Vec<f64> = sd.iter().map(|s| s + s0).collect();
+        let centroids = means.iter().zip(counts.iter()).map(|(mean, &count)| {
+            let m = (1.0 / count - 1.0 / n).max(0.0).sqrt();
+            (0..dim).map(|j| {
+                let width = m * scale[j];
+                if width == 0.0 {
+                    return overall[j];
+                }
+                let d = (mean[j] - overall[j]) / width;
+                let shrunk = d.signum() * (d.abs() - threshold).max(0.0);
+                overall[j] + width * shrunk
+            }).collect()
+        }).collect();
+        (centroids, Some(scale))
+    }
+
+    /// Predict the label of the centroid nearest to `x`, the earliest class
+    /// on ties. Return None if there are no centroids.
+    fn nearest(&self, (centroids, scale): &(Vec<Vec<f64>>, Option<Vec<f64>>), x: &[f64]) -> Option<T> {
+        let mut best: Option<(usize, f64)> = None;
+        for (i, centroid) in centroids.iter().enumerate() {
+            let dist = match scale {
+                Some(scale) => x.iter().zip(centroid.iter()).zip(scale.iter())
+                    .map(|((v, c), s)| if *s > 0.0 { ((v - c) / s).powi(2) } else { 0.0 })
+                    .sum(),
+                None        => squared_distance(x, centroid),
+            };
+            if best.is_none_or(|(_, best_dist)| dist < best_dist) {
+                best = Some((i, dist));
+            }
+        }
+        best.map(|(i, _)| self.classes[i].clone())
+    }
+}
+
+impl<T> Classifier for NearestCentroidClassifier<T> where T: PartialEq + Clone {
+    type ExampleType = Vec<f64>;
+    type LabelType = T;
+
+    /// Compute the centroid of each class, discarding any earlier ones.
+    fn fit(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>) {
+        self.classes.clear();
+        self.stats.clear();
+        self.partial_fit(&data, &labels);
+    }
+
+    /// Predict the labels of datapoints. Return None if `predict()` is
+    /// called before `fit()`.
+    fn predict(&self, data: &[Vec<f64>]) -> Option<Vec<T>> {
+        let model = self.model();
+        data.iter().map(|x| self.nearest(&model, x)).collect()
+    }
+
+    /// Predict the label of the nearest centroid, the earliest class on
+    /// ties. Return None if `predict_one()` is called before `fit()`.
+    fn predict_one(&self, x: &Vec<f64>) -> Option<T> {
+        self.nearest(&self.model(), x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Rng;
+
+    #[test]
+    fn test_partial_fit_matches_fit() {
+        let mut rng = Rng::new(5);
+        let labels: Vec<&str> = (0..90).map(|i| ["a", "b", "c"][i % 3]).collect();
+        let data: Vec<Vec<f64>> = labels.iter().enumerate()
+            .map(|(i, _)| vec![(i % 3) as f64 * 4.0 + rng.next_f64(), rng.next_f64() * 10.0])
+            .collect();
+
+        let mut batch = NearestCentroidClassifier::new();
+        assert_eq!(batch.predict_one(&data[0]), None);
+        batch.fit(data.clone(), labels.clone());
+
+        let mut online = NearestCentroidClassifier::new();
+        for start in (0..90).step_by(20) {
+            let end = (start + 20).min(90);
+            online.partial_fit(&data[start..end], &labels[start..end]);
+        }
+        assert_eq!(online.centroids(), batch.centroids());
+
+        let centroids = batch.centroids().unwrap();
+        assert_eq!(centroids.iter().map(|c| (c.0, c.2)).collect::<Vec<_>>(), vec![("a", 30), ("b", 30), ("c", 30)]);
+        assert_eq!(batch.predict(&[vec![0.5, 5.0], vec![8.5, 5.0]]).unwrap(), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_shrunken_centroids() {
+        let mut rng = Rng::new(13);
+        // Two features tell three classes apart; thirty more are noise.
+        let mut sample = |n: usize| -> (Vec<Vec<f64>>, Vec<usize>) {
+            let labels: Vec<usize> = (0..n).map(|i| i % 3).collect();
+            let data = labels.iter().map(|&l| {
+                let mut x = vec![l as f64 + 0.6 * rng.next_gaussian(), (l == 1) as u8 as f64 + 0.6 * rng.next_gaussian()];
+                x.extend((0..30).map(|_| rng.next_gaussian()));
+                x
+            }).collect();
+            (data, labels)
+        };
+        let (train, train_labels) = sample(30);
+        let (test, test_labels) = sample(600);
+        let accuracy = |clf: &NearestCentroidClassifier<usize>| {
+            let predictions = clf.predict(&test).unwrap();
+            predictions.iter().zip(test_labels.iter()).filter(|(p, l)| p == l).count() as f64 / test.len() as f64
+        };
+
+        let mut clf = NearestCentroidClassifier::new();
+        clf.fit(train, train_labels);
+        let plain = accuracy(&clf);
+        clf.set_shrink_threshold(Some(2.0));
+        let shrunk = accuracy(&clf);
+        assert!(shrunk >= plain + 0.05, "{} vs {}", shrunk, plain);
+
+        // Noise features mostly shrink to the overall mean in every class,
+        // the informative one doesn't.
+        let (centroids, _) = clf.model();
+        let dropped = (2..32).filter(|&j| centroids.iter().all(|c| c[j] == centroids[0][j])).count();
+        assert!(dropped >= 25, "{}", dropped);
+        assert!(centroids[0][0] < centroids[1][0] && centroids[1][0] < centroids[2][0]);
+
+        // Without shrinkage, the plain centroids are back.
+        clf.set_shrink_threshold(None);
+        assert_eq!(accuracy(&clf), plain);
+    }
+}
@@ -0,0 +1,104 @@
+use super::core::ProbabilisticClassifier;
+use super::knn::KNNClassifier;
+use std::hash::Hash;
+
+/// Wraps a trained `KNNClassifier` to predict sets of labels instead of
+/// single labels (split conformal prediction). After calibration on labeled
+/// data the classifier hasn't seen, the set predicted at level `alpha`
+/// contains the true label with probability at least `1 - alpha`, provided
+/// the calibration and test data come from the same distribution.
+#[derive(Debug, Clone)]
+pub struct ConformalKNN<T> {
+    knn: KNNClassifier<T>,
+    scores: Option<Vec<f64>>,
+}
+
+impl<T> ConformalKNN<T> where T: Hash + Eq + Clone {
+    /// Construct a new ConformalKNN around a trained classifier.
+    pub fn new(knn: KNNClassifier<T>) -> ConformalKNN<T> {
+        ConformalKNN { knn, scores: None }
+    }
+
+    /// The wrapped classifier.
+    pub fn classifier(&self) -> &KNNClassifier<T> {
+        &self.knn
+    }
+
+    /// Score how unusual each calibration datapoint's true label is to the
+    /// classifier, as one minus its predicted probability. Return None if
+    /// the classifier hasn't been trained.
+    pub fn calibrate(&mut self, data: &[Vec<f64>], labels: &[T]) -> Option<()> {
+        assert_eq!(data.len(), labels.len(), "data and labels differ in length");
+        let mut scores = Vec::with_capacity(data.len());
+        for (x, label) in data.iter().zip(labels.iter()) {
+            scores.push(self.nonconformity(x, label)?);
+        }
+        scores.sort_by(|a, b| a.total_cmp(b));
+        self.scores = Some(scores);
+        Some(())
+    }
+
+    /// Predict the set of labels that contains the true label of `x` with
+    /// probability at least `1 - alpha`, in order of first appearance in
+    /// the training labels. Return None if called before `calibrate()`.
+    pub fn predict_set(&self, x: &[f64], alpha: f64) -> Option<Vec<T>> {
+        assert!(alpha > 0.0 && alpha < 1.0, "alpha must be in (0, 1)");
+        let scores = self.scores.as_ref()?;
+        let n = scores.len();
+        // The ceil((n + 1)(1 - alpha))-th smallest calibration score; with
+        // too few calibration points to reach it, every label qualifies.
+        let rank = ((n + 1) as f64 * (1.0 - alpha)).ceil() as usize;
+        let threshold = if rank > n { f64::INFINITY } else { scores[rank - 1] };
+        let proba = self.knn.predict_proba(&x.to_vec())?;
+        Some(proba.into_iter().filter(|&(_, p)| 1.0 - p <= threshold).map(|(label, _)| label).collect())
+    }
+
+    /// Score how unusual `label` is for `x`: one minus its predicted
+    /// probability.
+    fn nonconformity(&self, x: &[f64], label: &T) -> Option<f64> {
+        let proba = self.knn.predict_proba(&x.to_vec())?;
+        let p = proba.iter().find(|(l, _)| l == label).map_or(0.0, |&(_, p)| p);
+        Some(1.0 - p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Classifier;
+    use crate::util::Rng;
+
+    /// Three overlapping classes along a line.
+    fn sample(rng: &mut Rng, n: usize) -> (Vec<Vec<f64>>, Vec<usize>) {
+        let labels: Vec<usize> = (0..n).map(|_| rng.gen_range(3)).collect();
+        let data = labels.iter().map(|&l| vec![l as f64 + 1.5 * (rng.next_f64() - 0.5) * 2.0]).collect();
+        (data, labels)
+    }
+
+    #[test]
+    fn test_coverage() {
+        let mut rng = Rng::new(17);
+        let (train, train_labels) = sample(&mut rng, 300);
+        let (calib, calib_labels) = sample(&mut rng, 300);
+        let (test, test_labels) = sample(&mut rng, 1000);
+
+        let mut knn = KNNClassifier::new(10);
+        knn.fit(train, train_labels);
+        let mut conformal = ConformalKNN::new(knn);
+        assert_eq!(conformal.predict_set(&test[0], 0.1), None);
+        conformal.calibrate(&calib, &calib_labels).unwrap();
+
+        for &alpha in &[0.05, 0.2] {
+            let sets: Vec<Vec<usize>> = test.iter().map(|x| conformal.predict_set(x, alpha).unwrap()).collect();
+            let covered = sets.iter().zip(test_labels.iter()).filter(|(set, label)| set.contains(label)).count();
+            let coverage = covered as f64 / test.len() as f64;
+            // Ties among the discrete KNN scores make the sets err on the
+            // large side, so coverage can exceed 1 - alpha by a fair bit.
+            assert!(coverage > 1.0 - alpha - 0.03 && coverage < 1.0, "alpha {}: coverage {}", alpha, coverage);
+            // The classes overlap, so some sets hold more than one label,
+            // but not every set holds all of them.
+            assert!(sets.iter().any(|set| set.len() > 1));
+            assert!(sets.iter().any(|set| set.len() < 3));
+        }
+    }
+}
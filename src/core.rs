@@ -1,3 +1,12 @@
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::ops::ControlFlow;
+use core::time::Duration;
+use super::util::RandomState;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::fmt;
 
 pub type Example<T> = Vec<T>;
 pub type Dataset<T> = Vec<Example<T>>;
@@ -13,8 +22,193 @@ pub trait Classifier {
     fn fit(&mut self, data: Vec<Self::ExampleType>, labels: Vec<Self::LabelType>);
 
     /// Predict the labels of a bunch of datapoints.
-    fn predict(&self, data: &Vec<Self::ExampleType>) -> Option<Vec<Self::LabelType>>;
+    fn predict(&self, data: &[Self::ExampleType]) -> Option<Vec<Self::LabelType>>;
 
     /// Predict the label of one datapoint.
     fn predict_one(&self, x: &Self::ExampleType) -> Option<Self::LabelType>;
 }
+
+/// A classifier that can estimate how likely each class is, not just pick
+/// the most likely one.
+pub trait ProbabilisticClassifier: Classifier {
+    /// Estimate the probability of each class for one datapoint, as (label,
+    /// probability) pairs summing to one.
+    fn predict_proba(&self, x: &Self::ExampleType) -> Option<Vec<(Self::LabelType, f64)>>;
+}
+
+/// Errors raised while training a classifier.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FitError {
+    /// A batch had a different number of examples and labels.
+    LengthMismatch { batch: usize, examples: usize, labels: usize },
+    /// An example in a batch had a different number of features than the
+    /// ones seen before it.
+    DimensionMismatch { batch: usize, expected: usize, got: usize },
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for FitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FitError::LengthMismatch { batch, examples, labels } =>
+                write!(f, "batch {}: {} examples but {} labels", batch, examples, labels),
+            FitError::DimensionMismatch { batch, expected, got } =>
+                write!(f, "batch {}: expected {} features, got {}", batch, expected, got),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for FitError {}
+
+/// A classifier that can be trained incrementally, one batch at a time, so
+/// that the training data never has to be in memory all at once.
+pub trait OnlineClassifier: Classifier<ExampleType = Vec<f64>> {
+    /// Update the classifier with a batch of labeled data. Unlike `fit()`,
+    /// this keeps what was learned from earlier batches.
+    fn partial_fit(&mut self, data: Vec<Vec<f64>>, labels: Vec<Self::LabelType>);
+
+    /// The number of features the classifier has been trained on, or None
+    /// if it hasn't seen any data yet.
+    fn n_features(&self) -> Option<usize>;
+
+    /// Train the classifier from a stream of (examples, labels) batches by
+    /// calling `partial_fit()` on each. The first example seen fixes the
+    /// number of features (unless the classifier was already trained);
+    /// a batch that disagrees is rejected before it is learned from, with
+    /// an error naming its zero-based index.
+    fn fit_from_iter<I>(&mut self, batches: I) -> Result<(), FitError>
+        where I: IntoIterator<Item = (Vec<Vec<f64>>, Vec<Self::LabelType>)>
+    {
+        let mut expected = self.n_features();
+        for (batch, (data, labels)) in batches.into_iter().enumerate() {
+            if data.len() != labels.len() {
+                return Err(FitError::LengthMismatch { batch, examples: data.len(), labels: labels.len() });
+            }
+            for x in &data {
+                match expected {
+                    Some(dim) if dim != x.len() =>
+                        return Err(FitError::DimensionMismatch { batch, expected: dim, got: x.len() }),
+                    Some(_) => {},
+                    None    => expected = Some(x.len()),
+                }
+            }
+            self.partial_fit(data, labels);
+        }
+        Ok(())
+    }
+}
+
+/// What an iterative estimator reports at the end of each training epoch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FitMetrics {
+    /// The training loss over the epoch, in the estimator's own measure.
+    pub loss: f64,
+    /// The time the epoch took.
+    pub elapsed: Duration,
+}
+
+/// Hooks into the training loop of an iterative estimator, e.g. to log
+/// progress or stop a run that isn't improving.
+pub trait FitCallback {
+    /// Called after each epoch, counted from zero. Returning `Break` stops
+    /// training after this epoch.
+    fn on_epoch_end(&mut self, epoch: usize, metrics: &FitMetrics) -> ControlFlow<()>;
+}
+
+/// A callback that records the loss of every epoch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LossHistory {
+    /// The loss of each epoch so far, in order.
+    pub losses: Vec<f64>,
+}
+
+impl LossHistory {
+    /// Construct an empty LossHistory.
+    pub fn new() -> LossHistory {
+        LossHistory::default()
+    }
+}
+
+impl FitCallback for LossHistory {
+    fn on_epoch_end(&mut self, _epoch: usize, metrics: &FitMetrics) -> ControlFlow<()> {
+        self.losses.push(metrics.loss);
+        ControlFlow::Continue(())
+    }
+}
+
+/// Settings for stopping training once the score on a held-out validation
+/// set stops improving.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EarlyStopping {
+    /// The fraction of the training data held out for validation.
+    pub validation_fraction: f64,
+    /// The number of epochs without improvement to wait before stopping.
+    pub patience: usize,
+    /// The smallest increase in validation score that counts as improving.
+    pub min_delta: f64,
+    /// Randomness for choosing the validation set.
+    pub random_state: Option<RandomState>,
+}
+
+/// What an `EarlyStopper` makes of the latest validation score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Progress {
+    /// The score is the best so far; the current parameters are worth
+    /// keeping.
+    Improved,
+    /// The score didn't improve, but patience isn't exhausted yet.
+    Stalled,
+    /// The score hasn't improved for `patience` epochs; training should
+    /// stop and the best parameters be restored.
+    Stop,
+}
+
+/// Tracks validation scores across epochs and decides when to stop. The
+/// estimators drive one internally when configured with `EarlyStopping`;
+/// callers running their own loop around `partial_fit()` can drive one
+/// themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EarlyStopper {
+    patience: usize,
+    min_delta: f64,
+    epoch: usize,
+    best: Option<(usize, f64)>,
+}
+
+impl EarlyStopper {
+    /// Construct an EarlyStopper that stops after `patience` epochs without
+    /// an improvement of more than `min_delta` (higher scores are better).
+    pub fn new(patience: usize, min_delta: f64) -> EarlyStopper {
+        EarlyStopper { patience, min_delta, epoch: 0, best: None }
+    }
+
+    /// Record the validation score of the next epoch.
+    pub fn update(&mut self, score: f64) -> Progress {
+        let epoch = self.epoch;
+        self.epoch += 1;
+        match self.best {
+            Some((best_epoch, best)) if score <= best + self.min_delta => {
+                if epoch - best_epoch >= self.patience {
+                    Progress::Stop
+                } else {
+                    Progress::Stalled
+                }
+            },
+            _ => {
+                self.best = Some((epoch, score));
+                Progress::Improved
+            },
+        }
+    }
+
+    /// The epoch, counted from zero, with the best score so far.
+    pub fn best_epoch(&self) -> Option<usize> {
+        self.best.map(|(epoch, _)| epoch)
+    }
+
+    /// The best score so far.
+    pub fn best_score(&self) -> Option<f64> {
+        self.best.map(|(_, score)| score)
+    }
+}
@@ -0,0 +1,808 @@
+use super::util::{quantile, Counter};
+#[cfg(feature = "flate2")]
+use flate2::read::MultiGzDecoder;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// A labeled dataset read from disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabeledData {
+    /// Names of the feature columns, if the file had a header.
+    pub feature_names: Option<Vec<String>>,
+    /// One row of features per example.
+    pub data: Vec<Vec<f64>>,
+    /// The label of each example, kept as the raw field text.
+    pub labels: Vec<String>,
+    /// Which feature values are missing. Missing cells hold NaN in `data`,
+    /// but the mask, not the NaN, is what marks them as missing.
+    pub missing: MissingMask,
+}
+
+impl LabeledData {
+    /// Count the missing values in each feature column.
+    pub fn missing_counts(&self) -> Vec<usize> {
+        let dim = self.data.first().map_or(0, |x| x.len());
+        (0..dim).map(|j| (0..self.data.len()).filter(|&i| self.missing.is_missing(i, j)).count()).collect()
+    }
+
+    /// Get the indices of the rows without missing values.
+    pub fn complete_rows(&self) -> Vec<usize> {
+        (0..self.data.len()).filter(|&i| self.missing.row_is_complete(i)).collect()
+    }
+
+    /// Return a copy of the dataset keeping only the rows without missing
+    /// values.
+    pub fn drop_missing_rows(&self) -> LabeledData {
+        let rows = self.complete_rows();
+        let dim = self.data.first().map_or(0, |x| x.len());
+        LabeledData {
+            feature_names: self.feature_names.clone(),
+            data: rows.iter().map(|&i| self.data[i].clone()).collect(),
+            labels: rows.iter().map(|&i| self.labels[i].clone()).collect(),
+            missing: MissingMask::new(rows.len(), dim),
+        }
+    }
+
+    /// Summarize the features column by column, along with the label
+    /// distribution. The values marked in `missing` count as missing; a
+    /// literal NaN is a value like any other.
+    pub fn describe(&self) -> DataSummary {
+        let mut summary = summarize(&self.data, |i, j| self.missing.is_missing(i, j));
+        summary.feature_names = self.feature_names.clone();
+        let ctr = Counter::with_iterator(self.labels.iter().cloned());
+        let mut label_counts: Vec<(String, u64)> = ctr.iter().map(|(label, &n)| (label.clone(), n)).collect();
+        label_counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        summary.label_counts = Some(label_counts);
+        summary
+    }
+}
+
+/// A per-cell record of which values of a dataset are missing, stored as a
+/// bitset in row-major order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MissingMask {
+    n_rows: usize,
+    n_cols: usize,
+    bits: Vec<u64>,
+}
+
+impl MissingMask {
+    /// Construct a mask for `n_rows` x `n_cols` values, none of them missing.
+    pub fn new(n_rows: usize, n_cols: usize) -> MissingMask {
+        MissingMask { n_rows, n_cols, bits: vec![0; (n_rows * n_cols).div_ceil(64)] }
+    }
+
+    /// Check whether the value at (`row`, `col`) is missing.
+    pub fn is_missing(&self, row: usize, col: usize) -> bool {
+        let bit = self.bit(row, col);
+        self.bits[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    /// Mark the value at (`row`, `col`) as missing or observed.
+    pub fn set_missing(&mut self, row: usize, col: usize, missing: bool) {
+        let bit = self.bit(row, col);
+        if missing {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        } else {
+            self.bits[bit / 64] &= !(1 << (bit % 64));
+        }
+    }
+
+    /// Check whether every value in `row` is observed.
+    pub fn row_is_complete(&self, row: usize) -> bool {
+        (0..self.n_cols).all(|col| !self.is_missing(row, col))
+    }
+
+    /// Append a row to the mask.
+    fn push_row(&mut self, missing: &[bool]) {
+        if self.n_rows == 0 {
+            self.n_cols = missing.len();
+        }
+        assert_eq!(missing.len(), self.n_cols, "row length differs from mask width");
+        self.n_rows += 1;
+        self.bits.resize((self.n_rows * self.n_cols).div_ceil(64), 0);
+        for (col, &m) in missing.iter().enumerate() {
+            self.set_missing(self.n_rows - 1, col, m);
+        }
+    }
+
+    fn bit(&self, row: usize, col: usize) -> usize {
+        assert!(row < self.n_rows && col < self.n_cols, "({}, {}) out of bounds", row, col);
+        row * self.n_cols + col
+    }
+}
+
+/// Summary statistics of one feature column. Missing values are counted,
+/// and left out of the other statistics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnSummary {
+    /// Number of values that are not missing.
+    pub count: usize,
+    /// Number of missing values.
+    pub missing: usize,
+    pub mean: f64,
+    /// Sample standard deviation.
+    pub std: f64,
+    pub min: f64,
+    /// 25th percentile.
+    pub p25: f64,
+    /// Median.
+    pub p50: f64,
+    /// 75th percentile.
+    pub p75: f64,
+    pub max: f64,
+}
+
+/// Per-column summary statistics of a dataset, as built by `describe()`.
+/// Displays as an aligned table with one row per feature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataSummary {
+    /// Names of the features, if known.
+    pub feature_names: Option<Vec<String>>,
+    pub columns: Vec<ColumnSummary>,
+    /// Number of examples per label, most frequent first, if the data is
+    /// labeled.
+    pub label_counts: Option<Vec<(String, u64)>>,
+}
+
+/// Compute summary statistics for every column of `data`. Without a
+/// missing-value mask to go by, NaN values count as missing; see
+/// `LabeledData::describe()` for data loaded with one.
+pub fn describe(data: &[Vec<f64>]) -> DataSummary {
+    summarize(data, |i, j| data[i][j].is_nan())
+}
+
+/// Compute summary statistics for every column of `data`, leaving out the
+/// values at (row, column) for which `is_missing` holds.
+fn summarize<F: Fn(usize, usize) -> bool>(data: &[Vec<f64>], is_missing: F) -> DataSummary {
+    let dim = data.first().map_or(0, |x| x.len());
+    let columns = (0..dim).map(|j| {
+        let values: Vec<f64> = data.iter().enumerate().filter(|&(i, _)| !is_missing(i, j)).map(|(_, x)| x[j]).collect();
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let var = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (count as f64 - 1.0);
+        ColumnSummary {
+            count,
+            missing: data.len() - count,
+            mean,
+            std: var.sqrt(),
+            min: quantile(&values, 0.0),
+            p25: quantile(&values, 0.25),
+            p50: quantile(&values, 0.5),
+            p75: quantile(&values, 0.75),
+            max: quantile(&values, 1.0),
+        }
+    }).collect();
+    DataSummary { feature_names: None, columns, label_counts: None }
+}
+
+impl fmt::Display for DataSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> = match self.feature_names {
+            Some(ref names) => names.clone(),
+            None            => (0..self.columns.len()).map(|i| format!("f{}", i)).collect(),
+        };
+        let width = names.iter().map(|n| n.len()).fold("feature".len(), usize::max);
+        write!(f, "{:<width$}", "feature", width = width)?;
+        for head in &["count", "missing", "mean", "std", "min", "25%", "50%", "75%", "max"] {
+            write!(f, " {:>10}", head)?;
+        }
+        writeln!(f)?;
+        for (name, col) in names.iter().zip(self.columns.iter()) {
+            write!(f, "{:<width$} {:>10} {:>10}", name, col.count, col.missing, width = width)?;
+            for val in &[col.mean, col.std, col.min, col.p25, col.p50, col.p75, col.max] {
+                write!(f, " {:>10.4}", val)?;
+            }
+            writeln!(f)?;
+        }
+        if let Some(ref counts) = self.label_counts {
+            writeln!(f)?;
+            let width = counts.iter().map(|(l, _)| l.len()).fold("label".len(), usize::max);
+            writeln!(f, "{:<width$} {:>10}", "label", "count", width = width)?;
+            for (label, n) in counts {
+                writeln!(f, "{:<width$} {:>10}", label, n, width = width)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Errors raised while loading a dataset. Line and column numbers start at
+/// 1; columns count the fields of a record, the label included.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The underlying reader failed.
+    Io(io::Error),
+    /// A field could not be parsed, or a record is malformed.
+    Parse { line: usize, col: usize, message: String },
+    /// A record has a different number of features than the ones before it.
+    InconsistentWidth { line: usize, expected: usize, got: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "{}", e),
+            LoadError::Parse { line, col, ref message } =>
+                write!(f, "line {}, column {}: {}", line, col, message),
+            LoadError::InconsistentWidth { line, expected, got } =>
+                write!(f, "line {}: expected {} features, got {}", line, expected, got),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LoadError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+/// Load a CSV file from `reader`. All columns except `label_column` are
+/// parsed as `f64` features; when `label_column` is `None` the last column
+/// holds the labels. Fields may be double-quoted, with `""` standing for a
+/// literal quote inside a quoted field. Empty feature fields and `?` are
+/// read as missing values.
+pub fn load_csv<R: Read>(reader: R, has_header: bool, label_column: Option<usize>) -> Result<LabeledData, LoadError> {
+    let mut reader = BufReader::new(reader);
+    let mut line_no = 0;
+    let mut feature_names = None;
+    let mut data = Vec::new();
+    let mut labels = Vec::new();
+    let mut missing = MissingMask::default();
+    let mut first = true;
+
+    while let Some((line, record)) = read_record(&mut reader, &mut line_no)? {
+        if first && has_header {
+            feature_names = Some(split_label(record, line, label_column)?.0);
+        } else {
+            let (row, row_missing, label) = parse_record(record, line, label_column)?;
+            if !data.is_empty() && row.len() != missing.n_cols {
+                return Err(LoadError::InconsistentWidth { line, expected: missing.n_cols, got: row.len() });
+            }
+            missing.push_row(&row_missing);
+            data.push(row);
+            labels.push(label);
+        }
+        first = false;
+    }
+
+    Ok(LabeledData { feature_names, data, labels, missing })
+}
+
+/// Load a gzip-compressed CSV file from `reader` as in `load_csv()`,
+/// decompressing it as it is read.
+#[cfg(feature = "flate2")]
+pub fn load_csv_gz<R: Read>(reader: R, has_header: bool, label_column: Option<usize>) -> Result<LabeledData, LoadError> {
+    load_csv(MultiGzDecoder::new(reader), has_header, label_column)
+}
+
+/// Load the CSV file at `path` as in `load_csv()`. Files whose name ends in
+/// `.gz` are decompressed as they are read, which needs the `flate2`
+/// feature; without it they fail with an `Unsupported` I/O error.
+pub fn load_csv_path<P: AsRef<Path>>(path: P, has_header: bool, label_column: Option<usize>) -> Result<LabeledData, LoadError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        #[cfg(feature = "flate2")]
+        return load_csv_gz(file, has_header, label_column);
+        #[cfg(not(feature = "flate2"))]
+        return Err(LoadError::Io(io::Error::new(io::ErrorKind::Unsupported,
+                                                "reading .gz files needs the `flate2` feature")));
+    }
+    load_csv(file, has_header, label_column)
+}
+
+/// Reads a labeled CSV file lazily in batches of a fixed number of rows, so
+/// that data too large for memory can be streamed into an online learner.
+/// The file format is the one accepted by `load_csv()`, except that batches
+/// carry no missing-value mask: a missing feature fails its batch with a
+/// `Parse` error rather than reaching the learner as NaN.
+pub struct CsvBatchReader<R> {
+    reader: BufReader<R>,
+    line_no: usize,
+    batch_size: usize,
+    label_column: Option<usize>,
+    feature_names: Option<Vec<String>>,
+    n_features: Option<usize>,
+    done: bool,
+}
+
+impl<R: Read> CsvBatchReader<R> {
+    /// Construct a reader yielding batches of up to `batch_size` rows. The
+    /// header, if any, is read immediately.
+    pub fn new(reader: R, batch_size: usize, has_header: bool, label_column: Option<usize>) -> Result<CsvBatchReader<R>, LoadError> {
+        assert!(batch_size > 0, "batch size must be positive");
+        let mut reader = BufReader::new(reader);
+        let mut line_no = 0;
+        let mut feature_names = None;
+        if has_header {
+            if let Some((line, record)) = read_record(&mut reader, &mut line_no)? {
+                feature_names = Some(split_label(record, line, label_column)?.0);
+            }
+        }
+        Ok(CsvBatchReader { reader, line_no, batch_size, label_column, feature_names, n_features: None, done: false })
+    }
+
+    /// Names of the feature columns, if the file had a header.
+    pub fn feature_names(&self) -> Option<&[String]> {
+        self.feature_names.as_deref()
+    }
+
+    fn read_batch(&mut self) -> Result<(Vec<Vec<f64>>, Vec<String>), LoadError> {
+        let mut data = Vec::with_capacity(self.batch_size);
+        let mut labels = Vec::with_capacity(self.batch_size);
+        while data.len() < self.batch_size {
+            match read_record(&mut self.reader, &mut self.line_no)? {
+                Some((line, record)) => {
+                    let len = record.len();
+                    let (row, missing, label) = parse_record(record, line, self.label_column)?;
+                    if let Some(j) = missing.iter().position(|&m| m) {
+                        let label_idx = label_index(len, line, self.label_column)?;
+                        return Err(LoadError::Parse {
+                            line,
+                            col: j + 1 + (j >= label_idx) as usize,
+                            message: "missing value in a streamed batch".to_string(),
+                        });
+                    }
+                    match self.n_features {
+                        Some(expected) if expected != row.len() =>
+                            return Err(LoadError::InconsistentWidth { line, expected, got: row.len() }),
+                        Some(_) => {},
+                        None    => self.n_features = Some(row.len()),
+                    }
+                    data.push(row);
+                    labels.push(label);
+                },
+                None => {
+                    self.done = true;
+                    break;
+                },
+            }
+        }
+        Ok((data, labels))
+    }
+}
+
+impl<R: Read> Iterator for CsvBatchReader<R> {
+    type Item = Result<(Vec<Vec<f64>>, Vec<String>), LoadError>;
+
+    /// Read the next batch. The last batch may be short; after an error no
+    /// further batches are produced.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_batch() {
+            Ok((ref data, _)) if data.is_empty() => None,
+            Ok(batch) => Some(Ok(batch)),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+/// Write predictions next to their inputs as CSV. The header row holds the
+/// feature names (`f0`, `f1`, ... when none are given), then `prediction`
+/// and, if confidences are supplied, `confidence`. Labels are written via
+/// `Display` and quoted when they contain commas, quotes or newlines.
+pub fn export_predictions_csv<W: Write, T: Display>(mut writer: W,
+                                                    data: &[Vec<f64>],
+                                                    predictions: &[T],
+                                                    confidences: Option<&[f64]>,
+                                                    feature_names: Option<&[String]>) -> io::Result<()> {
+    if data.len() != predictions.len() {
+        return Err(invalid_input(format!("{} rows but {} predictions", data.len(), predictions.len())));
+    }
+    if let Some(conf) = confidences {
+        if conf.len() != data.len() {
+            return Err(invalid_input(format!("{} rows but {} confidences", data.len(), conf.len())));
+        }
+    }
+    let dim = data.first().map_or(0, |row| row.len());
+    if data.iter().any(|row| row.len() != dim) {
+        return Err(invalid_input("rows differ in length".to_string()));
+    }
+
+    let mut header: Vec<String> = match feature_names {
+        Some(names) => {
+            if names.len() != dim {
+                return Err(invalid_input(format!("{} feature names for {} features", names.len(), dim)));
+            }
+            names.to_vec()
+        },
+        None => (0..dim).map(|i| format!("f{}", i)).collect(),
+    };
+    header.push("prediction".to_string());
+    if confidences.is_some() {
+        header.push("confidence".to_string());
+    }
+    write_record(&mut writer, &header)?;
+
+    for (i, (row, pred)) in data.iter().zip(predictions.iter()).enumerate() {
+        let mut record: Vec<String> = row.iter().map(|val| val.to_string()).collect();
+        record.push(pred.to_string());
+        if let Some(conf) = confidences {
+            record.push(conf[i].to_string());
+        }
+        write_record(&mut writer, &record)?;
+    }
+    writer.flush()
+}
+
+/// Get the index of the label field in a record with `len` fields. When
+/// `label_column` is `None` the label is the last field.
+fn label_index(len: usize, line: usize, label_column: Option<usize>) -> Result<usize, LoadError> {
+    let label_idx = label_column.unwrap_or(len - 1);
+    if label_idx >= len {
+        return Err(LoadError::Parse {
+            line,
+            col: label_idx + 1,
+            message: format!("no label column in a record of {} fields", len),
+        });
+    }
+    Ok(label_idx)
+}
+
+/// Separate the label field from the feature fields of a record.
+fn split_label(mut record: Vec<String>, line: usize, label_column: Option<usize>) -> Result<(Vec<String>, String), LoadError> {
+    let label_idx = label_index(record.len(), line, label_column)?;
+    let label = record.remove(label_idx);
+    Ok((record, label))
+}
+
+/// Parse a record into its features, their missing-value mask and its
+/// label. Empty feature fields and `?` are missing: they are read as NaN
+/// and flagged in the mask.
+fn parse_record(record: Vec<String>, line: usize, label_column: Option<usize>) -> Result<(Vec<f64>, Vec<bool>, String), LoadError> {
+    let label_idx = label_index(record.len(), line, label_column)?;
+    let mut row = Vec::with_capacity(record.len() - 1);
+    let mut missing = Vec::with_capacity(record.len() - 1);
+    let mut label = String::new();
+    for (col, field) in record.into_iter().enumerate() {
+        if col == label_idx {
+            label = field;
+            continue;
+        }
+        let trimmed = field.trim();
+        if trimmed.is_empty() || trimmed == "?" {
+            row.push(f64::NAN);
+            missing.push(true);
+            continue;
+        }
+        match trimmed.parse::<f64>() {
+            Ok(val) => row.push(val),
+            Err(_)  => return Err(LoadError::Parse {
+                line,
+                col: col + 1,
+                message: format!("cannot parse {:?} as a number", trimmed),
+            }),
+        }
+        missing.push(false);
+    }
+    Ok((row, missing, label))
+}
+
+/// Read one CSV record, joining physical lines while inside a quoted field.
+/// Blank lines are skipped. `line_no` counts the physical lines read so
+/// far. Returns the line the record starts on with its fields, or `None`
+/// at end of input.
+fn read_record<R: BufRead>(reader: &mut R, line_no: &mut usize) -> Result<Option<(usize, Vec<String>)>, LoadError> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        *line_no += 1;
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+    let start = *line_no;
+
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    loop {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_quotes {
+                if c == '"' {
+                    if chars.peek() == Some(&'"') {
+                        field.push('"');
+                        chars.next();
+                    } else {
+                        in_quotes = false;
+                    }
+                } else {
+                    field.push(c);
+                }
+            } else {
+                match c {
+                    '"'         => in_quotes = true,
+                    ','         => fields.push(std::mem::take(&mut field)),
+                    '\r' | '\n' => {},
+                    _           => field.push(c),
+                }
+            }
+        }
+        if !in_quotes {
+            break;
+        }
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(LoadError::Parse {
+                line: start,
+                col: fields.len() + 1,
+                message: "unterminated quoted field".to_string(),
+            });
+        }
+        *line_no += 1;
+    }
+    fields.push(field);
+    Ok(Some((start, fields)))
+}
+
+/// Write one CSV record, quoting fields where needed.
+fn write_record<W: Write>(writer: &mut W, fields: &[String]) -> io::Result<()> {
+    let escaped: Vec<String> = fields.iter().map(|f| escape_field(f)).collect();
+    writeln!(writer, "{}", escaped.join(","))
+}
+
+/// Quote a field if it contains a delimiter, quote or line break.
+fn escape_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_csv() {
+        let csv = "x,y,label\n1.0,2.0,a\n3.5,-1,\"b, c\"\n";
+        let loaded = load_csv(csv.as_bytes(), true, None).unwrap();
+
+        assert_eq!(loaded.feature_names, Some(vec!["x".to_string(), "y".to_string()]));
+        assert_eq!(loaded.data, vec![vec![1.0, 2.0], vec![3.5, -1.0]]);
+        assert_eq!(loaded.labels, vec!["a".to_string(), "b, c".to_string()]);
+    }
+
+    #[test]
+    #[cfg(feature = "flate2")]
+    fn test_load_csv_gz() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let csv = "x,y,label\n1.0,2.0,a\n3.5,-1,b\n0,,a\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(csv.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("oxide-gz-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("data.csv"), csv).unwrap();
+        std::fs::write(dir.join("data.csv.gz"), &compressed).unwrap();
+        let plain = load_csv_path(dir.join("data.csv"), true, None).unwrap();
+        let from_path = load_csv_path(dir.join("data.csv.gz"), true, None).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let from_reader = load_csv_gz(&compressed[..], true, None).unwrap();
+        for loaded in &[from_path, from_reader] {
+            assert_eq!(loaded.feature_names, plain.feature_names);
+            assert_eq!(loaded.data[..2], plain.data[..2]);
+            assert_eq!(loaded.labels, plain.labels);
+            assert_eq!(loaded.missing_counts(), vec![0, 1]);
+        }
+    }
+
+    #[test]
+    fn test_missing_mask_from_csv() {
+        let csv = "a,b,c,label\n1,2,3,x\n4,,6,y\n?,8,9,x\n10,11,12,y\n";
+        let loaded = load_csv(csv.as_bytes(), true, None).unwrap();
+
+        assert!(loaded.missing.is_missing(1, 1));
+        assert!(loaded.missing.is_missing(2, 0));
+        assert!(!loaded.missing.is_missing(0, 0));
+        assert!(!loaded.missing.is_missing(3, 2));
+        assert_eq!(loaded.missing_counts(), vec![1, 1, 0]);
+        assert_eq!(loaded.complete_rows(), vec![0, 3]);
+
+        let complete = loaded.drop_missing_rows();
+        assert_eq!(complete.data, vec![vec![1.0, 2.0, 3.0], vec![10.0, 11.0, 12.0]]);
+        assert_eq!(complete.labels, vec!["x".to_string(), "y".to_string()]);
+        assert_eq!(complete.missing_counts(), vec![0, 0, 0]);
+
+        // A literal NaN is a value, not a missing cell.
+        let loaded = load_csv("nan,1\n".as_bytes(), false, None).unwrap();
+        assert!(loaded.data[0][0].is_nan());
+        assert_eq!(loaded.missing_counts(), vec![0]);
+    }
+
+    #[test]
+    fn test_missing_mask_bits() {
+        let mut mask = MissingMask::new(3, 50);
+        mask.set_missing(1, 20, true);
+        mask.set_missing(2, 49, true);
+        assert!(mask.is_missing(1, 20));
+        assert!(mask.is_missing(2, 49));
+        assert!(!mask.is_missing(1, 21));
+        assert!(mask.row_is_complete(0));
+        assert!(!mask.row_is_complete(2));
+        mask.set_missing(2, 49, false);
+        assert!(mask.row_is_complete(2));
+    }
+
+    #[test]
+    fn test_describe() {
+        let csv = "height,const,weight,label\n\
+                   1.0,7,10,cat\n\
+                   2.0,7,,dog\n\
+                   3.0,7,30,cat\n\
+                   4.0,7,50,cat\n";
+        let loaded = load_csv(csv.as_bytes(), true, None).unwrap();
+        let summary = loaded.describe();
+        assert_eq!(summary.columns.len(), 3);
+
+        let height = &summary.columns[0];
+        assert_eq!((height.count, height.missing), (4, 0));
+        assert_eq!(height.mean, 2.5);
+        assert!((height.std - (5f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert_eq!((height.min, height.p25, height.p50, height.p75, height.max),
+                   (1.0, 1.75, 2.5, 3.25, 4.0));
+
+        let constant = &summary.columns[1];
+        assert_eq!((constant.mean, constant.std, constant.min, constant.max), (7.0, 0.0, 7.0, 7.0));
+
+        let weight = &summary.columns[2];
+        assert_eq!((weight.count, weight.missing), (3, 1));
+        assert_eq!(weight.mean, 30.0);
+        assert_eq!(weight.std, 20.0);
+        assert_eq!((weight.p25, weight.p50, weight.p75), (20.0, 30.0, 40.0));
+
+        // A literal NaN is a value, not a missing cell.
+        let loaded = load_csv("1,x\nnan,y\n?,x\n".as_bytes(), false, None).unwrap();
+        let column = &loaded.describe().columns[0];
+        assert_eq!((column.count, column.missing), (2, 1));
+        assert!(column.mean.is_nan());
+        assert_eq!(describe(&loaded.data).columns[0].missing, 2);
+
+        assert_eq!(summary.label_counts,
+                   Some(vec![("cat".to_string(), 3), ("dog".to_string(), 1)]));
+
+        let table = summary.to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("feature "));
+        assert!(lines[1].starts_with("height "));
+        assert!(lines[3].starts_with("weight "));
+        assert_eq!(lines[0].len(), lines[1].len());
+        assert!(table.contains("cat"));
+    }
+
+    #[test]
+    fn test_csv_batch_reader() {
+        let csv = "a,b,label\n1,2,x\n3,4,y\n\n5,6,x\n7,8,y\n9,10,x\n";
+        let mut reader = CsvBatchReader::new(csv.as_bytes(), 2, true, None).unwrap();
+        assert_eq!(reader.feature_names(), Some(&["a".to_string(), "b".to_string()][..]));
+
+        let batches: Vec<_> = reader.by_ref().map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].0, vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+        assert_eq!(batches[1].1, vec!["x".to_string(), "y".to_string()]);
+        assert_eq!(batches[2].0, vec![vec![9.0, 10.0]]);
+        assert!(reader.next().is_none());
+
+        let bad = "1,2,x\n3,oops,y\n";
+        let mut reader = CsvBatchReader::new(bad.as_bytes(), 1, false, None).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next().unwrap(), Err(LoadError::Parse { line: 2, col: 2, .. })));
+        assert!(reader.next().is_none());
+
+        let ragged = "1,2,x\n3,y\n";
+        let mut reader = CsvBatchReader::new(ragged.as_bytes(), 5, false, None).unwrap();
+        assert!(matches!(reader.next().unwrap(),
+                         Err(LoadError::InconsistentWidth { line: 2, expected: 2, got: 1 })));
+
+        // Batches have no mask, so missing cells fail rather than pass as
+        // NaN; a literal NaN is a value like any other.
+        let gappy = "1,nan,x\n3,4,y\n?,5,x\n";
+        let mut reader = CsvBatchReader::new(gappy.as_bytes(), 2, false, None).unwrap();
+        assert!(reader.next().unwrap().unwrap().0[0][1].is_nan());
+        assert!(matches!(reader.next().unwrap(), Err(LoadError::Parse { line: 3, col: 1, .. })));
+        assert!(reader.next().is_none());
+        let gappy = "x,1,\ny,2,3\n";
+        let mut reader = CsvBatchReader::new(gappy.as_bytes(), 5, false, Some(0)).unwrap();
+        assert!(matches!(reader.next().unwrap(), Err(LoadError::Parse { line: 1, col: 3, .. })));
+    }
+
+    #[test]
+    fn test_load_csv_errors() {
+        // The label comes first here, so the bad field is in column 3.
+        let csv = "label,a,b\nx,1,2\n\ny,3,4.5.6\n";
+        match load_csv(csv.as_bytes(), true, Some(0)) {
+            Err(LoadError::Parse { line, col, message }) => {
+                assert_eq!((line, col), (4, 3));
+                assert!(message.contains("4.5.6"));
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // A quoted label spanning two lines shifts the line numbers after it.
+        let csv = "1,2,\"x\ny\"\n3,4,5,z\n";
+        assert!(matches!(load_csv(csv.as_bytes(), false, None),
+                         Err(LoadError::InconsistentWidth { line: 3, expected: 2, got: 3 })));
+
+        let csv = "1,2,x\n3,4,\"y\n";
+        assert!(matches!(load_csv(csv.as_bytes(), false, None),
+                         Err(LoadError::Parse { line: 2, col: 3, .. })));
+
+        let csv = "1,2,x\n";
+        assert!(matches!(load_csv(csv.as_bytes(), false, Some(5)),
+                         Err(LoadError::Parse { line: 1, col: 6, .. })));
+
+        let err = load_csv("a,1\nb,oops\n".as_bytes(), false, Some(0)).unwrap_err();
+        assert_eq!(err.to_string(), "line 2, column 2: cannot parse \"oops\" as a number");
+    }
+
+    #[test]
+    fn test_export_predictions_round_trip() {
+        let data = vec![
+            vec![0.1, 2.0],
+            vec![-3.25, 1e-7],
+            vec![4.0, 5.5],
+        ];
+        let predictions = vec![
+            "plain".to_string(),
+            "with, comma".to_string(),
+            "with \"quotes\"".to_string(),
+        ];
+        let confidences = vec![0.5, 1.0, 0.75];
+
+        let mut out = Vec::new();
+        export_predictions_csv(&mut out, &data, &predictions, Some(&confidences), None).unwrap();
+
+        let loaded = load_csv(&out[..], true, Some(2)).unwrap();
+        assert_eq!(loaded.feature_names,
+                   Some(vec!["f0".to_string(), "f1".to_string(), "confidence".to_string()]));
+        assert_eq!(loaded.labels, predictions);
+        for (i, row) in loaded.data.iter().enumerate() {
+            assert_eq!(&row[..2], &data[i][..]);
+            assert_eq!(row[2], confidences[i]);
+        }
+    }
+
+    #[test]
+    fn test_export_predictions_named_features() {
+        let data = vec![vec![1.0], vec![2.0]];
+        let names = vec!["height".to_string()];
+
+        let mut out = Vec::new();
+        export_predictions_csv(&mut out, &data, &[1, 2], None, Some(&names)).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "height,prediction\n1,1\n2,2\n");
+    }
+}
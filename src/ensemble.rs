@@ -0,0 +1,266 @@
+use super::core::{Classifier, ProbabilisticClassifier};
+use super::model_selection::k_fold;
+use super::util::RandomState;
+
+/// A classifier whose members can be combined in an ensemble.
+pub type Member<T> = Box<dyn ProbabilisticClassifier<ExampleType = Vec<f64>, LabelType = T>>;
+
+/// How a `VotingClassifier` combines the opinions of its members.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Voting {
+    /// Each member votes for its predicted label; the most votes win.
+    Hard,
+    /// The members' class probabilities are averaged; the most probable
+    /// class wins.
+    Soft,
+}
+
+/// An ensemble that trains several classifiers on the same data and lets
+/// them vote on each prediction.
+pub struct VotingClassifier<T> {
+    members: Vec<Member<T>>,
+    voting: Voting,
+    fitted: bool,
+}
+
+impl<T> VotingClassifier<T> where T: PartialEq + Clone {
+    /// Construct a new VotingClassifier from its members.
+    pub fn new(members: Vec<Member<T>>, voting: Voting) -> VotingClassifier<T> {
+        VotingClassifier::<T>{ members, voting, fitted: false }
+    }
+
+    /// Add up the votes of all members as (label, score) pairs, in order of
+    /// first appearance.
+    fn tally(&self, x: &Vec<f64>) -> Option<Vec<(T, f64)>> {
+        let mut scores: Vec<(T, f64)> = Vec::new();
+        for member in &self.members {
+            let votes = match self.voting {
+                Voting::Hard => vec![(member.predict_one(x)?, 1f64)],
+                Voting::Soft => member.predict_proba(x)?,
+            };
+            for (label, score) in votes {
+                match scores.iter_mut().find(|entry| entry.0 == label) {
+                    Some(entry) => entry.1 += score,
+                    None        => scores.push((label, score)),
+                }
+            }
+        }
+        Some(scores)
+    }
+}
+
+impl<T> Classifier for VotingClassifier<T> where T: PartialEq + Clone {
+    type ExampleType = Vec<f64>;
+    type LabelType = T;
+
+    /// Train every member on the data.
+    fn fit(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>) {
+        for member in self.members.iter_mut() {
+            member.fit(data.clone(), labels.clone());
+        }
+        self.fitted = true;
+    }
+
+    /// Predict the labels of datapoints. Return None if `predict()` is
+    /// called before `fit()`.
+    fn predict(&self, data: &[Vec<f64>]) -> Option<Vec<T>> {
+        data.iter().map(|x| self.predict_one(x)).collect()
+    }
+
+    /// Predict the label for one datapoint by a vote of the members. Ties go
+    /// to the label voted for first. Return None if `predict_one()` is
+    /// called before `fit()`.
+    fn predict_one(&self, x: &Vec<f64>) -> Option<T> {
+        if !self.fitted {
+            return None;
+        }
+        let mut best: Option<(T, f64)> = None;
+        for (label, score) in self.tally(x)? {
+            if best.as_ref().is_none_or(|&(_, best_score)| score > best_score) {
+                best = Some((label, score));
+            }
+        }
+        best.map(|(label, _)| label)
+    }
+}
+
+impl<T> ProbabilisticClassifier for VotingClassifier<T> where T: PartialEq + Clone {
+    /// Estimate class probabilities as the share of the vote each class
+    /// gets: the fraction of members predicting it with hard voting, or the
+    /// members' mean probability with soft voting.
+    fn predict_proba(&self, x: &Vec<f64>) -> Option<Vec<(T, f64)>> {
+        if !self.fitted {
+            return None;
+        }
+        let mut scores = self.tally(x)?;
+        let total: f64 = scores.iter().map(|&(_, score)| score).sum();
+        for entry in scores.iter_mut() {
+            entry.1 /= total;
+        }
+        Some(scores)
+    }
+}
+
+/// An ensemble that feeds the class probabilities of several base
+/// classifiers to a meta classifier, which learns how far to trust each.
+/// To train the meta classifier on probabilities like those it will see at
+/// prediction time, they are produced out of fold: each base model scores
+/// every training point with a model fitted on the other folds.
+pub struct StackingClassifier<T, M> {
+    members: Vec<Member<T>>,
+    meta: M,
+    n_folds: usize,
+    random_state: Option<RandomState>,
+    classes: Vec<T>,
+    fitted: bool,
+}
+
+impl<T, M> StackingClassifier<T, M>
+    where T: PartialEq + Clone, M: Classifier<ExampleType = Vec<f64>, LabelType = T>
+{
+    /// Construct a new StackingClassifier that trains `meta` on out-of-fold
+    /// predictions of `members` from `n_folds` folds.
+    pub fn new(members: Vec<Member<T>>, meta: M, n_folds: usize) -> StackingClassifier<T, M> {
+        StackingClassifier { members, meta, n_folds, random_state: None, classes: Vec::new(), fitted: false }
+    }
+
+    /// Set the randomness used to draw the folds.
+    pub fn set_random_state(&mut self, random_state: Option<RandomState>) {
+        self.random_state = random_state;
+    }
+
+    /// Compute the meta features of a datapoint: each member's probability
+    /// for each class, member by member.
+    fn meta_features(&self, x: &Vec<f64>) -> Option<Vec<f64>> {
+        let mut features = Vec::with_capacity(self.members.len() * self.classes.len());
+        for member in &self.members {
+            let proba = member.predict_proba(x)?;
+            features.extend(self.classes.iter().map(|class| {
+                proba.iter().find(|(label, _)| label == class).map_or(0.0, |&(_, p)| p)
+            }));
+        }
+        Some(features)
+    }
+}
+
+impl<T, M> Classifier for StackingClassifier<T, M>
+    where T: PartialEq + Clone, M: Classifier<ExampleType = Vec<f64>, LabelType = T>
+{
+    type ExampleType = Vec<f64>;
+    type LabelType = T;
+
+    /// Train the members fold by fold to get out-of-fold meta features,
+    /// train the meta classifier on those, then retrain the members on all
+    /// the data.
+    fn fit(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>) {
+        self.classes.clear();
+        for label in &labels {
+            if !self.classes.contains(label) {
+                self.classes.push(label.clone());
+            }
+        }
+        let mut meta_data = vec![Vec::new(); data.len()];
+        for (train, test) in k_fold(data.len(), self.n_folds, self.random_state.clone()) {
+            let train_data: Vec<Vec<f64>> = train.iter().map(|&i| data[i].clone()).collect();
+            let train_labels: Vec<T> = train.iter().map(|&i| labels[i].clone()).collect();
+            for member in self.members.iter_mut() {
+                member.fit(train_data.clone(), train_labels.clone());
+            }
+            for &i in &test {
+                meta_data[i] = self.meta_features(&data[i]).expect("no predictions after fit()");
+            }
+        }
+        for member in self.members.iter_mut() {
+            member.fit(data.clone(), labels.clone());
+        }
+        self.meta.fit(meta_data, labels);
+        self.fitted = true;
+    }
+
+    /// Predict the labels of datapoints. Return None if `predict()` is
+    /// called before `fit()`.
+    fn predict(&self, data: &[Vec<f64>]) -> Option<Vec<T>> {
+        data.iter().map(|x| self.predict_one(x)).collect()
+    }
+
+    /// Predict the label for one datapoint with the meta classifier. Return
+    /// None if `predict_one()` is called before `fit()`.
+    fn predict_one(&self, x: &Vec<f64>) -> Option<T> {
+        if !self.fitted {
+            return None;
+        }
+        self.meta.predict_one(&self.meta_features(x)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::knn::KNNClassifier;
+    use crate::linear::Perceptron;
+    use crate::metrics::score;
+    use crate::util::Rng;
+
+    fn members() -> Vec<Member<&'static str>> {
+        vec![
+            Box::new(KNNClassifier::new(1)),
+            Box::new(KNNClassifier::new(5)),
+            Box::new(KNNClassifier::new(5)),
+        ]
+    }
+
+    #[test]
+    fn test_soft_beats_hard() {
+        let train: Vec<Vec<f64>> = vec![vec![0.0], vec![0.3], vec![0.35], vec![0.4], vec![0.5]];
+        let labels = vec!["a", "b", "b", "b", "a"];
+        let test = vec![vec![0.1], vec![0.38]];
+        let truth = vec!["a", "b"];
+
+        let mut hard = VotingClassifier::new(members(), Voting::Hard);
+        let mut soft = VotingClassifier::new(members(), Voting::Soft);
+        assert_eq!(hard.predict_one(&test[0]), None);
+
+        hard.fit(train.clone(), labels.clone());
+        soft.fit(train, labels);
+
+        // At 0.1 the k = 1 member is sure of "a", while both k = 5 members
+        // lean 3:2 towards "b". Soft voting trusts the confident member.
+        assert_eq!(hard.predict(&test).unwrap(), vec!["b", "b"]);
+        assert_eq!(soft.predict(&test).unwrap(), truth);
+
+        let proba = soft.predict_proba(&test[0]).unwrap();
+        assert_eq!(proba.len(), 2);
+        assert!((proba.iter().map(|&(_, p)| p).sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!((proba[0].1 - 0.6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_stacking_beats_members() {
+        // The label is decided by the first feature; the second is noise
+        // on a much larger scale, which swamps the KNN distances unless
+        // the neighbourhood is large enough to average it out.
+        let mut rng = Rng::new(12);
+        let mut sample = |n: usize| -> (Vec<Vec<f64>>, Vec<i32>) {
+            let data: Vec<Vec<f64>> = (0..n).map(|_| vec![rng.next_f64() * 2.0 - 1.0, rng.next_f64() * 4.0]).collect();
+            let labels = data.iter().map(|x| if x[0] > 0.0 { 1 } else { 0 }).collect();
+            (data, labels)
+        };
+        let (train, train_labels) = sample(300);
+        let (test, test_labels) = sample(300);
+
+        let members = || -> Vec<Member<i32>> {
+            vec![Box::new(KNNClassifier::new(1)), Box::new(KNNClassifier::new(25))]
+        };
+        let mut best_member = 0f64;
+        for mut member in members() {
+            member.fit(train.clone(), train_labels.clone());
+            best_member = best_member.max(score(&*member, &test, &test_labels).unwrap());
+        }
+
+        let mut stack = StackingClassifier::new(members(), Perceptron::new(20, 0.1), 5);
+        assert_eq!(stack.predict_one(&test[0]), None);
+        stack.fit(train, train_labels);
+        let stacked = score(&stack, &test, &test_labels).unwrap();
+        assert!(stacked >= best_member, "stack {} vs best member {}", stacked, best_member);
+    }
+}
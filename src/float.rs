@@ -0,0 +1,50 @@
+//! Float functions that come from std when it is available and from `libm`
+//! otherwise.
+
+/// Square root of `x`.
+#[cfg(feature = "std")]
+pub fn sqrt(x: f64) -> f64 {
+    x.sqrt()
+}
+
+/// Square root of `x`.
+#[cfg(not(feature = "std"))]
+pub fn sqrt(x: f64) -> f64 {
+    ::libm::sqrt(x)
+}
+
+/// Natural logarithm of `x`.
+#[cfg(feature = "std")]
+pub fn ln(x: f64) -> f64 {
+    x.ln()
+}
+
+/// Natural logarithm of `x`.
+#[cfg(not(feature = "std"))]
+pub fn ln(x: f64) -> f64 {
+    ::libm::log(x)
+}
+
+/// Cosine of `x` radians.
+#[cfg(feature = "std")]
+pub fn cos(x: f64) -> f64 {
+    x.cos()
+}
+
+/// Cosine of `x` radians.
+#[cfg(not(feature = "std"))]
+pub fn cos(x: f64) -> f64 {
+    ::libm::cos(x)
+}
+
+/// Exponential function, e raised to `x`.
+#[cfg(feature = "std")]
+pub fn exp(x: f64) -> f64 {
+    x.exp()
+}
+
+/// Exponential function, e raised to `x`.
+#[cfg(not(feature = "std"))]
+pub fn exp(x: f64) -> f64 {
+    ::libm::exp(x)
+}
@@ -0,0 +1,186 @@
+use super::util::{pairwise_distances, Metric};
+
+/// How the distance between two clusters is derived from the distances
+/// between their points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Linkage {
+    /// The distance between their closest points.
+    Single,
+    /// The distance between their farthest points.
+    Complete,
+    /// The mean distance over all pairs of points.
+    Average,
+}
+
+/// One step of agglomerative clustering. Clusters are numbered as in
+/// SciPy: the datapoints are clusters `0..n`, and the cluster formed by
+/// the i-th merge is `n + i`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Merge {
+    /// The smaller-numbered of the merged clusters.
+    pub left: usize,
+    /// The larger-numbered of the merged clusters.
+    pub right: usize,
+    /// The linkage distance between them.
+    pub distance: f64,
+    /// The number of datapoints in the merged cluster.
+    pub size: usize,
+}
+
+/// Bottom-up hierarchical clustering: starting from one cluster per
+/// datapoint, repeatedly merges the two closest clusters until one is
+/// left. The merges form a tree (a dendrogram) that can be cut into any
+/// number of flat clusters.
+#[derive(Debug, Clone)]
+pub struct AgglomerativeClustering {
+    linkage: Linkage,
+    metric: Metric,
+    merges: Option<Vec<Merge>>,
+}
+
+impl AgglomerativeClustering {
+    /// Construct a new AgglomerativeClustering using Euclidean distances.
+    pub fn new(linkage: Linkage) -> AgglomerativeClustering {
+        AgglomerativeClustering::with_metric(linkage, Metric::Euclidean)
+    }
+
+    /// Construct a new AgglomerativeClustering using the given metric.
+    pub fn with_metric(linkage: Linkage, metric: Metric) -> AgglomerativeClustering {
+        AgglomerativeClustering { linkage, metric, merges: None }
+    }
+
+    /// Build the merge tree of `data`. Ties between equally close pairs go
+    /// to the pair of lowest-numbered clusters.
+    pub fn fit(&mut self, data: &[Vec<f64>]) {
+        assert!(!data.is_empty(), "no data to cluster");
+        let n = data.len();
+        let mut dist = pairwise_distances(data, self.metric);
+        // The cluster id and size held in each row of `dist`; None once the
+        // row has been merged into another.
+        let mut clusters: Vec<Option<(usize, usize)>> = (0..n).map(|i| Some((i, 1))).collect();
+        let mut merges = Vec::with_capacity(n - 1);
+
+        for step in 0..n - 1 {
+            let mut best: Option<(usize, usize, f64)> = None;
+            for i in 0..n {
+                if clusters[i].is_none() {
+                    continue;
+                }
+                for j in (i + 1)..n {
+                    if clusters[j].is_some() && best.is_none_or(|(_, _, d)| dist[i][j] < d) {
+                        best = Some((i, j, dist[i][j]));
+                    }
+                }
+            }
+            let (i, j, distance) = best.unwrap();
+            let (id_i, size_i) = clusters[i].unwrap();
+            let (id_j, size_j) = clusters[j].unwrap();
+
+            // Row i becomes the merged cluster (Lance-Williams update).
+            for k in 0..n {
+                if k == i || k == j || clusters[k].is_none() {
+                    continue;
+                }
+                let d = match self.linkage {
+                    Linkage::Single   => dist[i][k].min(dist[j][k]),
+                    Linkage::Complete => dist[i][k].max(dist[j][k]),
+                    Linkage::Average  =>
+                        (dist[i][k] * size_i as f64 + dist[j][k] * size_j as f64) / (size_i + size_j) as f64,
+                };
+                dist[i][k] = d;
+                dist[k][i] = d;
+            }
+            clusters[i] = Some((n + step, size_i + size_j));
+            clusters[j] = None;
+            merges.push(Merge {
+                left: id_i.min(id_j),
+                right: id_i.max(id_j),
+                distance,
+                size: size_i + size_j,
+            });
+        }
+        self.merges = Some(merges);
+    }
+
+    /// The merges made by `fit()`, in order. Return None if called before
+    /// `fit()`.
+    pub fn merges(&self) -> Option<&[Merge]> {
+        self.merges.as_deref()
+    }
+
+    /// Cut the tree into `n_clusters` flat clusters by undoing the last
+    /// merges, and label each datapoint with its cluster. Clusters are
+    /// numbered from 0 in order of their first datapoint. Return None if
+    /// called before `fit()`.
+    pub fn labels(&self, n_clusters: usize) -> Option<Vec<usize>> {
+        let merges = self.merges.as_ref()?;
+        let n = merges.len() + 1;
+        assert!(n_clusters >= 1 && n_clusters <= n, "n_clusters must be in [1, n]");
+        // The cluster each cluster id has been merged into so far.
+        let mut parent: Vec<usize> = (0..n + merges.len()).collect();
+        for (step, merge) in merges.iter().take(n - n_clusters).enumerate() {
+            parent[merge.left] = n + step;
+            parent[merge.right] = n + step;
+        }
+        let root = |mut c: usize| {
+            while parent[c] != c {
+                c = parent[c];
+            }
+            c
+        };
+        let mut roots: Vec<usize> = Vec::new();
+        Some((0..n).map(|i| {
+            let r = root(i);
+            match roots.iter().position(|&x| x == r) {
+                Some(label) => label,
+                None        => {
+                    roots.push(r);
+                    roots.len() - 1
+                },
+            }
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn three_clusters() -> Vec<Vec<f64>> {
+        vec![
+            vec![0.0, 0.0], vec![10.0, 10.0], vec![0.3, 0.1], vec![-10.0, 10.0],
+            vec![10.2, 9.9], vec![0.1, 0.4], vec![-9.8, 10.3], vec![9.7, 10.1],
+        ]
+    }
+
+    #[test]
+    fn test_three_cluster_cut() {
+        for &linkage in &[Linkage::Single, Linkage::Complete, Linkage::Average] {
+            let mut clustering = AgglomerativeClustering::new(linkage);
+            assert_eq!(clustering.labels(3), None);
+
+            clustering.fit(&three_clusters());
+            assert_eq!(clustering.merges().unwrap().len(), 7);
+            assert_eq!(clustering.labels(3).unwrap(), vec![0, 1, 0, 2, 1, 0, 2, 1]);
+            assert_eq!(clustering.labels(1).unwrap(), vec![0; 8]);
+            assert_eq!(clustering.labels(8).unwrap(), (0..8).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_linkage_distances() {
+        let data = vec![vec![0.0], vec![1.0], vec![3.0], vec![10.0]];
+        let distances = |linkage| -> Vec<f64> {
+            let mut clustering = AgglomerativeClustering::new(linkage);
+            clustering.fit(&data);
+            clustering.merges().unwrap().iter().map(|m| m.distance).collect()
+        };
+        assert_eq!(distances(Linkage::Single), vec![1.0, 2.0, 7.0]);
+        assert_eq!(distances(Linkage::Complete), vec![1.0, 3.0, 10.0]);
+        assert_eq!(distances(Linkage::Average), vec![1.0, 2.5, 26.0 / 3.0]);
+
+        let mut clustering = AgglomerativeClustering::new(Linkage::Single);
+        clustering.fit(&data);
+        assert_eq!(clustering.merges().unwrap()[1], Merge { left: 2, right: 4, distance: 2.0, size: 3 });
+    }
+}
@@ -1,141 +1,2919 @@
-use super::util::{Counter,squared_distance};
-use super::core::Classifier;
+use super::util::{column_means, jaccard_distance, matvec, quantile, Counter, Metric, RandomState, RunningStats};
+use super::parallel;
+use super::core::{Classifier, ProbabilisticClassifier};
+use super::metrics::{evaluation_report, EvaluationReport};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::hash::Hash;
+use std::mem;
+use std::time::{Duration, Instant};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How many datapoints `predict_with_progress()` predicts between reports.
+const PROGRESS_CHUNK: usize = 256;
+
+/// The default batch size up to which `predict()` stays on the calling
+/// thread.
+const DEFAULT_PARALLEL_THRESHOLD: usize = 64;
+
+thread_local! {
+    /// Neighbour buffer reused across the allocation-free prediction calls.
+    static NEIGHBOURS: RefCell<Vec<(usize, f64)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A predicted label together with the probabilities of every class, as
+/// returned by `KNNClassifier::predict_records()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PredictionRecord<T> {
+    /// The predicted label.
+    pub label: T,
+    /// Each class with its probability, as from `predict_proba()`.
+    pub probabilities: Vec<(T, f64)>,
+}
+
+/// The neighbours of a fixed set of queries, as found by
+/// `KNNClassifier::precompute_neighbors()`, for predicting them repeatedly
+/// without searching again.
+#[derive(Debug, Clone)]
+pub struct NeighborCache {
+    // The neighbours of each query, closest first.
+    neighbours: Vec<Vec<(usize, f64)>>,
+    // How many neighbours were searched for, and among how many rows.
+    k: usize,
+    n_train: usize,
+}
+
+impl NeighborCache {
+    /// The number of queries cached.
+    pub fn len(&self) -> usize {
+        self.neighbours.len()
+    }
+
+    /// Whether no queries are cached.
+    pub fn is_empty(&self) -> bool {
+        self.neighbours.is_empty()
+    }
+}
+
+/// Each class with its probability, as from `predict_proba()`.
+pub type ClassProbabilities<T> = Vec<(T, f64)>;
+
+/// A line segment between two points in the plane, as returned by
+/// `KNNClassifier::decision_boundary_segments()`.
+pub type Segment = ((f64, f64), (f64, f64));
+
+/// Per-query prediction latencies, as measured by
+/// `KNNClassifier::benchmark_latency()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyStats {
+    /// The mean latency.
+    pub mean: Duration,
+    /// The median latency.
+    pub p50: Duration,
+    /// The 90th percentile latency.
+    pub p90: Duration,
+    /// The 99th percentile latency.
+    pub p99: Duration,
+}
 
 /// A K-Nearest Neighbours classifier.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(deserialize = "T: Deserialize<'de> + Hash + Eq")))]
 pub struct KNNClassifier<T> {
     k: usize,
+    class_k: HashMap<T, usize>,
+    metric: Metric,
+    prior_correction: bool,
+    bandwidth: Option<f64>,
+    deduplicate: bool,
+    // Batches of up to this many datapoints are predicted on one thread.
+    parallel_threshold: usize,
+    auto_feature_weights: bool,
+    standardize: bool,
+    // Each feature's standard deviation over the training data, if
+    // standardizing.
+    stds: Option<Vec<f64>>,
+    // Each feature's weight in the distances, if not all equal.
+    feature_weights: Option<Vec<f64>>,
+    // The matrix examples are multiplied by before distances, if any.
+    transform: Option<Vec<Vec<f64>>>,
+    // The training points multiplied by `transform`, if set.
+    projected: Option<Vec<Vec<f64>>>,
     data: Option<Vec<Vec<f64>>>,
+    quantized: Option<Quantized>,
     labels: Option<Vec<T>>,
+    // How many identical training points each stored row stands for, if
+    // deduplicated.
+    multiplicity: Option<Vec<usize>>,
+    classes: Vec<T>,
+    // The mean training point of each class in `classes`.
+    centroids: Vec<Vec<f64>>,
+    // The mean of each feature over the training data.
+    means: Vec<f64>,
+    priors: HashMap<T, f64>,
+}
+
+/// Training points stored as one byte per feature. Feature j of a row is
+/// approximately `mins[j] + code * steps[j]`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Quantized {
+    len: usize,
+    dim: usize,
+    // Row-major, `dim` codes per row.
+    codes: Vec<u8>,
+    mins: Vec<f64>,
+    steps: Vec<f64>,
+}
+
+impl Quantized {
+    /// Quantize equal-length rows using each column's min and max.
+    fn new(data: &[Vec<f64>]) -> Quantized {
+        let dim = data.first().map_or(0, |x| x.len());
+        assert!(data.iter().all(|x| x.len() == dim), "quantization needs rows of equal length");
+        let mut mins = vec![f64::INFINITY; dim];
+        let mut maxs = vec![f64::NEG_INFINITY; dim];
+        for x in data {
+            for j in 0..dim {
+                mins[j] = mins[j].min(x[j]);
+                maxs[j] = maxs[j].max(x[j]);
+            }
+        }
+        let steps: Vec<f64> = mins.iter().zip(maxs.iter()).map(|(lo, hi)| (hi - lo) / 255.0).collect();
+        let mut codes = Vec::with_capacity(data.len() * dim);
+        for x in data {
+            for j in 0..dim {
+                let code = if steps[j] > 0.0 { ((x[j] - mins[j]) / steps[j] + 0.5).min(255.0) } else { 0.0 };
+                codes.push(code as u8);
+            }
+        }
+        Quantized { len: data.len(), dim, codes, mins, steps }
+    }
+
+    /// Reconstruct feature `j` of a row from its code.
+    fn value(&self, j: usize, code: u8) -> f64 {
+        self.mins[j] + code as f64 * self.steps[j]
+    }
+
+    fn codes(&self, i: usize) -> &[u8] {
+        &self.codes[i * self.dim..(i + 1) * self.dim]
+    }
+}
+
+/// A training point's nearest other training points, as (row,
+/// occurrences, distance) triples.
+type Neighbourhood = Vec<(usize, usize, f64)>;
+
+/// How the training points are stored.
+#[derive(Clone, Copy)]
+enum Storage<'a> {
+    Dense(&'a [Vec<f64>]),
+    Quantized(&'a Quantized),
+}
+
+/// A view of the training points, whichever way they are stored, along
+/// with the feature weights and transform distances to them use.
+#[derive(Clone, Copy)]
+struct Rows<'a> {
+    storage: Storage<'a>,
+    weights: Option<&'a [f64]>,
+    transform: Option<&'a [Vec<f64>]>,
+    // The rows multiplied by `transform`, if precomputed.
+    projected: Option<&'a [Vec<f64>]>,
+}
+
+impl<'a> Rows<'a> {
+    /// View dense rows, with unweighted, untransformed distances.
+    fn dense(data: &'a [Vec<f64>]) -> Rows<'a> {
+        Rows { storage: Storage::Dense(data), weights: None, transform: None, projected: None }
+    }
+
+    /// Multiply a query by the transform, if any, as `distance()` expects.
+    fn project<'b>(&self, x: &'b [f64]) -> Cow<'b, [f64]> {
+        match self.transform {
+            Some(m) => Cow::Owned(matvec(m, x)),
+            None    => Cow::Borrowed(x),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self.storage {
+            Storage::Dense(data) => data.len(),
+            Storage::Quantized(q) => q.len,
+        }
+    }
+
+    /// The length of the first row, if any.
+    fn dim(&self) -> Option<usize> {
+        match self.storage {
+            Storage::Dense(data) => data.first().map(|x| x.len()),
+            Storage::Quantized(q) => if q.len > 0 { Some(q.dim) } else { None },
+        }
+    }
+
+    /// Get row `i`, dequantizing it if needed.
+    fn row(&self, i: usize) -> Cow<'a, [f64]> {
+        match self.storage {
+            Storage::Dense(data) => Cow::Borrowed(&data[i]),
+            Storage::Quantized(q) => Cow::Owned(q.codes(i).iter().enumerate().map(|(j, &c)| q.value(j, c)).collect()),
+        }
+    }
+
+    /// Compute the distance from `x`, already passed through `project()`,
+    /// to row `i`. Euclidean distances to quantized rows are computed
+    /// without materializing the row.
+    fn distance(&self, metric: Metric, x: &[f64], i: usize) -> f64 {
+        if let Some(m) = self.transform {
+            return match self.projected {
+                Some(projected) => weighted_distance(metric, self.weights, x, &projected[i]),
+                None            => weighted_distance(metric, self.weights, x, &matvec(m, &self.row(i))),
+            };
+        }
+        match (self.storage, metric) {
+            (Storage::Dense(data), _) => weighted_distance(metric, self.weights, x, &data[i]),
+            (Storage::Quantized(q), Metric::Euclidean) => {
+                q.codes(i).iter().zip(x.iter()).enumerate()
+                    .map(|(j, (&c, &v))| self.weights.map_or(1.0, |w| w[j]) * (v - q.value(j, c)).powi(2))
+                    .sum::<f64>()
+                    .sqrt()
+            },
+            (Storage::Quantized(_), _) => weighted_distance(metric, self.weights, x, &self.row(i)),
+        }
+    }
+}
+
+/// Compute the distance between `x` and `y` under `metric`, with each
+/// feature's squared difference multiplied by its weight, if given. That
+/// is, the distance between the vectors with each feature scaled by the
+/// square root of its weight.
+fn weighted_distance(metric: Metric, weights: Option<&[f64]>, x: &[f64], y: &[f64]) -> f64 {
+    match (weights, metric) {
+        (None, _)                    => metric.distance(x, y),
+        (Some(w), Metric::Euclidean) => {
+            x.iter().zip(y.iter()).zip(w.iter())
+                .map(|((a, b), w)| w * (a - b) * (a - b))
+                .sum::<f64>()
+                .sqrt()
+        },
+        (Some(w), _)                 => {
+            let scale = |v: &[f64]| -> Vec<f64> { v.iter().zip(w.iter()).map(|(a, w)| a * w.sqrt()).collect() };
+            metric.distance(&scale(x), &scale(y))
+        },
+    }
+}
+
+/// Compute the running statistics of each column of `data`.
+fn column_stats(data: &[Vec<f64>]) -> Vec<RunningStats> {
+    let dim = data.first().map_or(0, |x| x.len());
+    let mut stats = vec![RunningStats::new(); dim];
+    for x in data {
+        for (s, &v) in stats.iter_mut().zip(x.iter()) {
+            s.push(v);
+        }
+    }
+    stats
+}
+
+/// Weight each column by its inverse variance, normalized so the weights
+/// sum to 1. Constant columns get weight 0.
+fn inverse_variance_weights(stats: &[RunningStats]) -> Vec<f64> {
+    let mut weights: Vec<f64> = stats.iter()
+        .map(|s| if s.variance() > 0.0 { 1.0 / s.variance() } else { 0.0 })
+        .collect();
+    let total: f64 = weights.iter().sum();
+    if total > 0.0 {
+        for w in weights.iter_mut() {
+            *w /= total;
+        }
+    }
+    weights
 }
 
 impl<T> KNNClassifier<T> where T: Hash + Eq + Clone {
-    /// Construct a new KNNClassifier.
+    /// Construct a new KNNClassifier using the Euclidean distance.
     pub fn new(k: usize) -> KNNClassifier<T> {
-        KNNClassifier::<T>{ k: k, data: None, labels: None }
+        KNNClassifier::with_metric(k, Metric::Euclidean)
     }
-}
 
-impl<T> Classifier for KNNClassifier<T> where T: Hash + Eq + Clone {
-    type ExampleType = Vec<f64>;
-    type LabelType = T;
+    /// Construct a new KNNClassifier that compares examples using `metric`.
+    pub fn with_metric(k: usize, metric: Metric) -> KNNClassifier<T> {
+        KNNClassifier::<T>{
+            k,
+            class_k: HashMap::new(),
+            metric,
+            prior_correction: false,
+            bandwidth: None,
+            deduplicate: false,
+            parallel_threshold: DEFAULT_PARALLEL_THRESHOLD,
+            auto_feature_weights: false,
+            standardize: false,
+            stds: None,
+            feature_weights: None,
+            transform: None,
+            projected: None,
+            data: None,
+            quantized: None,
+            labels: None,
+            multiplicity: None,
+            classes: Vec::new(),
+            centroids: Vec::new(),
+            means: Vec::new(),
+            priors: HashMap::new(),
+        }
+    }
 
-    /// Train the classifier with examples and their labels. A KNN classifier
-    /// doesn't actually do anything in the training phase, which is why it has
-    /// been called a "lazy learner".
-    fn fit(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>) {
-        self.data = Some(data);
-        self.labels = Some(labels);
+    /// Construct a new KNNClassifier that multiplies examples by the matrix
+    /// `m`, given as rows, before computing Euclidean distances, i.e. that
+    /// measures the distance between `x` and `y` as `|M x - M y|`: a
+    /// Mahalanobis-like metric with `MᵀM` as its inverse covariance, e.g.
+    /// one learned to pull same-class points together. `m` needs as many
+    /// columns as the examples have features; it may have fewer rows, to
+    /// project them into a lower dimension. The training points are
+    /// multiplied once at `fit()`, and every query as it comes. Not
+    /// available with feature weights or standardization.
+    pub fn with_transform_matrix(k: usize, m: Vec<Vec<f64>>) -> KNNClassifier<T> {
+        let cols = m.first().map_or(0, |row| row.len());
+        assert!(cols > 0 && m.iter().all(|row| row.len() == cols), "transform rows must be non-empty and of equal length");
+        let mut clf = KNNClassifier::new(k);
+        clf.transform = Some(m);
+        clf
     }
 
-    /// Predict the labels of datapoints. Return None if `predict()` is
+    /// Construct a new KNNClassifier where the classes in `overrides` use
+    /// their own number of neighbours, and all others use `default_k`.
+    ///
+    /// The largest k of all is used for the neighbour search. Each class is
+    /// then scored by the fraction of its own k nearest neighbours that
+    /// carry it, so a class with k = 3 looks only at the 3 closest points
+    /// while one with k = 9 looks at all 9. With no overrides this reduces
+    /// to plain majority voting.
+    pub fn with_per_class_k(default_k: usize, overrides: HashMap<T, usize>) -> KNNClassifier<T> {
+        let mut clf = KNNClassifier::new(default_k);
+        clf.class_k = overrides;
+        clf
+    }
+
+    /// Enable or disable prior correction. When enabled, each class's vote
+    /// count is divided by the fraction of training examples in that class
+    /// before the winner is chosen, so that frequent classes don't win votes
+    /// by sheer numbers.
+    pub fn set_prior_correction(&mut self, prior_correction: bool) {
+        self.prior_correction = prior_correction;
+    }
+
+    /// Enable or disable deduplication at `fit()`. When enabled, identical
+    /// training points with the same label are stored once, along with how
+    /// many there were, saving memory and search time. The vote then counts
+    /// each stored point as many times as it occurred, so predictions match
+    /// those without deduplication (up to the order of equidistant points),
+    /// also when combined with Gaussian weighting (`set_bandwidth()`), where
+    /// the occurrences share the kernel weight of their distance. Indices
+    /// returned by the neighbour searches refer to the deduplicated points.
+    pub fn set_deduplicate(&mut self, deduplicate: bool) {
+        self.deduplicate = deduplicate;
+    }
+
+    /// Predict batches of more than `threshold` datapoints on several
+    /// threads, and smaller ones on the calling thread, where starting the
+    /// threads would cost more than it saves. Applies to `predict()` and
+    /// `predict_ref()`, and only with the `parallel` feature; the
+    /// predictions are the same either way. Defaults to 64.
+    pub fn set_parallel_threshold(&mut self, threshold: usize) {
+        self.parallel_threshold = threshold;
+    }
+
+    /// Enable or disable automatic feature weights at `fit()`. When enabled,
+    /// each feature's squared difference in the distance is weighted by the
+    /// inverse of its variance over the training data, normalized so the
+    /// weights sum to 1, so that features on large scales no longer drown
+    /// out those on small ones. Constant features get weight 0. Not
+    /// available with metrics that allow varying lengths.
+    pub fn set_auto_feature_weights(&mut self, auto_feature_weights: bool) {
+        self.auto_feature_weights = auto_feature_weights;
+    }
+
+    /// Enable or disable standardization at `fit()`. When enabled, distances
+    /// are computed as if every feature were divided by its standard
+    /// deviation over the training data, as `StandardScaler` would (constant
+    /// features are left as they are). Centering doesn't change Euclidean
+    /// distances and isn't applied. `scaling_params()` then gives the
+    /// learned statistics. Combined with `set_auto_feature_weights()`,
+    /// whose weights are these inverse variances normalized to sum to 1,
+    /// the automatic weights are used. Not available with metrics that
+    /// allow varying lengths.
+    pub fn set_standardize(&mut self, standardize: bool) {
+        self.standardize = standardize;
+    }
+
+    /// Get each feature's mean and standard deviation over the training
+    /// data, as (means, stds). Return None if called before `fit()` or if
+    /// standardization wasn't enabled at `fit()`.
+    pub fn scaling_params(&self) -> Option<(Vec<f64>, Vec<f64>)> {
+        let stds = self.stds.as_ref()?;
+        Some((self.means.clone(), stds.clone()))
+    }
+
+    /// Train the classifier as in `fit()`, but compute distances from only
+    /// the features whose entry in `feature_mask` is true, e.g. to see how
+    /// much a feature matters without slicing the data. The data is stored
+    /// whole, and queries keep all their features. The mask applies until
+    /// the next fit; automatic feature weights are normalized over the
+    /// features kept. Not available with metrics that allow varying
+    /// lengths.
+    pub fn fit_with_mask(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>, feature_mask: &[bool]) {
+        self.fit_masked(data, labels, Some(feature_mask));
+    }
+
+    /// Train the classifier on `train`, then predict `test` and summarize
+    /// the predictions against `test_labels` with `evaluation_report()`.
+    /// Return None if `test` is empty.
+    pub fn fit_evaluate(&mut self, train: Vec<Vec<f64>>, train_labels: Vec<T>,
+                        test: &[Vec<f64>], test_labels: &[T]) -> Option<EvaluationReport<T>> {
+        assert_eq!(test.len(), test_labels.len(), "test data and labels differ in length");
+        self.fit(train, train_labels);
+        if test.is_empty() {
+            return None;
+        }
+        let predictions = self.predict(test)?;
+        Some(evaluation_report(test_labels, &predictions))
+    }
+
+    /// Weight each neighbour's vote by a Gaussian kernel of its distance,
+    /// `exp(-d² / (2σ²))` for bandwidth σ, or give every neighbour the same
+    /// weight with None (the default). A class then scores the fraction of
+    /// the weight of its k nearest neighbours that it carries. A small σ
+    /// approaches 1-nearest-neighbour voting and a large one plain majority
+    /// voting; `tune_bandwidth()` picks one from candidates.
+    pub fn set_bandwidth(&mut self, bandwidth: Option<f64>) {
+        assert!(bandwidth.is_none_or(|sigma| sigma > 0.0), "bandwidth must be positive");
+        self.bandwidth = bandwidth;
+    }
+
+    /// Find the Gaussian bandwidth among `sigmas` that predicts `val_data`
+    /// most accurately, as a (bandwidth, accuracy) pair. Ties go to the
+    /// bandwidth given first. The neighbours are searched once and reused
+    /// for every candidate; the classifier's own bandwidth is unchanged.
+    /// Return None if called before `fit()`.
+    pub fn tune_bandwidth(&self, val_data: &[Vec<f64>], val_labels: &[T], sigmas: &[f64]) -> Option<(f64, f64)> {
+        assert_eq!(val_data.len(), val_labels.len(), "data and labels differ in length");
+        assert!(!sigmas.is_empty(), "no bandwidths given");
+        let train = self.train()?;
+        let (k, metric) = (self.search_k(), self.metric);
+        let neighbours = parallel::map_rows(val_data, |x| nearest(train, metric, k, x));
+        let mut best: Option<(f64, f64)> = None;
+        for &sigma in sigmas {
+            assert!(sigma > 0.0, "bandwidth must be positive");
+            let correct = neighbours.iter().zip(val_labels.iter())
+                .filter(|&(neigh, label)| self.vote_with(neigh, Some(sigma)) == label)
+                .count();
+            let accuracy = correct as f64 / val_data.len() as f64;
+            if best.is_none_or(|(_, best_accuracy)| accuracy > best_accuracy) {
+                best = Some((sigma, accuracy));
+            }
+        }
+        best
+    }
+
+    /// Find the metric among `candidates` under which the classifier
+    /// predicts `val_data` most accurately, as a (metric, accuracy) pair.
+    /// Ties go to the metric given first. The training data is shared by
+    /// every candidate rather than copied; the classifier's own metric is
+    /// unchanged. Return None if called before `fit()`.
+    pub fn select_metric(&self, val_data: &[Vec<f64>], val_labels: &[T], candidates: &[Metric]) -> Option<(Metric, f64)> {
+        assert_eq!(val_data.len(), val_labels.len(), "data and labels differ in length");
+        assert!(!candidates.is_empty(), "no metrics given");
+        let train = self.train()?;
+        let k = self.search_k();
+        let mut best: Option<(Metric, f64)> = None;
+        for &metric in candidates {
+            let neighbours = parallel::map_rows(val_data, |x| nearest(train, metric, k, x));
+            let correct = neighbours.iter().zip(val_labels.iter())
+                .filter(|&(neigh, label)| self.vote(neigh) == label)
+                .count();
+            let accuracy = correct as f64 / val_data.len() as f64;
+            if best.is_none_or(|(_, best_accuracy)| accuracy > best_accuracy) {
+                best = Some((metric, accuracy));
+            }
+        }
+        best
+    }
+
+    /// Screen features one at a time: for each feature, retrain a copy of
+    /// the classifier on the training data with `fit_with_mask()` keeping
+    /// only that feature, and score its accuracy on `val_data`. Returns the
+    /// accuracies in feature order. A feature scoring near chance carries
+    /// little information on its own, though it may still help in
+    /// combination with others. The classifier itself is unchanged. Not
+    /// available with a transform matrix. Return None if called before
+    /// `fit()`.
+    pub fn univariate_knn_scores(&self, val_data: &[Vec<f64>], val_labels: &[T]) -> Option<Vec<f64>> {
+        assert_eq!(val_data.len(), val_labels.len(), "data and labels differ in length");
+        let train = self.train()?;
+        let labels = self.labels.as_ref()?;
+        // The training data as given to `fit()`, up to order.
+        let mut data = Vec::with_capacity(train.len());
+        let mut all_labels = Vec::with_capacity(train.len());
+        for (i, label) in labels.iter().enumerate() {
+            let row = train.row(i);
+            for _ in 0..self.multiplicity(i) {
+                data.push(row.to_vec());
+                all_labels.push(label.clone());
+            }
+        }
+        let dim = train.dim().unwrap_or(0);
+        Some((0..dim).map(|j| {
+            let mask: Vec<bool> = (0..dim).map(|i| i == j).collect();
+            let mut clf = self.clone();
+            clf.fit_with_mask(data.clone(), all_labels.clone(), &mask);
+            let predictions = clf.predict_ref(val_data).expect("no predictions after fit()");
+            let correct = predictions.iter().zip(val_labels.iter()).filter(|&(p, l)| *p == l).count();
+            correct as f64 / val_data.len() as f64
+        }).collect())
+    }
+
+    /// Predict the labels of datapoints without cloning them: the returned
+    /// references point into the labels stored at `fit()`. Return None if
     /// called before `fit()`.
-    fn predict(&self, data: &Vec<Vec<f64>>) -> Option<Vec<T>> {
-        if self.data.is_none() {
+    pub fn predict_ref(&self, data: &[Vec<f64>]) -> Option<Vec<&T>> {
+        let train = self.train()?;
+        let (k, metric) = (self.search_k(), self.metric);
+        // The neighbour searches are independent per query, so they can run
+        // in parallel; the votes are cheap and stay on this thread.
+        let search = |x_test: &Vec<f64>| nearest(train, metric, k, x_test);
+        let neighbours: Vec<_> = if self.runs_parallel(data.len()) {
+            parallel::map_rows(data, search)
+        } else {
+            data.iter().map(search).collect()
+        };
+        Some(neighbours.iter().map(|neigh| self.vote(neigh)).collect())
+    }
+
+    /// Predict the labels of datapoints along with their class
+    /// probabilities, searching the neighbours of each datapoint once for
+    /// both. The label is the one `predict()` gives, which has the highest
+    /// probability. Return None if called before `fit()`.
+    pub fn predict_records(&self, data: &[Vec<f64>]) -> Option<Vec<PredictionRecord<T>>> {
+        let train = self.train()?;
+        let (k, metric) = (self.search_k(), self.metric);
+        let neighbours = parallel::map_rows(data, |x| nearest(train, metric, k, x));
+        Some(neighbours.iter().map(|neigh| PredictionRecord {
+            label: self.vote(neigh).clone(),
+            probabilities: self.proba(neigh),
+        }).collect())
+    }
+
+    /// Predict the labels of datapoints, deferring the ambiguous ones to a
+    /// human: a datapoint gets None instead of a label when its vote margin,
+    /// the probability of the most likely class minus that of the runner-up
+    /// as from `predict_proba()`, is below `min_margin`. With a single
+    /// class the margin is 1. Return None if called before `fit()`.
+    pub fn predict_or_defer(&self, data: &[Vec<f64>], min_margin: f64) -> Option<Vec<Option<T>>> {
+        let train = self.train()?;
+        let (k, metric) = (self.search_k(), self.metric);
+        let neighbours = parallel::map_rows(data, |x| nearest(train, metric, k, x));
+        Some(neighbours.iter().map(|neigh| {
+            if vote_margin(&self.proba(neigh)) < min_margin {
+                None
+            } else {
+                Some(self.vote(neigh).clone())
+            }
+        }).collect())
+    }
+
+    /// Estimate class probabilities for one datapoint as `predict_proba()`
+    /// does, along with the distances to the neighbours they came from,
+    /// closest first, e.g. to calibrate probabilities that rest on distant
+    /// neighbours. Return None if called before `fit()`.
+    pub fn predict_proba_detailed(&self, x: &[f64]) -> Option<(ClassProbabilities<T>, Vec<f64>)> {
+        let neighbours = self.kneighbors(x)?;
+        Some((self.proba(&neighbours), neighbours.iter().map(|&(_, d)| d).collect()))
+    }
+
+    /// Search the neighbours of `queries` once, for `predict_cached()` and
+    /// `predict_records_cached()` to reuse while the model is tweaked. The
+    /// cache is tied to the training data and metric at the time of the
+    /// call: it stays valid through changes to the vote, such as the
+    /// bandwidth, prior correction or a smaller k, but must be computed
+    /// again after a new `fit()`, `augment_gaussian()` or `quantize_u8()`.
+    /// Return None if called before `fit()`.
+    pub fn precompute_neighbors(&self, queries: &[Vec<f64>]) -> Option<NeighborCache> {
+        let train = self.train()?;
+        let (k, metric) = (self.search_k(), self.metric);
+        Some(NeighborCache {
+            neighbours: parallel::map_rows(queries, |x| nearest(train, metric, k, x)),
+            k,
+            n_train: train.len(),
+        })
+    }
+
+    /// Predict the labels of the queries in `cache`, in order, as
+    /// `predict()` would. Return None if called before `fit()`, or if the
+    /// cache visibly doesn't fit the training data: it was computed for a
+    /// different number of training rows or for fewer neighbours than are
+    /// now needed. Other stale caches go undetected; see
+    /// `precompute_neighbors()`.
+    pub fn predict_cached(&self, cache: &NeighborCache) -> Option<Vec<T>> {
+        let k = self.cached_k(cache)?;
+        Some(cache.neighbours.iter().map(|neigh| self.vote(&neigh[..k.min(neigh.len())]).clone()).collect())
+    }
+
+    /// Predict the labels and class probabilities of the queries in
+    /// `cache`, in order, as `predict_records()` would. Return None as
+    /// `predict_cached()` does.
+    pub fn predict_records_cached(&self, cache: &NeighborCache) -> Option<Vec<PredictionRecord<T>>> {
+        let k = self.cached_k(cache)?;
+        Some(cache.neighbours.iter().map(|neigh| {
+            let neigh = &neigh[..k.min(neigh.len())];
+            PredictionRecord { label: self.vote(neigh).clone(), probabilities: self.proba(neigh) }
+        }).collect())
+    }
+
+    /// The number of cached neighbours to vote with, if the cache fits the
+    /// training data.
+    fn cached_k(&self, cache: &NeighborCache) -> Option<usize> {
+        let train = self.train()?;
+        let k = self.search_k();
+        if train.len() != cache.n_train || k > cache.k {
             return None;
         }
-        let mut ret = Vec::with_capacity(data.len());
-        for x_test in data {
-            ret.push(self.predict_one(x_test).unwrap());
+        Some(k)
+    }
+
+    /// Predict the labels of datapoints as `predict()` does, calling
+    /// `on_progress(done, total)` after every 256 datapoints and after the
+    /// last, with `done` the number predicted so far. Each batch is
+    /// searched in parallel when the `parallel` feature is enabled. Return
+    /// None, without calling `on_progress`, if called before `fit()`.
+    pub fn predict_with_progress<F: FnMut(usize, usize)>(&self, data: &[Vec<f64>], mut on_progress: F) -> Option<Vec<T>> {
+        self.train()?;
+        let mut predictions = Vec::with_capacity(data.len());
+        for chunk in data.chunks(PROGRESS_CHUNK) {
+            predictions.extend(self.predict_ref(chunk)?.into_iter().cloned());
+            on_progress(predictions.len(), data.len());
         }
-        Some(ret)
+        Some(predictions)
     }
 
-    /// Predict the label for one datapoint. Return None if `predict_one()`
-    /// is called before `fit()`.
-    fn predict_one(&self, x: &Vec<f64>) -> Option<T> {
-        match self.data {
-            Some(ref data) => {
-                // Store the indices of the k nearest neighours so far.
-                let mut best_neigh = Vec::with_capacity(self.k);
-                let mut best_dists = Vec::with_capacity(self.k);
-                for (i, x_train) in data.iter().enumerate() {
-                    let dist = squared_distance(x, x_train);
-                    if best_neigh.len() < self.k {
-                        best_neigh.push(i);
-                        best_dists.push(dist);
-                    } else {
-                        for j in 0..self.k {
-                            // TODO: Use BTreeSet so that we can break out
-                            // earlier here.
-                            if dist < best_dists[j] {
-                                best_dists[j] = dist;
-                                best_neigh[j] = i;
-                                break;
-                            }
-                        }
-                    }
+    /// Measure how long predicting a single datapoint takes, timing
+    /// `predict_one()` on every datapoint `repeats` times over. Each query
+    /// runs on its own, without batching or threads, so this is the
+    /// latency a server answering one request at a time would see. Return
+    /// None if called before `fit()`.
+    pub fn benchmark_latency(&self, data: &[Vec<f64>], repeats: usize) -> Option<LatencyStats> {
+        assert!(!data.is_empty() && repeats > 0, "nothing to benchmark");
+        self.train()?;
+        let mut latencies = Vec::with_capacity(data.len() * repeats);
+        for _ in 0..repeats {
+            for x in data {
+                let start = Instant::now();
+                self.predict_one_ref(x)?;
+                latencies.push(start.elapsed().as_secs_f64());
+            }
+        }
+        let percentile = |q: f64| Duration::from_secs_f64(quantile(&latencies, q));
+        Some(LatencyStats {
+            mean: Duration::from_secs_f64(latencies.iter().sum::<f64>() / latencies.len() as f64),
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+        })
+    }
+
+    /// Predict the label for one datapoint without cloning it. Return None
+    /// if called before `fit()`.
+    pub fn predict_one_ref(&self, x: &[f64]) -> Option<&T> {
+        let neighbours = self.kneighbors(x)?;
+        Some(self.vote(&neighbours))
+    }
+
+    /// Predict the label for one datapoint as the class whose mean training
+    /// point is nearest, ties going to the class seen first. The means are
+    /// computed at `fit()`, so this compares against one point per class
+    /// instead of every training point: a fast approximation of the full
+    /// search for serving under tight latency budgets. It agrees with KNN
+    /// when the classes form compact, well-separated blobs, but not near
+    /// class boundaries, nor for classes that are elongated, curved or
+    /// split into several clusters. Only meaningful for fixed-length
+    /// feature vectors. Return None if called before `fit()`.
+    pub fn predict_one_centroid(&self, x: &[f64]) -> Option<T> {
+        let mut best: Option<(usize, f64)> = None;
+        for (i, centroid) in self.centroids.iter().enumerate() {
+            let dist = self.pair_distance(x, centroid);
+            if best.is_none_or(|(_, best_dist)| dist < best_dist) {
+                best = Some((i, dist));
+            }
+        }
+        best.map(|(i, _)| self.classes[i].clone())
+    }
+
+    /// Predict the label for one datapoint with some features missing,
+    /// filling each missing feature with its mean over the training data
+    /// given to `fit()`. Return None if called before `fit()` or if `x`
+    /// doesn't have as many features as the training data.
+    pub fn predict_one_partial(&self, x: &[Option<f64>]) -> Option<T> {
+        self.train()?;
+        if x.len() != self.means.len() {
+            return None;
+        }
+        let imputed: Vec<f64> = x.iter().zip(self.means.iter()).map(|(v, &mean)| v.unwrap_or(mean)).collect();
+        self.predict_one_ref(&imputed).cloned()
+    }
+
+    /// Predict the label for one datapoint, or reject it as novel with
+    /// `Some(None)` if its mean distance to its k nearest training points
+    /// exceeds `max_distance`, e.g. one from `novelty_threshold()`. Uses the
+    /// k given at construction for the distance. Return None if called
+    /// before `fit()`.
+    pub fn predict_one_reject_far(&self, x: &[f64], max_distance: f64) -> Option<Option<T>> {
+        let neighbours = self.kneighbors(x)?;
+        let mean = mean_distance(&self.first_points(&neighbours, self.k));
+        Some(if mean > max_distance { None } else { Some(self.vote(&neighbours).clone()) })
+    }
+
+    /// Explain the prediction for `x` feature by feature: the attribution
+    /// of feature j is how much the probability of the predicted label
+    /// drops when feature j alone is replaced by its mean over the training
+    /// data. Positive values mark features that support the prediction,
+    /// negative ones features that argue against it, and values near 0
+    /// features the prediction doesn't hinge on. Unlike SHAP values, these
+    /// ablations ignore interactions between features, so they needn't sum
+    /// to anything in particular. Return None if called before `fit()` or
+    /// if `x` doesn't have as many features as the training data.
+    pub fn feature_attributions(&self, x: &[f64]) -> Option<Vec<f64>> {
+        let train = self.train()?;
+        if x.len() != self.means.len() {
+            return None;
+        }
+        let (k, metric) = (self.search_k(), self.metric);
+        let neighbours = nearest(train, metric, k, x);
+        let label = self.vote(&neighbours);
+        let probability = |neighbours: &[(usize, f64)]| {
+            self.proba(neighbours).into_iter().find(|(l, _)| l == label).map_or(0.0, |(_, p)| p)
+        };
+        let base = probability(&neighbours);
+        let ablated: Vec<Vec<f64>> = (0..x.len()).map(|j| {
+            let mut ablated = x.to_vec();
+            ablated[j] = self.means[j];
+            ablated
+        }).collect();
+        Some(parallel::map_rows(&ablated, |x| nearest(train, metric, k, x)).iter()
+            .map(|neighbours| base - probability(neighbours))
+            .collect())
+    }
+
+    /// Measure how stable the prediction for `x` is under small changes:
+    /// the fraction of `trials` noisy copies of `x`, each feature shifted by
+    /// independent Gaussian noise of standard deviation `sigma`, that are
+    /// predicted the same label as `x` itself. Points deep inside a class
+    /// score 1; points near a decision boundary lower. Return None if called
+    /// before `fit()`.
+    pub fn prediction_stability(&self, x: &[f64], sigma: f64, trials: usize, random_state: Option<RandomState>) -> Option<f64> {
+        assert!(sigma >= 0.0, "sigma must be non-negative");
+        assert!(trials > 0, "trials must be positive");
+        let label = self.predict_one_ref(x)?;
+        let mut state = RandomState::resolve(random_state);
+        let rng = state.rng();
+        let mut noisy = vec![0f64; x.len()];
+        let mut kept = 0;
+        for _ in 0..trials {
+            for (y, &v) in noisy.iter_mut().zip(x.iter()) {
+                *y = v + sigma * rng.next_gaussian();
+            }
+            if self.predict_one_ref(&noisy)? == label {
+                kept += 1;
+            }
+        }
+        Some(kept as f64 / trials as f64)
+    }
+
+    /// Predict every label that ties for the top vote score for one
+    /// datapoint, scored as in `predict_one()`, instead of breaking the tie.
+    /// A clear winner comes back alone. The labels are in order of their
+    /// nearest neighbour, so the first is the one `predict_one()` gives.
+    /// Return None if called before `fit()`.
+    pub fn predict_one_multi(&self, x: &[f64]) -> Option<Vec<T>> {
+        let neighbours = self.kneighbors(x)?;
+        let labels = self.labels.as_ref()?;
+        let mut best: Vec<&T> = Vec::new();
+        let mut best_score = 0f64;
+        for (i, &(idx, _)) in neighbours.iter().enumerate() {
+            let label = &labels[idx];
+            if neighbours[..i].iter().any(|&(j, _)| labels[j] == *label) {
+                continue;
+            }
+            let mut score = self.label_fraction(&neighbours, label, self.bandwidth);
+            if self.prior_correction {
+                score /= self.priors[label];
+            }
+            if best.is_empty() || score > best_score {
+                best.clear();
+                best_score = score;
+            }
+            if score == best_score {
+                best.push(label);
+            }
+        }
+        Some(best.into_iter().cloned().collect())
+    }
+
+    /// Predict the label for one datapoint along with its neighbour purity:
+    /// the fraction of the k nearest neighbours carrying the predicted
+    /// label. Return None if called before `fit()`.
+    pub fn predict_one_purity(&self, x: &[f64]) -> Option<(T, f64)> {
+        let neighbours = self.kneighbors(x)?;
+        let label = self.vote(&neighbours);
+        let labels = self.labels.as_ref()?;
+        let points = self.first_points(&neighbours, self.search_k());
+        let agreeing: usize = points.iter().filter(|&&(j, _, _)| labels[j] == *label).map(|&(_, m, _)| m).sum();
+        let total: usize = points.iter().map(|&(_, m, _)| m).sum();
+        Some((label.clone(), agreeing as f64 / total as f64))
+    }
+
+    /// Predict the label for one datapoint after shifting each class's
+    /// decision threshold: the class with the highest `predict_proba()`
+    /// probability minus its entry in `thresholds` wins, with classes
+    /// missing from `thresholds` shifted by 0. Raising a class's threshold
+    /// makes it harder to predict. Ties go to the class seen first in
+    /// training. Return None if called before `fit()`.
+    pub fn predict_one_with_thresholds(&self, x: &[f64], thresholds: &HashMap<T, f64>) -> Option<T> {
+        let proba = self.predict_proba(&x.to_vec())?;
+        let mut best: Option<(T, f64)> = None;
+        for (class, p) in proba {
+            let score = p - thresholds.get(&class).cloned().unwrap_or(0.0);
+            if best.as_ref().is_none_or(|&(_, best_score)| score > best_score) {
+                best = Some((class, score));
+            }
+        }
+        best.map(|(class, _)| class)
+    }
+
+    /// Measure how spread out the k nearest neighbours of `x` are, as the
+    /// mean distance between each pair of them. Neighbours drawn from one
+    /// tight region give a low value; a high one means they come from
+    /// different regions and the prediction is more ambiguous. Return None
+    /// if called before `fit()`.
+    pub fn predict_one_diversity(&self, x: &[f64]) -> Option<f64> {
+        let train = self.train()?;
+        let points = self.first_points(&self.kneighbors(x)?, self.search_k());
+        let neighbours: Vec<(Vec<f64>, usize)> = points.iter().map(|&(j, m, _)| (train.row(j).into_owned(), m)).collect();
+        let n: usize = neighbours.iter().map(|&(_, m)| m).sum();
+        if n < 2 {
+            return Some(0.0);
+        }
+        let total: f64 = neighbours.iter()
+            .map(|(x, mx)| neighbours.iter().map(|(y, my)| (mx * my) as f64 * self.pair_distance(x, y)).sum::<f64>())
+            .sum();
+        // Every pair is counted twice, and the diagonal, copies of a point
+        // included, is zero.
+        Some(total / (n * (n - 1)) as f64)
+    }
+
+    /// Estimate the density of the training data at `x` with a Gaussian
+    /// kernel of standard deviation `bandwidth` around every training point:
+    /// the mean over training points of `exp(-d² / 2h²) / (2πh²)^(dim/2)`,
+    /// where `d` is the distance under the classifier's metric. With the
+    /// Euclidean metric this is a proper probability density; low values
+    /// flag points unlike anything seen in training. Return None if called
+    /// before `fit()`.
+    pub fn kde(&self, x: &[f64], bandwidth: f64) -> Option<f64> {
+        assert!(bandwidth > 0.0, "bandwidth must be positive");
+        let train = self.train()?;
+        let x = &*train.project(x);
+        let variance = bandwidth * bandwidth;
+        let norm = (2.0 * std::f64::consts::PI * variance).powf(x.len() as f64 / 2.0);
+        let (mut total, mut count) = (0f64, 0usize);
+        for i in 0..train.len() {
+            let d = train.distance(self.metric, x, i);
+            let m = self.multiplicity(i);
+            total += m as f64 * (-d * d / (2.0 * variance)).exp();
+            count += m;
+        }
+        Some(if count > 0 { total / count as f64 / norm } else { 0.0 })
+    }
+
+    /// Predict the labels of datapoints, each along with the indices of its
+    /// features lying outside the range seen in training (see
+    /// `feature_ranges()`). Such predictions extrapolate and deserve less
+    /// trust. Return None if called before `fit()`.
+    pub fn predict_with_warnings(&self, data: &[Vec<f64>]) -> Option<Vec<(T, Vec<usize>)>> {
+        let ranges = self.feature_ranges()?;
+        let predictions = self.predict_ref(data)?;
+        Some(predictions.into_iter().zip(data.iter()).map(|(label, x)| {
+            let outside = x.iter().zip(ranges.iter()).enumerate()
+                .filter(|&(_, (v, &(lo, hi)))| *v < lo || *v > hi)
+                .map(|(j, _)| j)
+                .collect();
+            (label.clone(), outside)
+        }).collect())
+    }
+
+    /// Get the (min, max) of each feature over the training data. Return
+    /// None if called before `fit()`.
+    pub fn feature_ranges(&self) -> Option<Vec<(f64, f64)>> {
+        let train = self.train()?;
+        let dim = train.dim().unwrap_or(0);
+        let mut ranges = vec![(f64::INFINITY, f64::NEG_INFINITY); dim];
+        for i in 0..train.len() {
+            let x = train.row(i);
+            for (range, &v) in ranges.iter_mut().zip(x.iter()) {
+                range.0 = range.0.min(v);
+                range.1 = range.1.max(v);
+            }
+        }
+        Some(ranges)
+    }
+
+    /// Score how strongly one datapoint belongs to `positive_label`: the
+    /// fraction of that label's k nearest neighbours carrying it. Higher
+    /// scores mean more confidence, which is what ROC analysis of a binary
+    /// classifier needs. Return None if called before `fit()`.
+    pub fn decision_function_one(&self, x: &[f64], positive_label: &T) -> Option<f64> {
+        let neighbours = self.kneighbors(x)?;
+        Some(self.label_fraction(&neighbours, positive_label, self.bandwidth))
+    }
+
+    /// Score datapoints as in `decision_function_one()`. Return None if
+    /// called before `fit()`.
+    pub fn decision_function(&self, data: &[Vec<f64>], positive_label: &T) -> Option<Vec<f64>> {
+        let train = self.train()?;
+        let (k, metric) = (self.search_k(), self.metric);
+        let neighbours = parallel::map_rows(data, |x| nearest(train, metric, k, x));
+        Some(neighbours.iter().map(|neigh| self.label_fraction(neigh, positive_label, self.bandwidth)).collect())
+    }
+
+    /// Trace the boundaries between predicted classes over a rectangle, for
+    /// classifiers trained on 2D data. Predictions are made on a grid of
+    /// `resolution` by `resolution` points spanning `x_range` and `y_range`
+    /// (inclusive), and each grid cell whose corners aren't all predicted
+    /// alike gets boundary segments, marching-squares style, between the
+    /// midpoints of its edges with differing ends: one segment if two edges
+    /// differ, otherwise one from each such midpoint to the cell's centre.
+    /// The segments are accurate to about one grid step.
+    /// Return None if called before `fit()`.
+    ///
+    /// # Panics
+    ///
+    /// If the training data isn't 2D, or `resolution` is less than 2.
+    pub fn decision_boundary_segments(&self, x_range: (f64, f64), y_range: (f64, f64), resolution: usize)
+        -> Option<Vec<Segment>>
+    {
+        assert!(resolution >= 2, "resolution must be at least 2");
+        let train = self.train()?;
+        assert!(train.dim().is_none_or(|dim| dim == 2), "decision boundaries need 2D data");
+        let step = |(lo, hi): (f64, f64), i: usize| lo + (hi - lo) * i as f64 / (resolution - 1) as f64;
+        let grid: Vec<Vec<f64>> = (0..resolution)
+            .flat_map(|j| (0..resolution).map(move |i| vec![step(x_range, i), step(y_range, j)]))
+            .collect();
+        let predictions = self.predict_ref(&grid)?;
+        let at = |i: usize, j: usize| ((step(x_range, i), step(y_range, j)), predictions[j * resolution + i]);
+        let mut segments = Vec::new();
+        for j in 0..resolution - 1 {
+            for i in 0..resolution - 1 {
+                // The corners in order around the cell, and the midpoints of
+                // the edges between consecutive ones that differ.
+                let corners = [at(i, j), at(i + 1, j), at(i + 1, j + 1), at(i, j + 1)];
+                let crossings: Vec<(f64, f64)> = (0..4).filter_map(|c| {
+                    let ((x0, y0), a) = corners[c];
+                    let ((x1, y1), b) = corners[(c + 1) % 4];
+                    if a != b { Some(((x0 + x1) / 2.0, (y0 + y1) / 2.0)) } else { None }
+                }).collect();
+                match crossings.len() {
+                    0 => {},
+                    2 => segments.push((crossings[0], crossings[1])),
+                    _ => {
+                        let centre = ((corners[0].0.0 + corners[2].0.0) / 2.0, (corners[0].0.1 + corners[2].0.1) / 2.0);
+                        segments.extend(crossings.into_iter().map(|p| (p, centre)));
+                    },
                 }
-                let ctr = match self.labels {
-                    Some(ref labels) => Counter::with_iterator(best_neigh.iter().map(|&idx| {
-                        labels[idx].clone()
-                    })),
-                    None             => panic!("Empty labels after training"),
-                };
-                let (ret, _): (&T, u64) = ctr.most_frequent().unwrap();
-                Some((*ret).clone())
-            },
-            None => None
+            }
         }
+        Some(segments)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use core::Classifier;
+    /// Predict the labels of datapoints into `out`, reusing its capacity.
+    /// Once `out` and the internal neighbour buffer have grown to size, no
+    /// further heap allocations are made (provided cloning a label and the
+    /// metric don't allocate). Return None, leaving `out` empty, if called
+    /// before `fit()` or if a datapoint's dimension doesn't match the
+    /// training data.
+    pub fn predict_into(&self, data: &[Vec<f64>], out: &mut Vec<T>) -> Option<()> {
+        out.clear();
+        let train = self.train()?;
+        if !data.iter().all(|x| self.dimension_matches(x)) {
+            return None;
+        }
+        NEIGHBOURS.with(|buf| {
+            let mut neighbours = buf.borrow_mut();
+            for x in data {
+                nearest_into(train, self.metric, self.search_k(), x, &mut neighbours);
+                out.push(self.vote(&neighbours).clone());
+            }
+        });
+        Some(())
+    }
 
-    #[test]
-    fn test_creation() {
-        let clf: KNNClassifier<String> = KNNClassifier::new(3);
-        assert_eq!(clf.data, None);
-        assert_eq!(clf.labels, None);
+    /// Find the k training points nearest to `x` as (index, distance) pairs,
+    /// closest first. With per-class k values, the largest k is used. Return
+    /// None if called before `fit()`.
+    pub fn kneighbors(&self, x: &[f64]) -> Option<Vec<(usize, f64)>> {
+        let train = self.train()?;
+        Some(nearest(train, self.metric, self.search_k(), x))
     }
 
-    #[test]
-    fn test_fit() {
-        let mut clf: KNNClassifier<String> = KNNClassifier::new(3);
+    /// Find the k training points nearest to `x`, writing their indices and
+    /// distances, closest first, into the given buffers and reusing their
+    /// capacity. Return None, leaving both buffers empty, if called before
+    /// `fit()` or if the dimension of `x` doesn't match the training data.
+    pub fn kneighbors_into(&self, x: &[f64], indices: &mut Vec<usize>, distances: &mut Vec<f64>) -> Option<()> {
+        indices.clear();
+        distances.clear();
+        let train = self.train()?;
+        if !self.dimension_matches(x) {
+            return None;
+        }
+        NEIGHBOURS.with(|buf| {
+            let mut neighbours = buf.borrow_mut();
+            nearest_into(train, self.metric, self.search_k(), x, &mut neighbours);
+            for &(idx, dist) in neighbours.iter() {
+                indices.push(idx);
+                distances.push(dist);
+            }
+        });
+        Some(())
+    }
 
-        let train: Vec<Vec<f64>> = vec![
-            vec![0.0, 1.0, 2.0, 2.0, 3.0],
-            vec![5.0, 4.0, 3.0, 4.0, 5.0],
-        ];
+    /// Find the training points that are not among the k nearest neighbours
+    /// of any datapoint in `val_data`. These contribute nothing to those
+    /// predictions and are candidates for pruning. Return None if called
+    /// before `fit()`.
+    pub fn unused_training_points(&self, val_data: &[Vec<f64>]) -> Option<Vec<usize>> {
+        let n_train = self.train()?.len();
+        let mut used = vec![false; n_train];
+        for x in val_data {
+            for (idx, _) in self.kneighbors(x)? {
+                used[idx] = true;
+            }
+        }
+        Some((0..n_train).filter(|&i| !used[i]).collect())
+    }
 
-        let labels: Vec<String> = vec![
-            "good".to_string(),
-            "bad".to_string(),
-        ];
+    /// For each class, find the distance from each of its training points
+    /// to the nearest other training point of the same class, in training
+    /// order. These show how tightly each class is packed, e.g. to choose a
+    /// per-class distance beyond which a prediction is rejected. Points that
+    /// are alone in their class have no entry. Return None if called before
+    /// `fit()`.
+    pub fn intra_class_nn_distances(&self) -> Option<HashMap<T, Vec<f64>>> {
+        let train = self.train()?;
+        let labels = self.labels.as_ref()?;
+        let mut groups: HashMap<&T, Vec<usize>> = HashMap::new();
+        for (i, label) in labels.iter().enumerate() {
+            groups.entry(label).or_default().push(i);
+        }
+        Some(groups.into_iter().map(|(label, indices)| {
+            let members: Vec<Vec<f64>> = indices.iter().map(|&i| train.row(i).into_owned()).collect();
+            let distances = members.iter().enumerate().filter_map(|(i, x)| {
+                // The point itself is among its two nearest members, unless
+                // it has duplicates, which are just as good a neighbour.
+                let rows = Rows { storage: Storage::Dense(&members), projected: None, ..train };
+                nearest(rows, self.metric, 2, x).into_iter()
+                    .find(|&(j, _)| j != i)
+                    .map(|(_, dist)| dist)
+            }).collect();
+            (label.clone(), distances)
+        }).collect())
+    }
 
-        clf.fit(train.clone(), labels.clone());
+    /// Find each training point's distance to its k-th nearest other
+    /// training point, sorted ascending. Plotted against rank, these show a
+    /// "knee" where points stop being in dense regions, which is a good
+    /// choice of `eps` for DBSCAN with `min_samples = k + 1`. Return None if
+    /// called before `fit()`.
+    ///
+    /// # Panics
+    ///
+    /// If `k` is 0 or not less than the number of training points.
+    pub fn k_distance(&self, k: usize) -> Option<Vec<f64>> {
+        let train = self.train()?;
+        let n: usize = (0..train.len()).map(|i| self.multiplicity(i)).sum();
+        assert!(k >= 1 && k < n, "k must be in [1, number of training points)");
+        let (metric, multiplicity) = (self.metric, self.multiplicity.as_deref());
+        let rows: Vec<usize> = (0..train.len()).collect();
+        let per_row = parallel::map_rows(&rows, |&i| {
+            // Skip the point itself, but not its duplicates. If ties push it
+            // out of the k + 1 nearest, the k-th distance is 0 anyway.
+            let mut remaining = k;
+            for (j, d) in nearest(train, metric, k + 1, &train.row(i)) {
+                let m = multiplicity.map_or(1, |m| m[j]) - (j == i) as usize;
+                if m >= remaining {
+                    return d;
+                }
+                remaining -= m;
+            }
+            unreachable!("fewer than k + 1 training points")
+        });
+        let mut distances: Vec<f64> = per_row.into_iter().enumerate()
+            .flat_map(|(i, d)| std::iter::repeat_n(d, self.multiplicity(i)))
+            .collect();
+        distances.sort_by(|a, b| a.total_cmp(b));
+        Some(distances)
+    }
 
-        assert_eq!(clf.data, Some(train));
-        assert_eq!(clf.labels, Some(labels));
+    /// Measure how redundant the training data is, as the mean overlap
+    /// between the neighbourhoods of each training point and of its nearest
+    /// other training point. A neighbourhood is the set of k training
+    /// points nearest to a point's position, itself or an identical copy
+    /// included, and the overlap is one minus their Jaccard distance.
+    /// Scores near 1 mean most points could be dropped without changing the
+    /// neighbourhoods much, which suggests subsampling; points that are
+    /// exact copies of another score 1. Return None if called before
+    /// `fit()`.
+    ///
+    /// # Panics
+    ///
+    /// If there are fewer than two training points.
+    pub fn redundancy_score(&self) -> Option<f64> {
+        let train = self.train()?;
+        let (k, metric) = (self.k, self.metric);
+        let n: usize = (0..train.len()).map(|i| self.multiplicity(i)).sum();
+        assert!(n >= 2, "redundancy needs at least two training points");
+        let neighbourhoods: Vec<Vec<usize>> = parallel::map_rows(&(0..train.len()).collect::<Vec<_>>(), |&i| {
+            nearest(train, metric, k, &train.row(i)).into_iter().map(|(j, _)| j).collect()
+        });
+        let mut total = 0f64;
+        for (i, own) in neighbourhoods.iter().enumerate() {
+            let m = self.multiplicity(i);
+            if m > 1 {
+                // A deduplicated row stands for identical points, whose
+                // neighbourhoods coincide.
+                total += m as f64;
+                continue;
+            }
+            let closest = nearest(train, metric, 2, &train.row(i)).into_iter()
+                .find(|&(j, _)| j != i)
+                .map(|(j, _)| j);
+            if let Some(j) = closest {
+                total += 1.0 - jaccard_distance(own, &neighbourhoods[j]);
+            }
+        }
+        Some(total / n as f64)
     }
 
-    #[test]
-    fn test_predict() {
-        let mut clf = KNNClassifier::new(1);
+    /// Measure how well the training labels agree with their neighbours:
+    /// the mean, over training points, of the fraction of each point's k
+    /// nearest other training points that share its label. Each point is
+    /// left out of its own neighbourhood, but its duplicates are not.
+    /// Clean, well-separated classes score near 1; low values warn of
+    /// label noise or overlapping classes, before trusting the data. Uses
+    /// the k given at construction. Return None if called before `fit()`.
+    ///
+    /// # Panics
+    ///
+    /// If there are no more training points than k.
+    pub fn neighborhood_consistency(&self) -> Option<f64> {
+        let labels = self.labels.as_ref()?;
+        let k = self.k;
+        let neighbourhoods = self.neighbours_of_training_points()?;
+        let n: usize = (0..labels.len()).map(|i| self.multiplicity(i)).sum();
+        let total: f64 = neighbourhoods.iter().enumerate().map(|(i, neighbours)| {
+            let agreeing: usize = neighbours.iter().filter(|&&(j, _, _)| labels[j] == labels[i]).map(|&(_, m, _)| m).sum();
+            self.multiplicity(i) as f64 * agreeing as f64 / k as f64
+        }).sum();
+        Some(total / n as f64)
+    }
 
-        let train: Vec<Vec<f64>> = vec![
-            vec![0.0, 1.0, 2.0, 2.0, 3.0],
-            vec![5.0, 4.0, 3.0, 4.0, 5.0],
-            vec![0.0, 0.0, 0.0, 0.0, 0.0],
-        ];
+    /// Find the training points in regions where classes overlap: those
+    /// whose k nearest other training points include one of a different
+    /// class, as in `neighborhood_consistency()`. They trace the fuzzy
+    /// boundaries between classes, where predictions are least certain.
+    /// Returns their indices in increasing order; these refer to the
+    /// deduplicated points if deduplicating. Return None if called before
+    /// `fit()`.
+    ///
+    /// # Panics
+    ///
+    /// If there are no more training points than k.
+    pub fn overlap_points(&self) -> Option<Vec<usize>> {
+        let labels = self.labels.as_ref()?;
+        let neighbourhoods = self.neighbours_of_training_points()?;
+        Some(neighbourhoods.iter().enumerate()
+            .filter(|(i, neighbours)| neighbours.iter().any(|&(j, _, _)| labels[j] != labels[*i]))
+            .map(|(i, _)| i)
+            .collect())
+    }
 
-        let labels: Vec<String> = vec![
-            "good".to_string(),
-            "bad".to_string(),
-            "good".to_string(),
-        ];
+    /// Derive a distance beyond which a datapoint counts as novel, for
+    /// `predict_one_reject_far()`: the `percentile` quantile, in [0, 1], of
+    /// the training points' mean distances to their k nearest other
+    /// training points. With 0.99, say, about 1% of the training points
+    /// themselves would be rejected. Uses the k given at construction.
+    /// Return None if called before `fit()`.
+    ///
+    /// # Panics
+    ///
+    /// If there are no more training points than k.
+    pub fn novelty_threshold(&self, percentile: f64) -> Option<f64> {
+        let neighbourhoods = self.neighbours_of_training_points()?;
+        let distances: Vec<f64> = neighbourhoods.iter().enumerate().flat_map(|(i, neighbours)| {
+            std::iter::repeat_n(mean_distance(neighbours), self.multiplicity(i))
+        }).collect();
+        Some(quantile(&distances, percentile))
+    }
 
-        clf.fit(train.clone(), labels.clone());
+    /// Find each training point's k nearest other training points, as
+    /// (row, occurrences, distance) triples, closest first. Each point is
+    /// left out of its own neighbourhood, but its duplicates are not.
+    fn neighbours_of_training_points(&self) -> Option<Vec<Neighbourhood>> {
+        let train = self.train()?;
+        let (k, metric, multiplicity) = (self.k, self.metric, self.multiplicity.as_deref());
+        let n: usize = (0..train.len()).map(|i| self.multiplicity(i)).sum();
+        assert!(k < n, "need more than k training points");
+        let rows: Vec<usize> = (0..train.len()).collect();
+        Some(parallel::map_rows(&rows, |&i| {
+            let mut remaining = k;
+            let mut neighbours = Vec::with_capacity(k);
+            for (j, d) in nearest(train, metric, k + 1, &train.row(i)) {
+                let m = (multiplicity.map_or(1, |m| m[j]) - (j == i) as usize).min(remaining);
+                neighbours.push((j, m, d));
+                remaining -= m;
+                if remaining == 0 {
+                    break;
+                }
+            }
+            neighbours
+        }))
+    }
 
-        let test = vec![
-            vec![1.0, 1.0, 1.0, 1.0, 1.0],
-        ];
+    /// Compress the training data to one byte per feature, cutting its
+    /// memory roughly eightfold. Each feature's training range is split
+    /// into 256 evenly spaced levels and every value is rounded to the
+    /// nearest one; distances are then computed against these reconstructed
+    /// values. The rounding error is at most 1/510 of the feature's range,
+    /// so it only changes predictions where neighbours are about that close
+    /// to tied, but features with a few extreme outliers lose most of their
+    /// resolution. Query points are not quantized. The original data is
+    /// dropped, and a new `fit()` stores full-precision data again. With a
+    /// transform matrix, distances use the transformed points, which stay
+    /// at full precision, so quantizing saves no search time. Does nothing
+    /// if called before `fit()` or if already quantized.
+    ///
+    /// # Panics
+    ///
+    /// If the training rows differ in length.
+    pub fn quantize_u8(&mut self) {
+        if let Some(data) = self.data.take() {
+            self.quantized = Some(Quantized::new(&data));
+        }
+    }
 
-        let pred = clf.predict(&test).unwrap();
-        
-        assert_eq!(pred[0], "good".to_string());
+    /// Enlarge the training set with `copies` noisy variants of every
+    /// training point, each feature shifted by independent Gaussian noise
+    /// of standard deviation `sigma`, and labeled like the original. The
+    /// class frequencies, and so the priors, stay the same. Quantized data
+    /// is augmented from its reconstructed values and quantized again.
+    /// Return None if called before `fit()`.
+    pub fn augment_gaussian(&mut self, sigma: f64, copies: usize, random_state: Option<RandomState>) -> Option<()> {
+        assert!(sigma >= 0.0, "sigma must be non-negative");
+        let requantize = self.data.is_none();
+        if let Some(q) = self.quantized.take() {
+            self.data = Some((0..q.len).map(|i| Rows { storage: Storage::Quantized(&q), ..Rows::dense(&[]) }.row(i).into_owned()).collect());
+        }
+        let data = self.data.as_mut()?;
+        let labels = self.labels.as_mut()?;
+        let mut state = RandomState::resolve(random_state);
+        let rng = state.rng();
+        let n = data.len();
+        data.reserve(n * copies);
+        labels.reserve(n * copies);
+        for _ in 0..copies {
+            for i in 0..n {
+                let noisy = data[i].iter().map(|v| v + sigma * rng.next_gaussian()).collect();
+                data.push(noisy);
+                labels.push(labels[i].clone());
+            }
+        }
+        if let Some(multiplicity) = self.multiplicity.as_mut() {
+            let original = multiplicity.clone();
+            for _ in 0..copies {
+                multiplicity.extend_from_slice(&original);
+            }
+        }
+        self.centroids = class_centroids(&self.classes, data, labels, self.multiplicity.as_deref());
+        self.projected = self.transform.as_ref().map(|m| data.iter().map(|x| matvec(m, x)).collect());
+        if requantize {
+            self.quantize_u8();
+        }
+        Some(())
+    }
+
+    /// Estimate the heap memory taken by the stored training points, in
+    /// bytes. Return None if called before `fit()`.
+    pub fn memory_bytes(&self) -> Option<usize> {
+        let dense = |data: &[Vec<f64>]| -> usize {
+            data.iter().map(|x| mem::size_of::<Vec<f64>>() + x.len() * mem::size_of::<f64>()).sum()
+        };
+        let stored = match self.train()?.storage {
+            Storage::Dense(data)  => dense(data),
+            Storage::Quantized(q) => q.codes.len() + (q.mins.len() + q.steps.len()) * mem::size_of::<f64>(),
+        };
+        Some(stored + self.projected.as_deref().map_or(0, dense))
+    }
+
+    /// Estimate the cost of predicting `n_queries` datapoints as the number
+    /// of per-feature comparisons a brute-force search makes, i.e.
+    /// `n_queries * n_train * dim`. Return None if called before `fit()`.
+    pub fn estimated_distance_ops(&self, n_queries: usize) -> Option<u64> {
+        let train = self.train()?;
+        let dim = train.dim().unwrap_or(0);
+        Some(n_queries as u64 * train.len() as u64 * dim as u64)
+    }
+
+    /// Train the classifier as in `fit()`, ignoring the features whose
+    /// entry in `mask`, if given, is false.
+    fn fit_masked(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>, mask: Option<&[bool]>) {
+        let n = labels.len() as f64;
+        let ctr = Counter::with_iterator(labels.iter().cloned());
+        self.priors = ctr.iter().map(|(label, &count)| (label.clone(), count as f64 / n)).collect();
+        self.classes.clear();
+        for label in &labels {
+            if !self.classes.contains(label) {
+                self.classes.push(label.clone());
+            }
+        }
+        self.centroids = class_centroids(&self.classes, &data, &labels, None);
+        self.means = column_means(&data);
+        if self.auto_feature_weights || self.standardize || mask.is_some() {
+            assert!(!self.metric.allows_varying_length(), "feature weights need fixed-length examples");
+            assert!(self.transform.is_none(), "feature weights can't be combined with a transform matrix");
+        }
+        if let Some(m) = &self.transform {
+            assert!(data.iter().all(|x| x.len() == m[0].len()), "transform matrix columns and examples differ in length");
+        }
+        let stats = column_stats(&data);
+        self.stds = if self.standardize {
+            Some(stats.iter().map(|s| if s.variance() > 0.0 { s.variance().sqrt() } else { 1.0 }).collect())
+        } else {
+            None
+        };
+        self.feature_weights = match (self.auto_feature_weights, &self.stds) {
+            (true, _)           => Some(inverse_variance_weights(&stats)),
+            (false, Some(stds)) => Some(stds.iter().map(|s| 1.0 / (s * s)).collect()),
+            (false, None)       => None,
+        };
+        if let Some(mask) = mask {
+            assert!(data.iter().all(|x| x.len() == mask.len()), "feature mask and examples differ in length");
+            let mut weights = self.feature_weights.take().unwrap_or_else(|| vec![1.0; mask.len()]);
+            for (w, &keep) in weights.iter_mut().zip(mask.iter()) {
+                if !keep {
+                    *w = 0.0;
+                }
+            }
+            if self.auto_feature_weights {
+                let total: f64 = weights.iter().sum();
+                if total > 0.0 {
+                    weights.iter_mut().for_each(|w| *w /= total);
+                }
+            }
+            self.feature_weights = Some(weights);
+        }
+        let (data, labels) = if self.deduplicate {
+            let (data, labels, multiplicity) = deduplicate(data, labels);
+            self.multiplicity = Some(multiplicity);
+            (data, labels)
+        } else {
+            self.multiplicity = None;
+            (data, labels)
+        };
+        self.projected = self.transform.as_ref().map(|m| data.iter().map(|x| matvec(m, x)).collect());
+        self.data = Some(data);
+        self.quantized = None;
+        self.labels = Some(labels);
+    }
+
+    /// The training points, however they are stored. Return None if called
+    /// before `fit()`.
+    fn train(&self) -> Option<Rows<'_>> {
+        let storage = match (&self.data, &self.quantized) {
+            (Some(data), _) => Storage::Dense(data),
+            (None, Some(q)) => Storage::Quantized(q),
+            (None, None)    => return None,
+        };
+        Some(Rows {
+            storage,
+            weights: self.feature_weights.as_deref(),
+            transform: self.transform.as_deref(),
+            projected: self.projected.as_deref(),
+        })
+    }
+
+    /// Compute the distance between two datapoints as the neighbour
+    /// searches do.
+    fn pair_distance(&self, x: &[f64], y: &[f64]) -> f64 {
+        match &self.transform {
+            Some(m) => weighted_distance(self.metric, self.feature_weights.as_deref(), &matvec(m, x), &matvec(m, y)),
+            None    => weighted_distance(self.metric, self.feature_weights.as_deref(), x, y),
+        }
+    }
+
+    /// Whether a batch of `n` datapoints is predicted on several threads.
+    fn runs_parallel(&self, n: usize) -> bool {
+        n > self.parallel_threshold
+    }
+
+    /// The number of neighbours to search for: the largest k of any class.
+    fn search_k(&self) -> usize {
+        self.class_k.values().cloned().fold(self.k, usize::max)
+    }
+
+    /// Get the fraction of `label`'s k nearest neighbours, among the given
+    /// ones, that carry it, weighting them by a Gaussian kernel of the given
+    /// bandwidth if any.
+    fn label_fraction(&self, neighbours: &[(usize, f64)], label: &T, bandwidth: Option<f64>) -> f64 {
+        let labels = match self.labels {
+            Some(ref labels) => labels,
+            None             => panic!("Empty labels after training"),
+        };
+        let k = self.class_k.get(label).cloned().unwrap_or(self.k);
+        // Gaussian weights relative to the nearest neighbour's, which only
+        // rescales them all but keeps them from underflowing to zero far
+        // from the training data.
+        let d0 = neighbours.first().map_or(0.0, |&(_, d)| d);
+        let weight = |d: f64| match bandwidth {
+            Some(sigma) => (-(d * d - d0 * d0) / (2.0 * sigma * sigma)).exp(),
+            None        => 1.0,
+        };
+        // A deduplicated row stands for several training points; the last
+        // row within the k nearest points may count only in part.
+        let (mut own, mut total, mut remaining) = (0f64, 0f64, k);
+        for &(j, d) in neighbours {
+            if remaining == 0 {
+                break;
+            }
+            let n = self.multiplicity(j).min(remaining);
+            remaining -= n;
+            let w = weight(d) * n as f64;
+            total += w;
+            if labels[j] == *label {
+                own += w;
+            }
+        }
+        match bandwidth {
+            None                  => own / k as f64,
+            Some(_) if total > 0.0 => own / total,
+            Some(_)               => 0.0,
+        }
+    }
+
+    /// Turn the vote scores of the given neighbours into class
+    /// probabilities, as in `predict_proba()`.
+    fn proba(&self, neighbours: &[(usize, f64)]) -> Vec<(T, f64)> {
+        let mut scores: Vec<(T, f64)> = self.classes.iter().map(|class| {
+            let mut score = self.label_fraction(neighbours, class, self.bandwidth);
+            if self.prior_correction {
+                score /= self.priors[class];
+            }
+            (class.clone(), score)
+        }).collect();
+        let total: f64 = scores.iter().map(|&(_, score)| score).sum();
+        for entry in scores.iter_mut() {
+            entry.1 /= total;
+        }
+        scores
+    }
+
+    /// The number of training points that stored row `i` stands for.
+    fn multiplicity(&self, i: usize) -> usize {
+        self.multiplicity.as_ref().map_or(1, |m| m[i])
+    }
+
+    /// Take the first `k` training points among the given neighbours, as
+    /// (row, occurrences, distance) triples. A deduplicated row stands for
+    /// several points, and the last row taken may count only in part.
+    fn first_points(&self, neighbours: &[(usize, f64)], k: usize) -> Neighbourhood {
+        let mut remaining = k;
+        let mut points = Vec::with_capacity(k.min(neighbours.len()));
+        for &(j, d) in neighbours {
+            if remaining == 0 {
+                break;
+            }
+            let m = self.multiplicity(j).min(remaining);
+            points.push((j, m, d));
+            remaining -= m;
+        }
+        points
+    }
+
+    /// Check that `x` has as many features as the training data, for metrics
+    /// that need equal lengths.
+    fn dimension_matches(&self, x: &[f64]) -> bool {
+        if self.metric.allows_varying_length() {
+            return true;
+        }
+        match self.train().and_then(|train| train.dim()) {
+            Some(dim) => dim == x.len(),
+            None      => true,
+        }
+    }
+
+    /// Tally the labels of the given neighbours and return the most frequent
+    /// one, counting each label only among its own k nearest neighbours.
+    /// Ties go to the label of the nearest neighbour among the tied labels.
+    /// This doesn't allocate, which keeps `predict_into()` allocation-free.
+    fn vote(&self, neighbours: &[(usize, f64)]) -> &T {
+        self.vote_with(neighbours, self.bandwidth)
+    }
+
+    /// Vote as in `vote()`, with the given bandwidth.
+    fn vote_with(&self, neighbours: &[(usize, f64)], bandwidth: Option<f64>) -> &T {
+        let labels = match self.labels {
+            Some(ref labels) => labels,
+            None             => panic!("Empty labels after training"),
+        };
+        let mut best = None;
+        let mut best_score = 0f64;
+        for (i, &(idx, _)) in neighbours.iter().enumerate() {
+            let label = &labels[idx];
+            // Score each label once, at its nearest occurrence.
+            if neighbours[..i].iter().any(|&(j, _)| labels[j] == *label) {
+                continue;
+            }
+            let mut score = self.label_fraction(neighbours, label, bandwidth);
+            if self.prior_correction {
+                score /= self.priors[label];
+            }
+            if best.is_none() || score > best_score {
+                best = Some(label);
+                best_score = score;
+            }
+        }
+        best.unwrap()
+    }
+}
+
+/// Collapse identical (row, label) pairs into one, keeping the first
+/// occurrence's position, and count how often each occurred.
+fn deduplicate<T: Hash + Eq + Clone>(data: Vec<Vec<f64>>, labels: Vec<T>) -> (Vec<Vec<f64>>, Vec<T>, Vec<usize>) {
+    let mut index: HashMap<(Vec<u64>, T), usize> = HashMap::new();
+    let (mut unique_data, mut unique_labels, mut counts) = (Vec::new(), Vec::new(), Vec::new());
+    for (x, label) in data.into_iter().zip(labels) {
+        let key = (x.iter().map(|v| v.to_bits()).collect(), label.clone());
+        match index.get(&key) {
+            Some(&i) => counts[i] += 1,
+            None     => {
+                index.insert(key, unique_data.len());
+                unique_data.push(x);
+                unique_labels.push(label);
+                counts.push(1);
+            },
+        }
+    }
+    (unique_data, unique_labels, counts)
+}
+
+/// Compute the mean of each class's rows, in the order of `classes`,
+/// counting each row as often as its multiplicity, if given.
+fn class_centroids<T: PartialEq>(classes: &[T], data: &[Vec<f64>], labels: &[T], multiplicity: Option<&[usize]>) -> Vec<Vec<f64>> {
+    let dim = data.first().map_or(0, |x| x.len());
+    let mut sums = vec![vec![0f64; dim]; classes.len()];
+    let mut counts = vec![0usize; classes.len()];
+    for (i, (x, label)) in data.iter().zip(labels.iter()).enumerate() {
+        let c = classes.iter().position(|class| class == label).expect("label missing from classes");
+        let m = multiplicity.map_or(1, |m| m[i]);
+        for (s, v) in sums[c].iter_mut().zip(x.iter()) {
+            *s += v * m as f64;
+        }
+        counts[c] += m;
+    }
+    for (sum, &count) in sums.iter_mut().zip(counts.iter()) {
+        for s in sum.iter_mut() {
+            *s /= count as f64;
+        }
+    }
+    sums
+}
+
+/// Get the probability of the most likely class minus that of the
+/// runner-up, or of nothing if there is only one class.
+fn vote_margin<T>(proba: &[(T, f64)]) -> f64 {
+    let (mut first, mut second) = (0f64, 0f64);
+    for &(_, p) in proba {
+        if p > first {
+            second = first;
+            first = p;
+        } else if p > second {
+            second = p;
+        }
+    }
+    first - second
+}
+
+/// Get the mean distance to the training points of a neighbourhood,
+/// counting each row as many times as it occurs, as `novelty_threshold()`
+/// and `predict_one_reject_far()` both measure it.
+fn mean_distance(neighbours: &[(usize, usize, f64)]) -> f64 {
+    let (total, n) = neighbours.iter().fold((0f64, 0usize), |(total, n), &(_, m, d)| (total + m as f64 * d, n + m));
+    total / n.max(1) as f64
+}
+
+/// Find the `k` rows of `data` nearest to `x` under `metric` and store them
+/// in `best` as (index, distance) pairs, closest first.
+fn nearest_into(data: Rows, metric: Metric, k: usize, x: &[f64], best: &mut Vec<(usize, f64)>) {
+    best.clear();
+    best.reserve(k + 1);
+    let x = &*data.project(x);
+    for i in 0..data.len() {
+        let dist = data.distance(metric, x, i);
+        if best.len() == k && best.last().is_none_or(|&(_, d)| dist >= d) {
+            continue;
+        }
+        let pos = best.iter().position(|&(_, d)| dist < d).unwrap_or(best.len());
+        best.insert(pos, (i, dist));
+        best.truncate(k);
+    }
+}
+
+/// Find the `k` rows of `data` nearest to `x` under `metric` as (index,
+/// distance) pairs, closest first. This is the brute-force search behind
+/// `KNNClassifier`, for other neighbour-based models.
+pub(crate) fn nearest_rows(data: &[Vec<f64>], metric: Metric, k: usize, x: &[f64]) -> Vec<(usize, f64)> {
+    nearest(Rows::dense(data), metric, k, x)
+}
+
+/// Find the `k` rows of `data` nearest to `x` under `metric` as (index,
+/// distance) pairs, closest first.
+fn nearest(data: Rows, metric: Metric, k: usize, x: &[f64]) -> Vec<(usize, f64)> {
+    let mut best = Vec::with_capacity(k + 1);
+    nearest_into(data, metric, k, x, &mut best);
+    best
+}
+
+impl<T> Classifier for KNNClassifier<T> where T: Hash + Eq + Clone {
+    type ExampleType = Vec<f64>;
+    type LabelType = T;
+
+    /// Train the classifier with examples and their labels. A KNN classifier
+    /// doesn't actually do anything in the training phase, which is why it has
+    /// been called a "lazy learner".
+    fn fit(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>) {
+        self.fit_masked(data, labels, None);
+    }
+
+    /// Predict the labels of datapoints. Return None if `predict()` is
+    /// called before `fit()`.
+    fn predict(&self, data: &[Vec<f64>]) -> Option<Vec<T>> {
+        self.predict_ref(data).map(|preds| preds.into_iter().cloned().collect())
+    }
+
+    /// Predict the label for one datapoint. Return None if `predict_one()`
+    /// is called before `fit()`.
+    fn predict_one(&self, x: &Vec<f64>) -> Option<T> {
+        self.predict_one_ref(x).cloned()
+    }
+}
+
+impl<T> ProbabilisticClassifier for KNNClassifier<T> where T: Hash + Eq + Clone {
+    /// Estimate class probabilities for one datapoint from its neighbours'
+    /// votes, scored as in `predict_one()` and normalized to sum to one.
+    /// Every training class is listed, in order of first appearance in the
+    /// training labels. Return None if called before `fit()`.
+    fn predict_proba(&self, x: &Vec<f64>) -> Option<Vec<(T, f64)>> {
+        let neighbours = self.kneighbors(x)?;
+        Some(self.proba(&neighbours))
+    }
+}
+
+/// A K-Nearest Neighbours regressor: predicts the mean target of the k
+/// training points nearest to a datapoint.
+#[derive(Debug, Clone)]
+pub struct KNNRegressor {
+    k: usize,
+    metric: Metric,
+    data: Option<Vec<Vec<f64>>>,
+    targets: Option<Vec<f64>>,
+}
+
+impl KNNRegressor {
+    /// Construct a new KNNRegressor using the Euclidean distance.
+    pub fn new(k: usize) -> KNNRegressor {
+        KNNRegressor::with_metric(k, Metric::Euclidean)
+    }
+
+    /// Construct a new KNNRegressor that compares examples using `metric`.
+    pub fn with_metric(k: usize, metric: Metric) -> KNNRegressor {
+        KNNRegressor { k, metric, data: None, targets: None }
+    }
+
+    /// Store the training examples and their targets.
+    pub fn fit(&mut self, data: Vec<Vec<f64>>, targets: Vec<f64>) {
+        assert_eq!(data.len(), targets.len(), "data and targets differ in length");
+        self.data = Some(data);
+        self.targets = Some(targets);
+    }
+
+    /// Predict the targets of datapoints. Return None if called before
+    /// `fit()`.
+    pub fn predict(&self, data: &[Vec<f64>]) -> Option<Vec<f64>> {
+        data.iter().map(|x| self.predict_one(x)).collect()
+    }
+
+    /// Predict the target of one datapoint. Return None if called before
+    /// `fit()`.
+    pub fn predict_one(&self, x: &[f64]) -> Option<f64> {
+        let targets = self.neighbour_targets(x)?;
+        Some(targets.iter().sum::<f64>() / targets.len() as f64)
+    }
+
+    /// Predict the target of one datapoint as the mean of its neighbours'
+    /// targets, along with the `quantiles.0` and `quantiles.1` quantiles of
+    /// those targets as a (mean, lower, upper) triple. The interval is wide
+    /// where the neighbours disagree, as in sparse or noisy regions, but
+    /// with only k targets behind it the extreme quantiles are rough.
+    /// Return None if called before `fit()`.
+    pub fn predict_one_interval(&self, x: &[f64], quantiles: (f64, f64)) -> Option<(f64, f64, f64)> {
+        assert!(quantiles.0 <= quantiles.1, "lower quantile exceeds upper quantile");
+        let targets = self.neighbour_targets(x)?;
+        let mean = targets.iter().sum::<f64>() / targets.len() as f64;
+        Some((mean, quantile(&targets, quantiles.0), quantile(&targets, quantiles.1)))
+    }
+
+    /// Get the targets of the k training points nearest to `x`.
+    fn neighbour_targets(&self, x: &[f64]) -> Option<Vec<f64>> {
+        let data = self.data.as_ref()?;
+        let targets = self.targets.as_ref()?;
+        let neighbours = nearest_rows(data, self.metric, self.k, x);
+        Some(neighbours.iter().map(|&(i, _)| targets[i]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Classifier;
+
+    #[test]
+    fn test_creation() {
+        let clf: KNNClassifier<String> = KNNClassifier::new(3);
+        assert_eq!(clf.data, None);
+        assert_eq!(clf.labels, None);
+    }
+
+    #[test]
+    fn test_fit() {
+        let mut clf: KNNClassifier<String> = KNNClassifier::new(3);
+
+        let train: Vec<Vec<f64>> = vec![
+            vec![0.0, 1.0, 2.0, 2.0, 3.0],
+            vec![5.0, 4.0, 3.0, 4.0, 5.0],
+        ];
+
+        let labels: Vec<String> = vec![
+            "good".to_string(),
+            "bad".to_string(),
+        ];
+
+        clf.fit(train.clone(), labels.clone());
+
+        assert_eq!(clf.data, Some(train));
+        assert_eq!(clf.labels, Some(labels));
+    }
+
+    #[test]
+    fn test_predict() {
+        let mut clf = KNNClassifier::new(1);
+
+        let train: Vec<Vec<f64>> = vec![
+            vec![0.0, 1.0, 2.0, 2.0, 3.0],
+            vec![5.0, 4.0, 3.0, 4.0, 5.0],
+            vec![0.0, 0.0, 0.0, 0.0, 0.0],
+        ];
+
+        let labels: Vec<String> = vec![
+            "good".to_string(),
+            "bad".to_string(),
+            "good".to_string(),
+        ];
+
+        clf.fit(train.clone(), labels.clone());
+
+        let test = vec![
+            vec![1.0, 1.0, 1.0, 1.0, 1.0],
+        ];
+
+        let pred = clf.predict(&test).unwrap();
+        
+        assert_eq!(pred[0], "good".to_string());
+    }
+
+    #[test]
+    fn test_predict_levenshtein() {
+        let mut clf = KNNClassifier::with_metric(1, Metric::Levenshtein);
+
+        // Event logs of differing length.
+        let train: Vec<Vec<f64>> = vec![
+            vec![1.0, 2.0, 3.0],
+            vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+            vec![9.0, 9.0],
+        ];
+
+        let labels = vec!["short", "long", "odd"];
+
+        clf.fit(train, labels);
+
+        let test = vec![
+            vec![1.0, 2.0, 3.0, 4.0, 5.0],
+            vec![1.0, 2.0],
+            vec![9.0],
+        ];
+
+        assert_eq!(clf.predict(&test).unwrap(), vec!["long", "short", "odd"]);
+    }
+
+    #[test]
+    fn test_predict_ref() {
+        let mut clf = KNNClassifier::new(3);
+
+        let train: Vec<Vec<f64>> = vec![
+            vec![0.0, 0.0],
+            vec![0.0, 1.0],
+            vec![1.0, 0.0],
+            vec![9.0, 9.0],
+            vec![9.0, 8.0],
+            vec![8.0, 9.0],
+        ];
+
+        let labels: Vec<String> = vec![
+            "near".to_string(),
+            "near".to_string(),
+            "near".to_string(),
+            "far".to_string(),
+            "far".to_string(),
+            "far".to_string(),
+        ];
+
+        assert_eq!(clf.predict_ref(&train), None);
+        clf.fit(train, labels);
+
+        let test = vec![
+            vec![0.5, 0.5],
+            vec![8.5, 8.5],
+        ];
+
+        let refs = clf.predict_ref(&test).unwrap();
+        let owned = clf.predict(&test).unwrap();
+        let first = clf.predict_one_ref(&test[0]).unwrap();
+
+        // The references stay usable across further immutable calls.
+        assert_eq!(clf.predict_one(&test[1]).unwrap(), *refs[1]);
+        assert_eq!(refs.len(), owned.len());
+        for (r, o) in refs.iter().zip(owned.iter()) {
+            assert_eq!(*r, o);
+        }
+        assert_eq!(first, refs[0]);
+        assert_eq!(first, "near");
+    }
+
+    #[test]
+    fn test_predict_into() {
+        let mut clf = KNNClassifier::new(1);
+        let mut out = vec!["stale"];
+
+        assert_eq!(clf.predict_into(&[vec![0.0, 0.0]], &mut out), None);
+        assert!(out.is_empty());
+
+        clf.fit(vec![vec![0.0, 0.0], vec![5.0, 5.0]], vec!["low", "high"]);
+
+        let test = vec![vec![4.0, 4.0], vec![1.0, 0.0]];
+        assert_eq!(clf.predict_into(&test, &mut out), Some(()));
+        assert_eq!(out, clf.predict(&test).unwrap());
+
+        // A dimension mismatch anywhere in the batch clears `out`.
+        let bad = vec![vec![4.0, 4.0], vec![1.0, 0.0, 3.0]];
+        assert_eq!(clf.predict_into(&bad, &mut out), None);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_kneighbors_into() {
+        let mut clf = KNNClassifier::new(2);
+        clf.fit(vec![vec![0.0], vec![3.0], vec![1.0]], vec![0, 1, 0]);
+
+        let mut indices = vec![42];
+        let mut distances = Vec::new();
+        assert_eq!(clf.kneighbors_into(&[0.4], &mut indices, &mut distances), Some(()));
+        assert_eq!(indices, vec![0, 2]);
+        assert_eq!(distances, vec![0.4, 0.6]);
+        let expected: Vec<(usize, f64)> = indices.iter().cloned().zip(distances.iter().cloned()).collect();
+        assert_eq!(clf.kneighbors(&[0.4]).unwrap(), expected);
+
+        assert_eq!(clf.kneighbors_into(&[0.4, 1.0], &mut indices, &mut distances), None);
+        assert!(indices.is_empty());
+        assert!(distances.is_empty());
+    }
+
+    #[test]
+    fn test_unused_training_points() {
+        let mut clf = KNNClassifier::new(2);
+        assert_eq!(clf.unused_training_points(&[]), None);
+
+        let train: Vec<Vec<f64>> = vec![
+            vec![0.0, 0.0],
+            vec![1.0, 0.0],
+            vec![100.0, 100.0],
+            vec![0.0, 1.0],
+        ];
+        clf.fit(train, vec![0, 0, 1, 1]);
+
+        let val = vec![vec![0.1, 0.1], vec![0.9, 0.2], vec![0.2, 0.8]];
+        assert_eq!(clf.unused_training_points(&val), Some(vec![2]));
+        assert_eq!(clf.unused_training_points(&[]), Some(vec![0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn test_estimated_distance_ops() {
+        let mut clf = KNNClassifier::new(3);
+        assert_eq!(clf.estimated_distance_ops(10), None);
+
+        let train: Vec<Vec<f64>> = (0..120).map(|i| vec![i as f64; 4]).collect();
+        let labels: Vec<usize> = (0..120).map(|i| i % 2).collect();
+        clf.fit(train, labels);
+
+        assert_eq!(clf.estimated_distance_ops(25), Some(25 * 120 * 4));
+        assert_eq!(clf.estimated_distance_ops(0), Some(0));
+    }
+
+    #[test]
+    fn test_predict_one_purity() {
+        let mut clf = KNNClassifier::new(4);
+        assert_eq!(clf.predict_one_purity(&[0.0]), None);
+
+        let train: Vec<Vec<f64>> = vec![
+            vec![0.0], vec![0.5], vec![1.0], vec![1.5], vec![2.0],
+            vec![3.0], vec![3.5], vec![4.0], vec![4.5], vec![5.0],
+        ];
+        let labels = vec!["a", "a", "a", "a", "a", "b", "b", "b", "b", "b"];
+        clf.fit(train, labels);
+
+        assert_eq!(clf.predict_one_purity(&[0.8]), Some(("a", 1.0)));
+        assert_eq!(clf.predict_one_purity(&[2.2]), Some(("a", 0.75)));
+    }
+
+    #[test]
+    fn test_purity_and_diversity_deduplicated() {
+        // The example from `set_deduplicate()`: A occurs twice, so with
+        // k = 3 it outvotes B and C, and is two of the three neighbours.
+        let train = vec![vec![0.0], vec![0.0], vec![1.0], vec![1.5], vec![4.0], vec![4.0], vec![4.2]];
+        let labels = vec!["a", "a", "b", "b", "b", "b", "a"];
+        let mut plain = KNNClassifier::new(3);
+        plain.fit(train.clone(), labels.clone());
+        let mut dedup = plain.clone();
+        dedup.set_deduplicate(true);
+        dedup.fit(train, labels);
+        assert_eq!(dedup.data.as_ref().unwrap().len(), 5);
+
+        let purity = dedup.predict_one_purity(&[0.1]).unwrap();
+        assert_eq!(purity.0, "a");
+        assert!((purity.1 - 2.0 / 3.0).abs() < 1e-12, "{}", purity.1);
+        for x in &[[0.1], [0.9], [2.5], [4.1]] {
+            assert_eq!(dedup.predict_one_purity(x), plain.predict_one_purity(x));
+            let (a, b) = (dedup.predict_one_diversity(x).unwrap(), plain.predict_one_diversity(x).unwrap());
+            assert!((a - b).abs() < 1e-12, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_decision_function() {
+        let mut clf = KNNClassifier::new(4);
+        assert_eq!(clf.decision_function(&[vec![0.0]], &"a"), None);
+
+        let train: Vec<Vec<f64>> = vec![
+            vec![0.0], vec![0.5], vec![1.0], vec![1.5], vec![2.0],
+            vec![3.0], vec![3.5], vec![4.0], vec![4.5], vec![5.0],
+        ];
+        let labels = vec!["a", "a", "a", "a", "a", "b", "b", "b", "b", "b"];
+        clf.fit(train, labels);
+
+        let test = vec![vec![0.8], vec![2.2], vec![2.6], vec![4.9]];
+        let scores = clf.decision_function(&test, &"b").unwrap();
+        let one_by_one: Vec<f64> = test.iter().map(|x| clf.decision_function_one(x, &"b").unwrap()).collect();
+        assert_eq!(scores, one_by_one);
+        assert_eq!(scores, vec![0.0, 0.25, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn test_intra_class_nn_distances() {
+        let mut clf = KNNClassifier::new(1);
+        assert_eq!(clf.intra_class_nn_distances(), None);
+
+        let train: Vec<Vec<f64>> = vec![
+            vec![0.0, 0.0], vec![10.0, 0.0], vec![3.0, 4.0], vec![0.0, 1.0], vec![10.0, 2.0], vec![50.0, 50.0],
+        ];
+        clf.fit(train, vec!["a", "b", "a", "a", "b", "c"]);
+        let distances = clf.intra_class_nn_distances().unwrap();
+
+        assert_eq!(distances.len(), 3);
+        let a = &distances["a"];
+        assert_eq!(a.len(), 3);
+        assert_eq!(a[0], 1.0);
+        assert!((a[1] - 18f64.sqrt()).abs() < 1e-12);
+        assert_eq!(a[2], 1.0);
+        assert_eq!(distances["b"], vec![2.0, 2.0]);
+        assert!(distances["c"].is_empty());
+    }
+
+    #[test]
+    fn test_predict_with_warnings() {
+        let mut clf = KNNClassifier::new(1);
+        assert_eq!(clf.predict_with_warnings(&[vec![0.0, 0.0, 0.0]]), None);
+
+        let train = vec![vec![0.0, 10.0, -1.0], vec![1.0, 20.0, 1.0], vec![0.5, 15.0, 0.0]];
+        clf.fit(train, vec!["a", "b", "a"]);
+        assert_eq!(clf.feature_ranges().unwrap(), vec![(0.0, 1.0), (10.0, 20.0), (-1.0, 1.0)]);
+
+        let test = vec![vec![0.2, 12.0, 0.0], vec![1.5, 20.0, -3.0], vec![1.0, 25.0, 1.0]];
+        assert_eq!(clf.predict_with_warnings(&test).unwrap(), vec![
+            ("a", vec![]),
+            ("b", vec![0, 2]),
+            ("b", vec![1]),
+        ]);
+    }
+
+    #[test]
+    fn test_per_class_k() {
+        let train: Vec<Vec<f64>> = vec![
+            vec![0.0], vec![1.0], vec![1.1], vec![1.2], vec![1.3], vec![1.4],
+        ];
+        let labels = vec!["tight", "loose", "loose", "tight", "tight", "tight"];
+
+        // With k = 5 the three "tight" neighbours outvote the two "loose" ones.
+        let mut plain = KNNClassifier::new(5);
+        plain.fit(train.clone(), labels.clone());
+        assert_eq!(plain.predict_one(&vec![0.95]).unwrap(), "tight");
+
+        // "loose" only looks at its 2 nearest neighbours, both of which are
+        // "loose", beating "tight" at 3/5.
+        let mut overrides = HashMap::new();
+        overrides.insert("loose", 2);
+        let mut clf = KNNClassifier::with_per_class_k(5, overrides);
+        clf.fit(train, labels);
+        assert_eq!(clf.kneighbors(&[0.95]).unwrap().len(), 5);
+        assert_eq!(clf.predict_one(&vec![0.95]).unwrap(), "loose");
+        // Far from the "loose" points, its nearest two neighbours don't help.
+        assert_eq!(clf.predict_one(&vec![1.5]).unwrap(), "tight");
+    }
+
+    #[test]
+    fn test_predict_proba() {
+        let mut clf = KNNClassifier::new(4);
+        assert_eq!(clf.predict_proba(&vec![0.0]), None);
+
+        let train: Vec<Vec<f64>> = vec![vec![0.0], vec![1.0], vec![2.0], vec![3.0], vec![9.0]];
+        clf.fit(train, vec!["b", "a", "a", "b", "c"]);
+
+        let proba = clf.predict_proba(&vec![1.2]).unwrap();
+        assert_eq!(proba, vec![("b", 0.5), ("a", 0.5), ("c", 0.0)]);
+        let proba = clf.predict_proba(&vec![5.5]).unwrap();
+        assert_eq!(proba, vec![("b", 0.25), ("a", 0.5), ("c", 0.25)]);
+    }
+
+    #[test]
+    fn test_prior_correction() {
+        let mut clf = KNNClassifier::new(5);
+
+        let train: Vec<Vec<f64>> = vec![
+            vec![0.0, 0.0],
+            vec![0.0, 0.5],
+            vec![0.5, 0.0],
+            vec![0.5, 0.5],
+            vec![-1.0, -1.0],
+            vec![-1.0, 0.0],
+            vec![0.0, -1.0],
+            vec![-1.0, -0.5],
+            vec![1.0, 1.0],
+            vec![1.2, 1.2],
+        ];
+
+        let mut labels = vec!["majority"; 8];
+        labels.extend(vec!["minority"; 2]);
+
+        clf.fit(train, labels);
+
+        // Two minority and three majority neighbours.
+        let x = vec![1.1, 1.1];
+        assert_eq!(clf.predict_one(&x).unwrap(), "majority");
+
+        clf.set_prior_correction(true);
+        assert_eq!(clf.predict_one(&x).unwrap(), "minority");
+        assert_eq!(clf.predict_one(&vec![-0.5, -0.5]).unwrap(), "majority");
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_predict_parallel_matches_serial() {
+        let mut clf = KNNClassifier::new(5);
+
+        let train: Vec<Vec<f64>> = (0..200).map(|i| {
+            let i = i as f64;
+            vec![(i * 0.11).sin() * 4.0, (i * 0.7).cos() * 4.0]
+        }).collect();
+        // Two classes and an odd k, so votes are never tied.
+        let labels: Vec<usize> = (0..200).map(|i| i % 2).collect();
+        clf.fit(train, labels);
+
+        let test: Vec<Vec<f64>> = (0..64).map(|i| {
+            let i = i as f64;
+            vec![(i * 0.29).cos() * 4.0, (i * 0.53).sin() * 4.0]
+        }).collect();
+
+        let parallel = clf.predict(&test).unwrap();
+        let serial: Vec<usize> = test.iter().map(|x| clf.predict_one(x).unwrap()).collect();
+        assert_eq!(parallel, serial);
+    }
+
+    #[test]
+    fn test_quantize_u8() {
+        // Three overlapping blobs in four dimensions.
+        let mut rng = crate::util::Rng::new(23);
+        let mut sample = |n: usize| -> (Vec<Vec<f64>>, Vec<usize>) {
+            let labels: Vec<usize> = (0..n).map(|_| rng.gen_range(3)).collect();
+            let data = labels.iter().map(|&l| {
+                (0..4).map(|j| (l * (j + 1)) as f64 + 3.0 * rng.next_f64()).collect()
+            }).collect();
+            (data, labels)
+        };
+        let (train, train_labels) = sample(600);
+        let (test, _) = sample(300);
+
+        let mut clf = KNNClassifier::new(5);
+        clf.quantize_u8();
+        assert_eq!(clf.memory_bytes(), None);
+        clf.fit(train.clone(), train_labels.clone());
+        let before = clf.predict(&test).unwrap();
+        let dense_bytes = clf.memory_bytes().unwrap();
+        let ranges = clf.feature_ranges().unwrap();
+
+        clf.quantize_u8();
+        assert_eq!(clf.data, None);
+        let after = clf.predict(&test).unwrap();
+        let agreeing = before.iter().zip(after.iter()).filter(|(a, b)| a == b).count();
+        assert!(agreeing as f64 / test.len() as f64 >= 0.95, "only {} of {} agree", agreeing, test.len());
+        assert!(clf.memory_bytes().unwrap() * 8 < dense_bytes);
+
+        // The extremes of each range are stored exactly, up to rounding.
+        for (&(lo, hi), &(qlo, qhi)) in ranges.iter().zip(clf.feature_ranges().unwrap().iter()) {
+            assert!((lo - qlo).abs() < 1e-9 && (hi - qhi).abs() < 1e-9);
+        }
+        let (mut indices, mut distances) = (Vec::new(), Vec::new());
+        clf.kneighbors_into(&test[0], &mut indices, &mut distances).unwrap();
+        assert_eq!(indices.len(), 5);
+        assert_eq!(clf.kneighbors_into(&[0.0], &mut indices, &mut distances), None);
+
+        clf.fit(train, train_labels);
+        assert_eq!(clf.predict(&test).unwrap(), before);
+    }
+
+    #[test]
+    fn test_tune_bandwidth() {
+        // Around each centre, the single nearest training point is noise of
+        // class 1, the next two are class 0 and the three farthest class 1.
+        // Only a moderate bandwidth gets the class 0 queries at the centres
+        // right: a narrow one follows the nearest point, a wide one the
+        // majority.
+        let mut train = Vec::new();
+        let mut labels = Vec::new();
+        for &c in &[0.0, 100.0, 200.0] {
+            for &(offset, label) in &[(0.9, 1), (-1.0, 0), (1.0, 0), (-3.0, 1), (3.0, 1), (3.1, 1)] {
+                train.push(vec![c + offset]);
+                labels.push(label);
+            }
+        }
+        let mut clf = KNNClassifier::new(6);
+        let val_data = vec![vec![0.0], vec![100.0], vec![200.0]];
+        let val_labels = vec![0, 0, 0];
+        assert_eq!(clf.tune_bandwidth(&val_data, &val_labels, &[1.0]), None);
+        clf.fit(train, labels);
+
+        let (sigma, accuracy) = clf.tune_bandwidth(&val_data, &val_labels, &[0.1, 1.0, 100.0]).unwrap();
+        assert_eq!((sigma, accuracy), (1.0, 1.0));
+        assert_eq!(clf.tune_bandwidth(&val_data, &val_labels, &[0.1, 100.0]).unwrap().1, 0.0);
+        assert_eq!(clf.predict(&val_data).unwrap(), vec![1, 1, 1]);
+
+        clf.set_bandwidth(Some(sigma));
+        assert_eq!(clf.predict(&val_data).unwrap(), val_labels);
+        let proba = clf.predict_proba(&val_data[0]).unwrap();
+        assert!((proba[0].1 + proba[1].1 - 1.0).abs() < 1e-12 && proba[1].1 > proba[0].1);
+    }
+
+    #[test]
+    fn test_regressor_interval() {
+        // y = x, sampled every 0.01 on [0, 1] but only every 10 on [10, 100].
+        let xs: Vec<f64> = (0..=100).map(|i| i as f64 / 100.0).chain((1..=10).map(|i| i as f64 * 10.0)).collect();
+        let mut reg = KNNRegressor::new(5);
+        assert_eq!(reg.predict_one(&[0.5]), None);
+        reg.fit(xs.iter().map(|&x| vec![x]).collect(), xs.clone());
+
+        let (mean, lo, hi) = reg.predict_one_interval(&[0.5], (0.1, 0.9)).unwrap();
+        assert!((mean - 0.5).abs() < 1e-12);
+        assert!(lo < mean && mean < hi && hi - lo < 0.05);
+        let (mean, lo, hi) = reg.predict_one_interval(&[50.0], (0.1, 0.9)).unwrap();
+        assert!((mean - 50.0).abs() < 1e-12);
+        assert!(hi - lo > 20.0);
+        assert_eq!(reg.predict(&[vec![0.5], vec![50.0]]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_augment_gaussian() {
+        let mut rng = crate::util::Rng::new(12);
+        let labels: Vec<usize> = (0..40).map(|i| i % 2).collect();
+        let train: Vec<Vec<f64>> = labels.iter().map(|&l| vec![l as f64 * 6.0 + rng.next_f64(), rng.next_f64()]).collect();
+        let mut clf = KNNClassifier::new(3);
+        assert_eq!(clf.augment_gaussian(0.3, 4, None), None);
+        clf.fit(train.clone(), labels.clone());
+
+        let mut replay = clf.clone();
+        clf.augment_gaussian(0.3, 4, Some(RandomState::new(1))).unwrap();
+        replay.augment_gaussian(0.3, 4, Some(RandomState::new(1))).unwrap();
+        assert_eq!(clf.data, replay.data);
+        let data = clf.data.as_ref().unwrap();
+        assert_eq!(data.len(), 40 * 5);
+        assert_eq!(&data[..40], &train[..]);
+        assert_eq!(clf.labels.as_ref().unwrap()[40..80], labels[..]);
+
+        let test = vec![vec![0.5, 0.5], vec![6.5, 0.5], vec![-0.5, 1.0], vec![7.0, 0.0]];
+        assert_eq!(clf.predict(&test).unwrap(), vec![0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_predict_one_with_thresholds() {
+        let train = vec![vec![0.0], vec![0.1], vec![0.2], vec![0.3], vec![0.4]];
+        let mut clf = KNNClassifier::new(5);
+        let mut thresholds = HashMap::new();
+        assert_eq!(clf.predict_one_with_thresholds(&[0.0], &thresholds), None);
+        clf.fit(train, vec!["a", "a", "a", "b", "b"]);
+
+        // Probabilities are 0.6 for "a" and 0.4 for "b".
+        assert_eq!(clf.predict_one_with_thresholds(&[0.0], &thresholds), Some("a"));
+        thresholds.insert("a", 0.1);
+        assert_eq!(clf.predict_one_with_thresholds(&[0.0], &thresholds), Some("a"));
+        thresholds.insert("a", 0.3);
+        assert_eq!(clf.predict_one_with_thresholds(&[0.0], &thresholds), Some("b"));
+        thresholds.insert("b", 0.2);
+        assert_eq!(clf.predict_one_with_thresholds(&[0.0], &thresholds), Some("a"));
+    }
+
+    #[test]
+    fn test_predict_one_centroid() {
+        let mut rng = crate::util::Rng::new(40);
+        let centres = [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)];
+        let labels: Vec<usize> = (0..90).map(|i| i % 3).collect();
+        let train: Vec<Vec<f64>> = labels.iter().map(|&l| {
+            vec![centres[l].0 + rng.next_f64() * 2.0 - 1.0, centres[l].1 + rng.next_f64() * 2.0 - 1.0]
+        }).collect();
+        let mut clf = KNNClassifier::new(3);
+        assert_eq!(clf.predict_one_centroid(&[0.0, 0.0]), None);
+        clf.fit(train, labels);
+
+        // Well inside the blobs, the approximation agrees with the search.
+        for (l, &(cx, cy)) in centres.iter().enumerate() {
+            for &(dx, dy) in &[(0.0, 0.0), (0.5, -0.5), (-0.8, 0.3)] {
+                let x = vec![cx + dx, cy + dy];
+                assert_eq!(clf.predict_one_centroid(&x), Some(l));
+                assert_eq!(clf.predict_one(&x), Some(l));
+            }
+        }
+
+        // A far-off class point pulls the nearest training points away from
+        // the nearest centroid: only the full search follows it.
+        clf.fit(vec![vec![0.0], vec![1.0], vec![2.0], vec![3.0], vec![100.0]], vec![0, 0, 1, 1, 1]);
+        assert_eq!(clf.predict_one(&vec![1.2]), Some(0));
+        assert_eq!(clf.predict_one_centroid(&[3.5]), Some(0));
+        assert_eq!(clf.predict_one(&vec![3.5]), Some(1));
+    }
+
+    #[test]
+    fn test_predict_one_partial() {
+        let mut rng = crate::util::Rng::new(14);
+        // The class shows in the first feature; the second is noise around 5.
+        let labels: Vec<usize> = (0..60).map(|i| i % 2).collect();
+        let train: Vec<Vec<f64>> = labels.iter().map(|&l| vec![l as f64 * 4.0 + rng.next_f64(), 4.5 + rng.next_f64()]).collect();
+        let mut clf = KNNClassifier::new(5);
+        assert_eq!(clf.predict_one_partial(&[Some(0.5), None]), None);
+        clf.fit(train, labels);
+
+        let mean = clf.means[1];
+        assert!((mean - 5.0).abs() < 0.2, "{}", mean);
+        for &v in &[0.3, 4.6] {
+            let partial = clf.predict_one_partial(&[Some(v), None]);
+            assert_eq!(partial, clf.predict_one(&vec![v, mean]));
+            assert_eq!(partial, Some((v > 2.0) as usize));
+        }
+        assert_eq!(clf.predict_one_partial(&[Some(0.3)]), None);
+    }
+
+    #[test]
+    fn test_prediction_stability() {
+        // Two classes split at x = 5.
+        let train: Vec<Vec<f64>> = (0..100).map(|i| vec![i as f64 * 0.1, (i % 7) as f64 * 0.1]).collect();
+        let labels: Vec<usize> = (0..100).map(|i| (i >= 50) as usize).collect();
+        let mut clf = KNNClassifier::new(3);
+        assert_eq!(clf.prediction_stability(&[1.0, 0.3], 0.2, 10, None), None);
+        clf.fit(train, labels);
+
+        let interior = clf.prediction_stability(&[1.0, 0.3], 0.2, 200, Some(RandomState::new(4))).unwrap();
+        let boundary = clf.prediction_stability(&[4.97, 0.3], 0.2, 200, Some(RandomState::new(4))).unwrap();
+        assert_eq!(interior, 1.0);
+        assert!(boundary > 0.2 && boundary < 0.8, "{}", boundary);
+        assert_eq!(clf.prediction_stability(&[4.97, 0.3], 0.2, 200, Some(RandomState::new(4))), Some(boundary));
+    }
+
+    #[test]
+    fn test_predict_with_progress() {
+        let mut rng = crate::util::Rng::new(9);
+        let train: Vec<Vec<f64>> = (0..50).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let labels: Vec<usize> = train.iter().map(|x| (x[0] > 0.5) as usize).collect();
+        let test: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let mut clf = KNNClassifier::new(3);
+        let mut calls = Vec::new();
+        assert_eq!(clf.predict_with_progress(&test, |done, total| calls.push((done, total))), None);
+        assert!(calls.is_empty());
+        clf.fit(train, labels);
+
+        let predictions = clf.predict_with_progress(&test, |done, total| calls.push((done, total))).unwrap();
+        assert_eq!(predictions, clf.predict(&test).unwrap());
+        assert_eq!(calls.len(), 4);
+        assert!(calls.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(calls.iter().all(|&(_, total)| total == 1000));
+        assert_eq!(calls.last(), Some(&(1000, 1000)));
+    }
+
+    #[test]
+    fn test_predict_one_multi() {
+        let train = vec![vec![0.0], vec![0.1], vec![1.0], vec![1.1], vec![5.0], vec![5.1], vec![5.2]];
+        let labels = vec!["a", "a", "b", "b", "c", "c", "c"];
+        let mut clf = KNNClassifier::new(4);
+        assert_eq!(clf.predict_one_multi(&[0.5]), None);
+        clf.fit(train, labels);
+
+        // The four nearest are two a's and two b's; the a's are nearer.
+        assert_eq!(clf.predict_one_multi(&[0.5]).unwrap(), vec!["a", "b"]);
+        assert_eq!(clf.predict_one_multi(&[0.6]).unwrap(), vec!["b", "a"]);
+        assert_eq!(clf.predict_one(&vec![0.6]), Some("b"));
+        assert_eq!(clf.predict_one_multi(&[5.0]).unwrap(), vec!["c"]);
+    }
+
+    #[test]
+    fn test_benchmark_latency() {
+        let mut rng = crate::util::Rng::new(10);
+        let train: Vec<Vec<f64>> = (0..200).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let labels: Vec<usize> = (0..200).map(|i| i % 2).collect();
+        let test: Vec<Vec<f64>> = (0..20).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let mut clf = KNNClassifier::new(5);
+        assert_eq!(clf.benchmark_latency(&test, 2), None);
+        clf.fit(train, labels);
+
+        let stats = clf.benchmark_latency(&test, 5).unwrap();
+        assert!(stats.p50 <= stats.p90 && stats.p90 <= stats.p99, "{:?}", stats);
+        assert!(stats.p99 > Duration::ZERO);
+        assert!(stats.mean > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_transform_matrix() {
+        let mut rng = crate::util::Rng::new(21);
+        let train: Vec<Vec<f64>> = (0..60).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let labels: Vec<usize> = train.iter().map(|x| (x[0] + x[1] > 1.0) as usize).collect();
+        let test: Vec<Vec<f64>> = (0..20).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+
+        // The identity changes nothing.
+        let mut plain = KNNClassifier::new(3);
+        plain.fit(train.clone(), labels.clone());
+        let mut identity = KNNClassifier::with_transform_matrix(3, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+        assert_eq!(identity.kneighbors(&test[0]), None);
+        identity.fit(train, labels);
+        assert_eq!(identity.predict(&test), plain.predict(&test));
+        for x in &test {
+            assert_eq!(identity.kneighbors(x), plain.kneighbors(x));
+        }
+
+        // Stretching the second feature makes differences along it count
+        // ten times as much, so the nearest point changes.
+        let train = vec![vec![0.0, 0.5], vec![1.0, 0.0]];
+        let mut plain = KNNClassifier::new(1);
+        plain.fit(train.clone(), vec!["a", "b"]);
+        let mut scaled = KNNClassifier::with_transform_matrix(1, vec![vec![1.0, 0.0], vec![0.0, 10.0]]);
+        scaled.fit(train, vec!["a", "b"]);
+        assert_eq!(plain.predict_one(&vec![0.1, 0.0]), Some("a"));
+        assert_eq!(scaled.predict_one(&vec![0.1, 0.0]), Some("b"));
+        let (_, dist) = scaled.kneighbors(&[0.1, 0.0]).unwrap()[0];
+        assert!((dist - 0.9).abs() < 1e-12, "{}", dist);
+    }
+
+    #[test]
+    fn test_predict_one_diversity() {
+        // Two tight clusters; a query between them draws neighbours from
+        // both, a query inside one only from that one.
+        let train: Vec<Vec<f64>> = (0..10).map(|i| vec![i as f64 * 0.1]).chain((0..10).map(|i| vec![5.0 + i as f64 * 0.1])).collect();
+        let labels: Vec<usize> = (0..20).map(|i| i / 10).collect();
+        let mut clf = KNNClassifier::new(4);
+        assert_eq!(clf.predict_one_diversity(&[0.5]), None);
+        clf.fit(train, labels);
+
+        let interior = clf.predict_one_diversity(&[0.45]).unwrap();
+        let boundary = clf.predict_one_diversity(&[2.95]).unwrap();
+        // Neighbours 0.3 to 0.6 form six pairs, 1.0 apart in total.
+        assert!((interior - 1.0 / 6.0).abs() < 1e-12, "{}", interior);
+        assert!(boundary > 10.0 * interior, "{} vs {}", boundary, interior);
+    }
+
+    #[test]
+    fn test_kde() {
+        let mut rng = crate::util::Rng::new(8);
+        let train: Vec<Vec<f64>> = (0..100).map(|_| vec![rng.next_gaussian(), rng.next_gaussian()]).collect();
+        let labels = vec![0; 100];
+        let mut clf = KNNClassifier::new(3);
+        assert_eq!(clf.kde(&[0.0, 0.0], 0.5), None);
+        clf.fit(train, labels);
+
+        let dense = clf.kde(&[0.0, 0.0], 0.5).unwrap();
+        let empty = clf.kde(&[6.0, 6.0], 0.5).unwrap();
+        // A standard normal has density 1/2π ≈ 0.16 at its mean.
+        assert!(dense > 0.08 && dense < 0.25, "{}", dense);
+        assert!(empty < 1e-6 * dense, "{} vs {}", empty, dense);
+    }
+
+    #[test]
+    fn test_k_distance() {
+        let train = vec![vec![7.0], vec![0.0], vec![3.0], vec![1.0], vec![0.0]];
+        let labels = vec![0; 5];
+        let mut clf = KNNClassifier::new(1);
+        assert_eq!(clf.k_distance(1), None);
+        clf.fit(train.clone(), labels.clone());
+        assert_eq!(clf.k_distance(1).unwrap(), vec![0.0, 0.0, 1.0, 2.0, 4.0]);
+        assert_eq!(clf.k_distance(2).unwrap(), vec![1.0, 1.0, 1.0, 3.0, 6.0]);
+
+        clf.set_deduplicate(true);
+        clf.fit(train, labels);
+        assert_eq!(clf.k_distance(1).unwrap(), vec![0.0, 0.0, 1.0, 2.0, 4.0]);
+        assert_eq!(clf.k_distance(2).unwrap(), vec![1.0, 1.0, 1.0, 3.0, 6.0]);
+    }
+
+    #[test]
+    fn test_redundancy_score() {
+        let mut rng = crate::util::Rng::new(3);
+        let spread: Vec<Vec<f64>> = (0..60).map(|_| vec![rng.next_f64() * 10.0, rng.next_f64() * 10.0]).collect();
+        // The first ten of those points, each repeated six times.
+        let repeated: Vec<Vec<f64>> = (0..60).map(|i| spread[i % 10].clone()).collect();
+        let labels = vec![0; 60];
+
+        let mut clf = KNNClassifier::new(5);
+        assert_eq!(clf.redundancy_score(), None);
+        clf.fit(spread, labels.clone());
+        let low = clf.redundancy_score().unwrap();
+        clf.fit(repeated.clone(), labels.clone());
+        let high = clf.redundancy_score().unwrap();
+        // Neighbouring points share much of their neighbourhoods anyway.
+        assert!(low > 0.5 && low < 0.85, "{}", low);
+        assert!(high > 0.99, "{}", high);
+
+        clf.set_deduplicate(true);
+        clf.fit(repeated, labels);
+        assert_eq!(clf.redundancy_score(), Some(1.0));
+    }
+
+    #[test]
+    fn test_parallel_threshold() {
+        let mut rng = crate::util::Rng::new(12);
+        let train: Vec<Vec<f64>> = (0..100).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let labels: Vec<usize> = train.iter().map(|x| (x[0] > x[1]) as usize).collect();
+        let test: Vec<Vec<f64>> = (0..200).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let mut clf = KNNClassifier::new(3);
+        clf.fit(train, labels);
+        let expected: Vec<usize> = test.iter().map(|x| clf.predict_one(x).unwrap()).collect();
+
+        clf.set_parallel_threshold(50);
+        assert!(!clf.runs_parallel(10) && !clf.runs_parallel(50));
+        assert!(clf.runs_parallel(51) && clf.runs_parallel(200));
+        assert_eq!(clf.predict(&test[..10]).unwrap(), &expected[..10]);
+        assert_eq!(clf.predict(&test).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_univariate_knn_scores() {
+        let mut rng = crate::util::Rng::new(6);
+        // The class shows in feature 1 only; features 0 and 2 are noise.
+        let mut sample = |n: usize| -> (Vec<Vec<f64>>, Vec<usize>) {
+            let labels: Vec<usize> = (0..n).map(|_| rng.gen_range(2)).collect();
+            let data = labels.iter().map(|&l| vec![rng.next_f64(), l as f64 + 0.5 * rng.next_f64(), rng.next_f64()]).collect();
+            (data, labels)
+        };
+        let (train, train_labels) = sample(200);
+        let (val, val_labels) = sample(200);
+        let mut clf = KNNClassifier::new(5);
+        assert_eq!(clf.univariate_knn_scores(&val, &val_labels), None);
+        clf.fit(train, train_labels);
+
+        let scores = clf.univariate_knn_scores(&val, &val_labels).unwrap();
+        assert_eq!(scores.len(), 3);
+        assert_eq!(scores[1], 1.0);
+        assert!((scores[0] - 0.5).abs() < 0.12 && (scores[2] - 0.5).abs() < 0.12, "{:?}", scores);
+        // The classifier keeps using every feature.
+        assert_eq!(clf.feature_weights, None);
+    }
+
+    #[test]
+    fn test_decision_boundary_segments() {
+        // Class "a" left of x = 0.5, "b" right of it.
+        let train: Vec<Vec<f64>> = (0..10).flat_map(|i| (0..10).map(move |j| vec![i as f64 / 9.0, j as f64 / 9.0])).collect();
+        let labels: Vec<&str> = train.iter().map(|x| if x[0] < 0.5 { "a" } else { "b" }).collect();
+        let mut clf = KNNClassifier::new(1);
+        assert_eq!(clf.decision_boundary_segments((0.0, 1.0), (0.0, 1.0), 11), None);
+        clf.fit(train, labels);
+
+        let segments = clf.decision_boundary_segments((0.0, 1.0), (0.0, 1.0), 11).unwrap();
+        // One vertical segment per row of cells, in the column of cells
+        // around x = 0.5, together spanning the whole height.
+        assert_eq!(segments.len(), 10);
+        for &((x0, y0), (x1, y1)) in &segments {
+            assert_eq!(x0, x1);
+            assert!((x0 - 0.5).abs() <= 0.1, "{}", x0);
+            assert!((y1 - y0).abs() > 0.099);
+        }
+        let height: f64 = segments.iter().map(|&((_, y0), (_, y1))| (y1 - y0).abs()).sum();
+        assert!((height - 1.0).abs() < 1e-9, "{}", height);
+
+        // A rectangle inside one class has no boundary.
+        assert!(clf.decision_boundary_segments((0.0, 0.3), (0.0, 1.0), 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_neighbor_cache() {
+        let mut rng = crate::util::Rng::new(14);
+        let train: Vec<Vec<f64>> = (0..100).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let labels: Vec<usize> = train.iter().map(|x| (x[0] + 0.3 * rng.next_f64() > 0.6) as usize).collect();
+        let queries: Vec<Vec<f64>> = (0..30).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let mut clf = KNNClassifier::new(7);
+        assert!(clf.precompute_neighbors(&queries).is_none());
+        clf.fit(train.clone(), labels.clone());
+        let cache = clf.precompute_neighbors(&queries).unwrap();
+        assert_eq!(cache.len(), 30);
+        assert_eq!(clf.predict_cached(&cache), clf.predict(&queries));
+        assert_eq!(clf.predict_records_cached(&cache), clf.predict_records(&queries));
+
+        // Tweaking the vote keeps the cache valid.
+        clf.set_bandwidth(Some(0.1));
+        clf.set_prior_correction(true);
+        assert_eq!(clf.predict_cached(&cache), clf.predict(&queries));
+
+        // A smaller k votes with the nearest of the cached neighbours; a
+        // larger one needs a new search.
+        let mut small = KNNClassifier::new(3);
+        small.fit(train.clone(), labels.clone());
+        let mut cached = clf.clone();
+        cached.k = 3;
+        cached.set_bandwidth(None);
+        cached.set_prior_correction(false);
+        assert_eq!(cached.predict_cached(&cache), small.predict(&queries));
+        cached.k = 9;
+        assert_eq!(cached.predict_cached(&cache), None);
+
+        // So does new training data.
+        clf.fit(train[..50].to_vec(), labels[..50].to_vec());
+        assert_eq!(clf.predict_cached(&cache), None);
+    }
+
+    #[test]
+    fn test_overlap_points() {
+        // Two rows of points touching at x = 4.5: only the points near the
+        // seam have the other class among their nearest neighbours.
+        let train: Vec<Vec<f64>> = (0..10).flat_map(|i| (0..2).map(move |j| vec![i as f64, j as f64])).collect();
+        let labels: Vec<&str> = train.iter().map(|x| if x[0] < 4.5 { "a" } else { "b" }).collect();
+        let mut clf = KNNClassifier::new(3);
+        assert_eq!(clf.overlap_points(), None);
+        clf.fit(train.clone(), labels);
+
+        let overlap = clf.overlap_points().unwrap();
+        assert!(!overlap.is_empty());
+        for &i in &overlap {
+            assert!(train[i][0] == 4.0 || train[i][0] == 5.0, "{:?}", train[i]);
+        }
+        // Both sides of the seam are flagged, the ends of the rows aren't.
+        assert!(overlap.iter().any(|&i| train[i][0] == 4.0) && overlap.iter().any(|&i| train[i][0] == 5.0));
+        assert!(!overlap.contains(&0) && !overlap.contains(&19));
+    }
+
+    #[test]
+    fn test_feature_attributions() {
+        let mut rng = crate::util::Rng::new(19);
+        // Feature 0 separates the classes, feature 1 only somewhat and
+        // feature 2 not at all.
+        let labels: Vec<usize> = (0..200).map(|i| i % 2).collect();
+        let train: Vec<Vec<f64>> = labels.iter()
+            .map(|&l| vec![3.0 * l as f64 + rng.next_f64(), 0.5 * l as f64 + rng.next_f64(), rng.next_f64()])
+            .collect();
+        let mut clf = KNNClassifier::new(15);
+        assert_eq!(clf.feature_attributions(&[3.5, 1.0, 0.5]), None);
+        clf.fit(train, labels);
+        assert_eq!(clf.feature_attributions(&[3.5, 1.0]), None);
+
+        let attributions = clf.feature_attributions(&[3.5, 1.0, 0.5]).unwrap();
+        assert_eq!(attributions.len(), 3);
+        assert!(attributions[0] > 0.2, "{:?}", attributions);
+        assert!(attributions[0] > attributions[1].abs() && attributions[0] > attributions[2].abs(), "{:?}", attributions);
+        assert!(attributions[2].abs() < 0.2, "{:?}", attributions);
+    }
+
+    #[test]
+    fn test_predict_proba_detailed() {
+        let mut rng = crate::util::Rng::new(23);
+        let train: Vec<Vec<f64>> = (0..50).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        let labels: Vec<usize> = (0..50).map(|i| i % 3).collect();
+        let mut clf = KNNClassifier::new(6);
+        assert_eq!(clf.predict_proba_detailed(&[0.5, 0.5]), None);
+        clf.fit(train, labels);
+
+        for _ in 0..10 {
+            let x = vec![rng.next_f64(), rng.next_f64()];
+            let (proba, distances) = clf.predict_proba_detailed(&x).unwrap();
+            assert_eq!(Some(proba), clf.predict_proba(&x));
+            assert_eq!(distances.len(), 6);
+            assert!(distances.windows(2).all(|w| w[0] <= w[1]), "{:?}", distances);
+            let expected: Vec<f64> = clf.kneighbors(&x).unwrap().iter().map(|&(_, d)| d).collect();
+            assert_eq!(distances, expected);
+        }
+    }
+
+    #[test]
+    fn test_novelty_threshold() {
+        let mut rng = crate::util::Rng::new(29);
+        let train: Vec<Vec<f64>> = (0..200).map(|_| vec![rng.next_gaussian(), rng.next_gaussian()]).collect();
+        let labels: Vec<usize> = train.iter().map(|x| (x[0] > 0.0) as usize).collect();
+        let mut clf = KNNClassifier::new(5);
+        assert_eq!(clf.novelty_threshold(0.95), None);
+        assert_eq!(clf.predict_one_reject_far(&[0.0, 0.0], 1.0), None);
+        clf.fit(train.clone(), labels);
+
+        let threshold = clf.novelty_threshold(0.95).unwrap();
+        assert!(threshold > 0.0 && threshold <= clf.novelty_threshold(1.0).unwrap());
+        assert_eq!(clf.predict_one_reject_far(&[8.0, 8.0], threshold), Some(None));
+        assert_eq!(clf.predict_one_reject_far(&[0.5, 0.1], threshold), Some(Some(1)));
+        // About 5% of the training points fall beyond it.
+        let rejected = train.iter().filter(|x| clf.predict_one_reject_far(x, threshold).unwrap().is_none()).count();
+        assert!(rejected > 0 && rejected < 30, "{}", rejected);
+
+        // Deduplicated, the threshold and the rejections measure the same
+        // distances, copies included, as without.
+        let mut train = train;
+        let extra: Vec<Vec<f64>> = train.iter().step_by(3).cloned().collect();
+        train.extend(extra);
+        let labels: Vec<usize> = train.iter().map(|x| (x[0] > 0.0) as usize).collect();
+        let mut plain = KNNClassifier::new(5);
+        plain.fit(train.clone(), labels.clone());
+        let mut dedup = plain.clone();
+        dedup.set_deduplicate(true);
+        dedup.fit(train.clone(), labels);
+        assert_eq!(dedup.data.as_ref().unwrap().len(), 200);
+        let threshold = plain.novelty_threshold(0.95).unwrap();
+        assert!((dedup.novelty_threshold(0.95).unwrap() - threshold).abs() < 1e-12);
+        let rejected = |clf: &KNNClassifier<usize>| -> Vec<bool> {
+            train.iter().map(|x| clf.predict_one_reject_far(x, threshold).unwrap().is_none()).collect()
+        };
+        assert_eq!(rejected(&dedup), rejected(&plain));
+    }
+
+    #[test]
+    fn test_predict_or_defer() {
+        // Two classes, interleaved in the middle.
+        let train: Vec<Vec<f64>> = (0..30).map(|i| vec![i as f64]).collect();
+        let labels: Vec<&str> = (0..30).map(|i| match i {
+            0..=11  => "a",
+            18..=29 => "b",
+            _       => if i % 2 == 0 { "a" } else { "b" },
+        }).collect();
+        let mut clf = KNNClassifier::new(5);
+        assert_eq!(clf.predict_or_defer(&[vec![0.0]], 0.5), None);
+        clf.fit(train, labels);
+
+        let queries = vec![vec![2.0], vec![15.0], vec![27.0]];
+        assert_eq!(clf.predict_or_defer(&queries, 0.5).unwrap(), vec![Some("a"), None, Some("b")]);
+        // A zero margin defers nothing, so it predicts as usual.
+        let predictions: Vec<Option<&str>> = clf.predict(&queries).unwrap().into_iter().map(Some).collect();
+        assert_eq!(clf.predict_or_defer(&queries, 0.0).unwrap(), predictions);
+        assert_eq!(vote_margin(&[("a", 0.6), ("b", 0.1), ("c", 0.3)]), 0.3);
+    }
+
+    #[test]
+    fn test_fit_evaluate() {
+        let train = vec![vec![0.0], vec![0.2], vec![0.4], vec![1.0], vec![1.2], vec![1.4]];
+        let train_labels = vec!["a", "a", "a", "b", "b", "b"];
+        // The last two are on the wrong side of the boundary at 0.7.
+        let test = vec![vec![0.1], vec![0.3], vec![1.1], vec![1.3], vec![0.9], vec![0.5]];
+        let test_labels = ["a", "a", "b", "b", "a", "b"];
+
+        let mut clf = KNNClassifier::new(1);
+        assert_eq!(clf.fit_evaluate(train.clone(), train_labels.clone(), &[], &[]), None);
+        let report = clf.fit_evaluate(train, train_labels, &test, &test_labels).unwrap();
+        let predictions = clf.predict(&test).unwrap();
+        let correct = predictions.iter().zip(test_labels.iter()).filter(|(p, l)| p == l).count();
+        assert_eq!(report.accuracy, correct as f64 / test.len() as f64);
+        assert_eq!(report.accuracy, 4.0 / 6.0);
+        assert_eq!(report.macro_f1, 4.0 / 6.0);
+        assert_eq!(report.confusion.counts, vec![vec![2, 1], vec![1, 2]]);
+    }
+
+    #[test]
+    fn test_neighborhood_consistency() {
+        let mut rng = crate::util::Rng::new(8);
+        // Two clusters far apart, one per class.
+        let data: Vec<Vec<f64>> = (0..80).map(|i| vec![(i % 2) as f64 * 10.0 + rng.next_f64(), rng.next_f64()]).collect();
+        let clean: Vec<usize> = (0..80).map(|i| i % 2).collect();
+        let mut clf = KNNClassifier::new(5);
+        assert_eq!(clf.neighborhood_consistency(), None);
+        clf.fit(data.clone(), clean.clone());
+        assert_eq!(clf.neighborhood_consistency(), Some(1.0));
+
+        // Flipping a fifth of the labels leaves their points outvoted.
+        let noisy: Vec<usize> = clean.iter().enumerate().map(|(i, &l)| if i % 5 == 0 { 1 - l } else { l }).collect();
+        clf.fit(data.clone(), noisy.clone());
+        let score = clf.neighborhood_consistency().unwrap();
+        assert!(score < 0.8 && score > 0.5, "{}", score);
+
+        // Deduplicating duplicated data doesn't change the score.
+        let doubled: Vec<Vec<f64>> = data.iter().chain(data.iter()).cloned().collect();
+        let labels: Vec<usize> = noisy.iter().chain(noisy.iter()).cloned().collect();
+        clf.fit(doubled.clone(), labels.clone());
+        let plain = clf.neighborhood_consistency().unwrap();
+        clf.set_deduplicate(true);
+        clf.fit(doubled, labels);
+        assert!((clf.neighborhood_consistency().unwrap() - plain).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_auto_feature_weights() {
+        let mut rng = crate::util::Rng::new(21);
+        // The class shows in a feature on a unit scale, next to noise on a
+        // scale of thousands, which swamps it in plain Euclidean distances.
+        let mut sample = |n: usize| -> (Vec<Vec<f64>>, Vec<usize>) {
+            let labels: Vec<usize> = (0..n).map(|_| rng.gen_range(2)).collect();
+            let data = labels.iter().map(|&l| vec![l as f64 + 0.8 * rng.next_f64(), 1000.0 * rng.next_gaussian()]).collect();
+            (data, labels)
+        };
+        let (train, train_labels) = sample(200);
+        let (test, test_labels) = sample(200);
+        let accuracy = |clf: &KNNClassifier<usize>| {
+            let predictions = clf.predict(&test).unwrap();
+            predictions.iter().zip(test_labels.iter()).filter(|(p, l)| p == l).count() as f64 / test.len() as f64
+        };
+
+        let mut plain = KNNClassifier::new(5);
+        plain.fit(train.clone(), train_labels.clone());
+        let mut weighted = KNNClassifier::new(5);
+        weighted.set_auto_feature_weights(true);
+        weighted.fit(train, train_labels);
+
+        let weights = weighted.feature_weights.as_ref().unwrap();
+        assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(weights[0] > 0.999, "{:?}", weights);
+        let (plain_accuracy, weighted_accuracy) = (accuracy(&plain), accuracy(&weighted));
+        assert!(plain_accuracy < 0.7, "{}", plain_accuracy);
+        assert!(weighted_accuracy > 0.95, "{}", weighted_accuracy);
+    }
+
+    #[test]
+    fn test_scaling_params() {
+        let train = vec![vec![1.0, 10.0, 3.0], vec![2.0, 30.0, 3.0], vec![6.0, 20.0, 3.0]];
+        let mut clf = KNNClassifier::new(1);
+        clf.fit(train.clone(), vec![0, 1, 1]);
+        assert_eq!(clf.scaling_params(), None);
+        clf.set_standardize(true);
+        clf.fit(train.clone(), vec![0, 1, 1]);
+
+        let (means, stds) = clf.scaling_params().unwrap();
+        assert_eq!(means, column_means(&train));
+        for (j, (&mean, &std)) in means.iter().zip(stds.iter()).enumerate() {
+            let variance = train.iter().map(|x| (x[j] - mean).powi(2)).sum::<f64>() / 3.0;
+            let expected = if variance > 0.0 { variance.sqrt() } else { 1.0 };
+            assert!((std - expected).abs() < 1e-12, "{} vs {}", std, expected);
+        }
+        // Unscaled, the second feature decides; standardized, both count.
+        assert_eq!(clf.predict_one(&vec![1.0, 19.0, 3.0]), Some(0));
+        clf.set_standardize(false);
+        clf.fit(train, vec![0, 1, 1]);
+        assert_eq!(clf.predict_one(&vec![1.0, 19.0, 3.0]), Some(1));
+    }
+
+    #[test]
+    fn test_fit_with_mask() {
+        let mut rng = crate::util::Rng::new(23);
+        // The class shows in the first feature; the second is noise on the
+        // same scale.
+        let mut sample = |n: usize| -> (Vec<Vec<f64>>, Vec<usize>) {
+            let labels: Vec<usize> = (0..n).map(|_| rng.gen_range(2)).collect();
+            let data = labels.iter().map(|&l| vec![l as f64 + 0.8 * rng.next_f64(), 1.8 * rng.next_f64()]).collect();
+            (data, labels)
+        };
+        let (train, train_labels) = sample(200);
+        let (test, test_labels) = sample(200);
+        let accuracy = |clf: &KNNClassifier<usize>| {
+            let predictions = clf.predict(&test).unwrap();
+            predictions.iter().zip(test_labels.iter()).filter(|(p, l)| p == l).count() as f64 / test.len() as f64
+        };
+
+        let mut clf = KNNClassifier::new(5);
+        clf.fit_with_mask(train.clone(), train_labels.clone(), &[true, false]);
+        let without_noise = accuracy(&clf);
+        clf.fit_with_mask(train.clone(), train_labels.clone(), &[false, true]);
+        let without_signal = accuracy(&clf);
+        assert_eq!(without_noise, 1.0);
+        assert!(without_signal < 0.65, "{}", without_signal);
+
+        // A plain fit uses every feature again.
+        clf.fit(train, train_labels);
+        assert_eq!(clf.feature_weights, None);
+    }
+
+    #[test]
+    fn test_deduplicate() {
+        let mut rng = crate::util::Rng::new(19);
+        // Points on a coarse grid, so many coincide, with a scattered label
+        // per grid cell. (Copies of a point with different labels could be
+        // cut off at the k-th neighbour in a different order.)
+        let mut train = Vec::new();
+        let mut labels = Vec::new();
+        for _ in 0..200 {
+            let (i, j) = (rng.gen_range(6), rng.gen_range(6));
+            train.push(vec![i as f64, j as f64]);
+            labels.push(((i * 7 + j * 3) % 5 < 2) as usize);
+        }
+        // Off-grid queries, so no two training points are equally far.
+        let test: Vec<Vec<f64>> = (0..50).map(|_| vec![rng.next_f64() * 5.0 + 0.013, rng.next_f64() * 5.0 + 0.007]).collect();
+
+        for &bandwidth in &[None, Some(0.7)] {
+            let mut plain = KNNClassifier::new(9);
+            plain.set_bandwidth(bandwidth);
+            plain.fit(train.clone(), labels.clone());
+            let mut dedup = plain.clone();
+            dedup.set_deduplicate(true);
+            dedup.fit(train.clone(), labels.clone());
+
+            let stored = dedup.data.as_ref().unwrap().len();
+            assert!(stored < 72, "{}", stored);
+            assert_eq!(dedup.multiplicity.as_ref().unwrap().iter().sum::<usize>(), 200);
+            assert_eq!(dedup.predict(&test), plain.predict(&test));
+            for x in &test {
+                let (a, b) = (dedup.predict_proba(x).unwrap(), plain.predict_proba(x).unwrap());
+                for ((la, pa), (lb, pb)) in a.iter().zip(b.iter()) {
+                    assert!(la == lb && (pa - pb).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_metric() {
+        // The class is the direction of a point, while its magnitude varies
+        // over orders of magnitude: Euclidean neighbours of a small point
+        // are the other small points, whatever their direction.
+        let mut rng = crate::util::Rng::new(6);
+        let mut sample = |n: usize| -> (Vec<Vec<f64>>, Vec<usize>) {
+            let labels: Vec<usize> = (0..n).map(|_| rng.gen_range(2)).collect();
+            let data = labels.iter().map(|&l| {
+                let angle = (l as f64 + rng.next_f64()) * 0.2;
+                let radius = 10f64.powf(rng.next_f64() * 4.0);
+                vec![radius * angle.cos(), radius * angle.sin()]
+            }).collect();
+            (data, labels)
+        };
+        let (train, train_labels) = sample(200);
+        let (val, val_labels) = sample(100);
+        let mut clf = KNNClassifier::new(5);
+        assert_eq!(clf.select_metric(&val, &val_labels, &[Metric::Cosine]), None);
+        clf.fit(train, train_labels);
+
+        let (metric, accuracy) = clf.select_metric(&val, &val_labels, &[Metric::Euclidean, Metric::Cosine]).unwrap();
+        let (_, euclidean_accuracy) = clf.select_metric(&val, &val_labels, &[Metric::Euclidean]).unwrap();
+        assert_eq!(metric, Metric::Cosine);
+        assert!(accuracy > 0.95 && accuracy > euclidean_accuracy + 0.1, "{} vs {}", accuracy, euclidean_accuracy);
+    }
+
+    #[test]
+    fn test_predict_records() {
+        let mut rng = crate::util::Rng::new(2);
+        let labels: Vec<usize> = (0..60).map(|i| i % 3).collect();
+        let train: Vec<Vec<f64>> = labels.iter().map(|&l| vec![l as f64 + 2.0 * rng.next_f64(), rng.next_f64()]).collect();
+        let test: Vec<Vec<f64>> = (0..20).map(|i| vec![i as f64 * 0.2, 0.5]).collect();
+        let mut clf = KNNClassifier::new(5);
+        assert_eq!(clf.predict_records(&test), None);
+        clf.fit(train, labels);
+
+        let records = clf.predict_records(&test).unwrap();
+        assert_eq!(records.iter().map(|r| r.label).collect::<Vec<_>>(), clf.predict(&test).unwrap());
+        for (record, x) in records.iter().zip(test.iter()) {
+            assert_eq!(record.probabilities, clf.predict_proba(x).unwrap());
+            let max = record.probabilities.iter().map(|&(_, p)| p).fold(0f64, f64::max);
+            let own = record.probabilities.iter().find(|&&(l, _)| l == record.label).unwrap().1;
+            assert_eq!(own, max);
+        }
     }
 }
@@ -1,4 +1,42 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("building without the `std` feature requires the `libm` feature");
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+extern crate libm;
+
 pub mod core;
 pub mod util;
+#[cfg(feature = "std")]
 pub mod knn;
-
+#[cfg(feature = "std")]
+pub mod data;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod linear;
+#[cfg(feature = "std")]
+pub mod ensemble;
+#[cfg(feature = "std")]
+pub mod preprocessing;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod optim;
+#[cfg(feature = "std")]
+pub mod hierarchical;
+#[cfg(feature = "std")]
+pub mod model_selection;
+#[cfg(feature = "std")]
+pub mod conformal;
+#[cfg(feature = "std")]
+pub mod text;
+#[cfg(feature = "std")]
+pub mod centroid;
+#[cfg(feature = "std")]
+pub mod lof;
+pub mod parallel;
+mod float;
@@ -0,0 +1,474 @@
+use super::core::{Classifier, EarlyStopper, EarlyStopping, FitCallback, FitMetrics, OnlineClassifier, Progress};
+use super::optim::{Optimizer, Sgd};
+use super::util::{dot_product, train_validation_split};
+use std::iter;
+use std::ops::ControlFlow;
+use std::time::Instant;
+
+/// A multi-class perceptron. Each class has its own weight vector and bias;
+/// a datapoint is assigned the class with the highest score, and on every
+/// mistake the weights of the true class are moved towards the datapoint
+/// and those of the wrongly predicted class away from it.
+#[derive(Debug, Clone)]
+pub struct Perceptron<T> {
+    n_epochs: usize,
+    learning_rate: f64,
+    optimizer: Option<Box<dyn Optimizer>>,
+    early_stopping: Option<EarlyStopping>,
+    stopped_epoch: Option<usize>,
+    classes: Vec<T>,
+    weights: Vec<Vec<f64>>,
+    biases: Vec<f64>,
+    /// Each class's own copy of the optimizer, stepping its weights and
+    /// bias.
+    optimizers: Vec<Box<dyn Optimizer>>,
+}
+
+/// Perceptrons are equal if their settings and learned weights are; their
+/// optimizers, which can't be compared, are left out.
+impl<T> PartialEq for Perceptron<T> where T: PartialEq {
+    fn eq(&self, other: &Perceptron<T>) -> bool {
+        self.n_epochs == other.n_epochs
+            && self.learning_rate == other.learning_rate
+            && self.early_stopping == other.early_stopping
+            && self.stopped_epoch == other.stopped_epoch
+            && self.classes == other.classes
+            && self.weights == other.weights
+            && self.biases == other.biases
+    }
+}
+
+impl<T> Perceptron<T> where T: PartialEq + Clone {
+    /// Construct a new Perceptron that makes `n_epochs` passes over the data
+    /// in `fit()`.
+    pub fn new(n_epochs: usize, learning_rate: f64) -> Perceptron<T> {
+        Perceptron::<T>{
+            n_epochs,
+            learning_rate,
+            optimizer: None,
+            early_stopping: None,
+            stopped_epoch: None,
+            classes: Vec::new(),
+            weights: Vec::new(),
+            biases: Vec::new(),
+            optimizers: Vec::new(),
+        }
+    }
+
+    /// Update each class's weights and bias with its own copy of
+    /// `optimizer` rather than by plain steps of `learning_rate`, which
+    /// `None` goes back to. A mistake is a gradient step on the perceptron
+    /// loss, the predicted class's score minus the true class's, so the
+    /// true class gets the gradient `-x` (and -1 for its bias) and the
+    /// predicted class `x` (and 1). Classes already learned keep their
+    /// optimizers until the next `fit()`.
+    pub fn set_optimizer(&mut self, optimizer: Option<Box<dyn Optimizer>>) {
+        self.optimizer = optimizer;
+    }
+
+    /// Hold out part of the data in `fit()` and stop training once the
+    /// accuracy on it stops improving. Whether stopped early, at the last
+    /// epoch or by a callback, training ends with the weights of the best
+    /// epoch. `None` turns early stopping off.
+    pub fn set_early_stopping(&mut self, early_stopping: Option<EarlyStopping>) {
+        self.early_stopping = early_stopping;
+    }
+
+    /// The epoch, counted from zero, at which the last `fit()` was stopped
+    /// early, or None if it ran all `n_epochs`.
+    pub fn stopped_epoch(&self) -> Option<usize> {
+        self.stopped_epoch
+    }
+
+    /// Get the index of the highest-scoring class for `x`. Ties go to the
+    /// class seen first.
+    fn best_class(&self, x: &[f64]) -> Option<usize> {
+        let mut best = None;
+        let mut best_score = 0f64;
+        for (c, (w, b)) in self.weights.iter().zip(self.biases.iter()).enumerate() {
+            let score = dot_product(w, x) + b;
+            if best.is_none() || score > best_score {
+                best = Some(c);
+                best_score = score;
+            }
+        }
+        best
+    }
+
+    /// Train the perceptron from scratch as `fit()` does, reporting the
+    /// fraction of training datapoints misclassified during each epoch to
+    /// `callback`, which may stop training early.
+    pub fn fit_with_callback(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>, callback: &mut dyn FitCallback) {
+        self.classes.clear();
+        self.weights.clear();
+        self.biases.clear();
+        self.optimizers.clear();
+        self.stopped_epoch = None;
+        let (train, validation) = match self.early_stopping {
+            Some(ref es) => train_validation_split(data.len(), es.validation_fraction, es.random_state.clone()),
+            None         => ((0..data.len()).collect(), Vec::new()),
+        };
+        let mut stopper = self.early_stopping.as_ref().map(|es| EarlyStopper::new(es.patience, es.min_delta));
+        let mut best = None;
+
+        for epoch in 0..self.n_epochs {
+            let start = Instant::now();
+            let mut mistakes = 0;
+            for &i in &train {
+                if self.update(&data[i], &labels[i]) {
+                    mistakes += 1;
+                }
+            }
+            let metrics = FitMetrics {
+                loss: mistakes as f64 / train.len().max(1) as f64,
+                elapsed: start.elapsed(),
+            };
+            if callback.on_epoch_end(epoch, &metrics).is_break() {
+                break;
+            }
+            if let Some(stopper) = stopper.as_mut() {
+                let correct = validation.iter().filter(|&&i| {
+                    self.best_class(&data[i]).is_some_and(|c| self.classes[c] == labels[i])
+                }).count();
+                match stopper.update(correct as f64 / validation.len().max(1) as f64) {
+                    Progress::Improved => best = Some((self.classes.clone(), self.weights.clone(), self.biases.clone(),
+                                                       self.optimizers.clone())),
+                    Progress::Stalled  => {},
+                    Progress::Stop     => {
+                        self.stopped_epoch = Some(epoch);
+                        break;
+                    },
+                }
+            }
+        }
+        // However training ended, keep the epoch that validated best.
+        if let Some((classes, weights, biases, optimizers)) = best {
+            self.classes = classes;
+            self.weights = weights;
+            self.biases = biases;
+            self.optimizers = optimizers;
+        }
+    }
+
+    /// Learn from one labeled datapoint. Return whether it was
+    /// misclassified.
+    fn update(&mut self, x: &[f64], label: &T) -> bool {
+        let target = match self.classes.iter().position(|c| c == label) {
+            Some(c) => c,
+            None    => {
+                self.classes.push(label.clone());
+                self.weights.push(vec![0f64; x.len()]);
+                self.biases.push(0f64);
+                let optimizer = match self.optimizer {
+                    Some(ref optimizer) => optimizer.clone(),
+                    None                => Box::new(Sgd::new(self.learning_rate)),
+                };
+                self.optimizers.push(optimizer);
+                self.classes.len() - 1
+            },
+        };
+        let predicted = self.best_class(x).unwrap();
+        if predicted == target {
+            return false;
+        }
+        self.step(target, x, -1.0);
+        self.step(predicted, x, 1.0);
+        true
+    }
+
+    /// Step the weights and bias of class `c` with its optimizer, for the
+    /// gradient `sign * x`, and `sign` for the bias.
+    fn step(&mut self, c: usize, x: &[f64], sign: f64) {
+        let mut params = self.weights[c].clone();
+        params.push(self.biases[c]);
+        let grads: Vec<f64> = x.iter().chain(iter::once(&1.0)).map(|xi| sign * xi).collect();
+        self.optimizers[c].step(&mut params, &grads);
+        self.biases[c] = params.pop().unwrap();
+        self.weights[c] = params;
+    }
+}
+
+/// A callback that lets training run to the end.
+struct Silent;
+
+impl FitCallback for Silent {
+    fn on_epoch_end(&mut self, _epoch: usize, _metrics: &FitMetrics) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+impl<T> Classifier for Perceptron<T> where T: PartialEq + Clone {
+    type ExampleType = Vec<f64>;
+    type LabelType = T;
+
+    /// Train the perceptron from scratch, making `n_epochs` passes over the
+    /// data in order.
+    fn fit(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>) {
+        self.fit_with_callback(data, labels, &mut Silent);
+    }
+
+    /// Predict the labels of datapoints. Return None if `predict()` is
+    /// called before `fit()`.
+    fn predict(&self, data: &[Vec<f64>]) -> Option<Vec<T>> {
+        data.iter().map(|x| self.predict_one(x)).collect()
+    }
+
+    /// Predict the label for one datapoint. Return None if `predict_one()`
+    /// is called before `fit()`.
+    fn predict_one(&self, x: &Vec<f64>) -> Option<T> {
+        self.best_class(x).map(|c| self.classes[c].clone())
+    }
+}
+
+impl<T> OnlineClassifier for Perceptron<T> where T: PartialEq + Clone {
+    /// Make a single pass over the batch, keeping the current weights.
+    fn partial_fit(&mut self, data: Vec<Vec<f64>>, labels: Vec<T>) {
+        for (x, label) in data.iter().zip(labels.iter()) {
+            self.update(x, label);
+        }
+    }
+
+    fn n_features(&self) -> Option<usize> {
+        self.weights.first().map(|w| w.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Classifier, FitError, LossHistory, OnlineClassifier};
+    use crate::metrics::score;
+    use crate::util::{RandomState, Rng, DEFAULT_SEED};
+    use crate::data::CsvBatchReader;
+    use crate::optim::Adam;
+
+    fn toy_data() -> (Vec<Vec<f64>>, Vec<String>) {
+        let data = vec![
+            vec![0.0, 0.2],
+            vec![3.0, 3.5],
+            vec![0.3, -0.4],
+            vec![-3.0, 2.0],
+            vec![4.0, 2.5],
+            vec![-2.5, 3.0],
+            vec![-0.2, 0.1],
+            vec![3.5, 4.0],
+        ];
+        let labels = ["origin", "east", "origin", "west", "east", "west", "origin", "east"];
+        (data, labels.iter().map(|l| l.to_string()).collect())
+    }
+
+    #[test]
+    fn test_fit_predict() {
+        let (data, labels) = toy_data();
+        let mut clf = Perceptron::new(20, 1.0);
+        assert_eq!(clf.predict_one(&data[0]), None);
+
+        clf.fit(data.clone(), labels.clone());
+        assert_eq!(clf.predict(&data).unwrap(), labels);
+        assert_eq!(clf.n_features(), Some(2));
+    }
+
+    #[test]
+    fn test_optimizer() {
+        let (data, labels) = toy_data();
+        let mut plain = Perceptron::new(5, 0.5);
+        plain.fit(data.clone(), labels.clone());
+        // Plain steps are SGD on the perceptron loss.
+        let mut sgd = Perceptron::new(5, 1.0);
+        sgd.set_optimizer(Some(Box::new(Sgd::new(0.5))));
+        sgd.fit(data.clone(), labels.clone());
+        assert_eq!((&sgd.weights, &sgd.biases), (&plain.weights, &plain.biases));
+
+        let mut adam = Perceptron::new(20, 1.0);
+        adam.set_optimizer(Some(Box::new(Adam::new(0.1))));
+        adam.fit(data.clone(), labels.clone());
+        assert_eq!(adam.predict(&data).unwrap(), labels);
+        assert_ne!(adam.weights, plain.weights);
+        // Each class steps its own copy of the optimizer.
+        let steps: Vec<String> = adam.optimizers.iter().map(|o| format!("{:?}", o)).collect();
+        assert_eq!(steps.len(), 3);
+        assert!(steps[0] != steps[1] && steps[1] != steps[2], "{:?}", steps);
+
+        // Without one, it steps by the learning rate again.
+        let mut unset = Perceptron::new(20, 1.0);
+        unset.fit(data.clone(), labels.clone());
+        adam.set_optimizer(None);
+        adam.fit(data, labels);
+        assert_eq!(adam, unset);
+    }
+
+    #[test]
+    fn test_loss_history() {
+        let (data, labels) = toy_data();
+        let mut history = LossHistory::new();
+        let mut clf = Perceptron::new(10, 1.0);
+        clf.fit_with_callback(data.clone(), labels.clone(), &mut history);
+
+        assert_eq!(history.losses.len(), 10);
+        assert!(history.losses.windows(2).all(|w| w[1] <= w[0]), "{:?}", history.losses);
+        assert_eq!(*history.losses.last().unwrap(), 0.0);
+        assert_eq!(clf.predict(&data).unwrap(), labels);
+    }
+
+    #[test]
+    fn test_callback_stops_training() {
+        struct StopAfter(usize, usize);
+
+        impl FitCallback for StopAfter {
+            fn on_epoch_end(&mut self, epoch: usize, _metrics: &FitMetrics) -> ControlFlow<()> {
+                self.1 += 1;
+                if epoch + 1 == self.0 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+            }
+        }
+
+        let (data, labels) = toy_data();
+        let mut stop = StopAfter(3, 0);
+        let mut stopped = Perceptron::new(20, 0.5);
+        stopped.fit_with_callback(data.clone(), labels.clone(), &mut stop);
+        assert_eq!(stop.1, 3);
+
+        let mut three_epochs = Perceptron::new(3, 0.5);
+        three_epochs.fit(data, labels);
+        assert_eq!(stopped.weights, three_epochs.weights);
+    }
+
+    /// Two overlapping classes with a fifth of the labels flipped, so that
+    /// the perceptron never settles.
+    fn noisy_data() -> (Vec<Vec<f64>>, Vec<i32>) {
+        let mut rng = Rng::new(11);
+        let mut data = Vec::new();
+        let mut labels = Vec::new();
+        for i in 0..200 {
+            let label = i % 2;
+            let centre = if label == 0 { -1.0 } else { 1.0 };
+            data.push(vec![centre + 2.0 * rng.next_f64() - 1.0, 2.0 * rng.next_f64() - 1.0]);
+            labels.push(if rng.next_f64() < 0.2 { 1 - label } else { label });
+        }
+        (data, labels)
+    }
+
+    #[test]
+    fn test_early_stopping() {
+        let (data, labels) = noisy_data();
+        let es = EarlyStopping { validation_fraction: 0.25, patience: 5, min_delta: 0.0, random_state: Some(RandomState::new(3)) };
+
+        let mut stopped = Perceptron::new(100, 1.0);
+        stopped.set_early_stopping(Some(es));
+        stopped.fit(data.clone(), labels.clone());
+        let epoch = stopped.stopped_epoch().unwrap();
+        assert!(epoch < 99, "stopped at {}", epoch);
+
+        // The same training rows, run for every epoch.
+        let (train, validation) = train_validation_split(data.len(), 0.25, Some(RandomState::new(3)));
+        let mut full = Perceptron::new(100, 1.0);
+        full.fit(train.iter().map(|&i| data[i].clone()).collect(), train.iter().map(|&i| labels[i]).collect());
+        assert_eq!(full.stopped_epoch(), None);
+
+        let accuracy = |clf: &Perceptron<i32>| {
+            validation.iter().filter(|&&i| clf.predict_one(&data[i]) == Some(labels[i])).count()
+        };
+        assert!(accuracy(&stopped) >= accuracy(&full));
+    }
+
+    #[test]
+    fn test_early_stopping_out_of_epochs() {
+        // Too much patience to stop, so training runs out of epochs while
+        // the validation accuracy is stalled.
+        let (data, labels) = noisy_data();
+        let es = EarlyStopping { validation_fraction: 0.25, patience: 100, min_delta: 0.0, random_state: Some(RandomState::new(3)) };
+        let mut clf = Perceptron::new(8, 1.0);
+        clf.set_early_stopping(Some(es));
+        clf.fit(data.clone(), labels.clone());
+        assert_eq!(clf.stopped_epoch(), None);
+
+        // Replay each number of epochs on the same training rows to find
+        // the best one.
+        let (train, validation) = train_validation_split(data.len(), 0.25, Some(RandomState::new(3)));
+        let train_data: Vec<Vec<f64>> = train.iter().map(|&i| data[i].clone()).collect();
+        let train_labels: Vec<i32> = train.iter().map(|&i| labels[i]).collect();
+        let replays: Vec<Perceptron<i32>> = (1..=8).map(|n| {
+            let mut replay = Perceptron::new(n, 1.0);
+            replay.fit(train_data.clone(), train_labels.clone());
+            replay
+        }).collect();
+        let accuracy = |clf: &Perceptron<i32>| {
+            validation.iter().filter(|&&i| clf.predict_one(&data[i]) == Some(labels[i])).count()
+        };
+        let best = (0..8).fold(0, |best, n| if accuracy(&replays[n]) > accuracy(&replays[best]) { n } else { best });
+        assert!(accuracy(&replays[7]) < accuracy(&replays[best]), "best epoch {}", best);
+        assert_eq!((&clf.weights, &clf.biases), (&replays[best].weights, &replays[best].biases));
+    }
+
+    #[test]
+    fn test_random_state_reproducible() {
+        // Split off a test set, train with early stopping and score: every
+        // random choice comes from one state, so equal states agree.
+        let run = |mut state: RandomState| {
+            let (data, labels) = noisy_data();
+            let (train, test) = train_validation_split(data.len(), 0.3, Some(state.split()));
+            let mut clf = Perceptron::new(50, 1.0);
+            clf.set_early_stopping(Some(EarlyStopping {
+                validation_fraction: 0.2,
+                patience: 3,
+                min_delta: 0.0,
+                random_state: Some(state.split()),
+            }));
+            clf.fit(train.iter().map(|&i| data[i].clone()).collect(), train.iter().map(|&i| labels[i]).collect());
+            let test_data: Vec<Vec<f64>> = test.iter().map(|&i| data[i].clone()).collect();
+            let test_labels: Vec<i32> = test.iter().map(|&i| labels[i]).collect();
+            (clf.stopped_epoch(), score(&clf, &test_data, &test_labels).unwrap())
+        };
+        assert_eq!(run(RandomState::new(21)), run(RandomState::new(21)));
+        assert_eq!(run(RandomState::default()), run(RandomState::new(DEFAULT_SEED)));
+    }
+
+    #[test]
+    fn test_early_stopper() {
+        let mut stopper = EarlyStopper::new(2, 0.05);
+        assert_eq!(stopper.update(0.5), Progress::Improved);
+        assert_eq!(stopper.update(0.7), Progress::Improved);
+        assert_eq!(stopper.update(0.74), Progress::Stalled);
+        assert_eq!(stopper.update(0.6), Progress::Stop);
+        assert_eq!((stopper.best_epoch(), stopper.best_score()), (Some(1), Some(0.7)));
+    }
+
+    #[test]
+    fn test_fit_from_iter_matches_fit() {
+        let (data, labels) = toy_data();
+        let mut in_memory = Perceptron::new(1, 0.5);
+        in_memory.fit(data.clone(), labels.clone());
+
+        let batches: Vec<_> = data.chunks(3).zip(labels.chunks(3))
+            .map(|(d, l)| (d.to_vec(), l.to_vec()))
+            .collect();
+        let mut streamed = Perceptron::new(1, 0.5);
+        streamed.fit_from_iter(batches).unwrap();
+        assert_eq!(streamed, in_memory);
+
+        // The same, streaming from a CSV file.
+        let mut csv = String::new();
+        for (x, label) in data.iter().zip(labels.iter()) {
+            csv.push_str(&format!("{},{},{}\n", x[0], x[1], label));
+        }
+        let reader = CsvBatchReader::new(csv.as_bytes(), 3, false, None).unwrap();
+        let mut from_csv = Perceptron::new(1, 0.5);
+        from_csv.fit_from_iter(reader.map(|batch| batch.unwrap())).unwrap();
+        assert_eq!(from_csv, in_memory);
+    }
+
+    #[test]
+    fn test_fit_from_iter_dimension_change() {
+        let batches = vec![
+            (vec![vec![0.0, 1.0], vec![1.0, 0.0]], vec![0, 1]),
+            (vec![vec![0.5, 0.5]], vec![0]),
+            (vec![vec![0.5, 0.5], vec![1.0, 2.0, 3.0]], vec![1, 0]),
+        ];
+        let mut clf = Perceptron::new(1, 1.0);
+        assert_eq!(clf.fit_from_iter(batches),
+                   Err(FitError::DimensionMismatch { batch: 2, expected: 2, got: 3 }));
+
+        let batches = vec![(vec![vec![0.0, 1.0]], vec![0, 1])];
+        assert_eq!(clf.fit_from_iter(batches),
+                   Err(FitError::LengthMismatch { batch: 0, examples: 1, labels: 2 }));
+    }
+}
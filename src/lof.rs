@@ -0,0 +1,89 @@
+use super::knn::nearest_rows;
+use super::parallel;
+use super::util::Metric;
+
+/// Local Outlier Factor (Breunig et al., 2000): scores how isolated each
+/// datapoint is compared to its k nearest neighbours. A point's local
+/// reachability density is the inverse of its mean reachability distance
+/// to its neighbours, where the reachability distance to a neighbour is at
+/// least that neighbour's own distance to its k-th nearest neighbour. The
+/// outlier factor is the neighbours' mean density divided by the point's
+/// own. Points inside a cluster score about 1, whatever the cluster's
+/// density; scores well above 1 mark outliers.
+#[derive(Debug, Clone)]
+pub struct LocalOutlierFactor {
+    k: usize,
+    metric: Metric,
+    scores: Option<Vec<f64>>,
+}
+
+impl LocalOutlierFactor {
+    /// Construct a new LocalOutlierFactor over `k` neighbours, using the
+    /// Euclidean distance.
+    pub fn new(k: usize) -> LocalOutlierFactor {
+        LocalOutlierFactor::with_metric(k, Metric::Euclidean)
+    }
+
+    /// Construct a new LocalOutlierFactor that compares examples using
+    /// `metric`.
+    pub fn with_metric(k: usize, metric: Metric) -> LocalOutlierFactor {
+        LocalOutlierFactor { k, metric, scores: None }
+    }
+
+    /// Compute the outlier factor of every datapoint, with respect to the
+    /// others. There must be more than k datapoints.
+    pub fn fit(&mut self, data: &[Vec<f64>]) {
+        let (k, metric) = (self.k, self.metric);
+        assert!(k >= 1 && k < data.len(), "k must be in [1, n)");
+        // Each point's k nearest others. The point itself is among its k + 1
+        // nearest, unless it has duplicates, which are just as near.
+        let neighbours: Vec<Vec<(usize, f64)>> = parallel::map_rows(&(0..data.len()).collect::<Vec<_>>(), |&i| {
+            let mut neigh = nearest_rows(data, metric, k + 1, &data[i]);
+            let own = neigh.iter().position(|&(j, _)| j == i).unwrap_or(k);
+            neigh.remove(own);
+            neigh
+        });
+        let k_distance: Vec<f64> = neighbours.iter().map(|neigh| neigh[k - 1].1).collect();
+        let density: Vec<f64> = neighbours.iter().map(|neigh| {
+            let reach = neigh.iter().map(|&(j, d)| d.max(k_distance[j])).sum::<f64>() / k as f64;
+            // Duplicates can make the reachability distance 0.
+            1.0 / (reach + 1e-10)
+        }).collect();
+        self.scores = Some(neighbours.iter().zip(density.iter()).map(|(neigh, &own)| {
+            neigh.iter().map(|&(j, _)| density[j]).sum::<f64>() / k as f64 / own
+        }).collect());
+    }
+
+    /// The outlier factor of each datapoint given to `fit()`, in order.
+    /// Return None if called before `fit()`.
+    pub fn scores(&self) -> Option<&[f64]> {
+        self.scores.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Rng;
+
+    #[test]
+    fn test_injected_outlier() {
+        let mut rng = Rng::new(31);
+        // A tight cluster and a loose one, plus one point far from both.
+        let mut data: Vec<Vec<f64>> = (0..40).map(|_| vec![rng.next_f64(), rng.next_f64()]).collect();
+        data.extend((0..40).map(|_| vec![20.0 + 5.0 * rng.next_f64(), 5.0 * rng.next_f64()]));
+        data.push(vec![10.0, 10.0]);
+
+        let mut lof = LocalOutlierFactor::new(5);
+        assert_eq!(lof.scores(), None);
+        lof.fit(&data);
+        let scores = lof.scores().unwrap();
+        let outlier = scores[80];
+        let max_inlier = scores[..80].iter().cloned().fold(0f64, f64::max);
+        assert!(outlier > 3.0, "{}", outlier);
+        assert!(outlier > 2.0 * max_inlier, "{} vs {}", outlier, max_inlier);
+        // Both clusters score near 1 despite their different densities.
+        let mean_inlier = scores[..80].iter().sum::<f64>() / 80.0;
+        assert!((mean_inlier - 1.0).abs() < 0.2, "{}", mean_inlier);
+    }
+}
@@ -1,5 +1,3 @@
-use std::collections::BTreeSet;
-
 fn main() {
 
 }
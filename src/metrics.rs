@@ -0,0 +1,918 @@
+use super::core::Classifier;
+use super::util::{column_means, euclidean_distance, pairwise_distances, squared_distance, train_validation_split, Counter, Metric, RandomState};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::hash::Hash;
+
+/// Compute the fraction of positions at which two prediction sequences
+/// agree.
+pub fn prediction_agreement<T: PartialEq>(a: &[T], b: &[T]) -> f64 {
+    assert_eq!(a.len(), b.len(), "prediction sequences differ in length");
+    if a.is_empty() {
+        return 1.0;
+    }
+    let matches = a.iter().zip(b.iter()).filter(|&(x, y)| x == y).count();
+    matches as f64 / a.len() as f64
+}
+
+/// A classifier with a name to report it by.
+pub type NamedModel<E, T> = (String, Box<dyn Classifier<ExampleType = E, LabelType = T>>);
+
+/// Compute the accuracy of a trained classifier on labeled data. Return
+/// None if the classifier can't predict, e.g. because it hasn't been
+/// trained.
+pub fn score<C>(clf: &C, data: &[C::ExampleType], labels: &[C::LabelType]) -> Option<f64>
+    where C: Classifier + ?Sized, C::LabelType: PartialEq
+{
+    Some(prediction_agreement(&clf.predict(data)?, labels))
+}
+
+/// Compute the accuracy of predictions along with a Wilson score interval
+/// for it at the given `confidence` level, in (0, 1), as (accuracy, lower,
+/// upper). Treating each prediction as an independent trial, the interval
+/// holds the true accuracy with about that probability. Unlike the normal
+/// approximation it stays within [0, 1] and behaves at accuracies of 0 or
+/// 1 and on small test sets. It narrows with the square root of the number
+/// of predictions.
+pub fn accuracy_with_ci<T: PartialEq>(y_true: &[T], y_pred: &[T], confidence: f64) -> (f64, f64, f64) {
+    assert!(confidence > 0.0 && confidence < 1.0, "confidence must be in (0, 1)");
+    assert!(!y_true.is_empty(), "accuracy needs at least one prediction");
+    let accuracy = prediction_agreement(y_true, y_pred);
+    let n = y_true.len() as f64;
+    let z = normal_critical_value(confidence);
+    let z2 = z * z;
+    let denominator = 1.0 + z2 / n;
+    let centre = (accuracy + z2 / (2.0 * n)) / denominator;
+    let half_width = z / denominator * (accuracy * (1.0 - accuracy) / n + z2 / (4.0 * n * n)).sqrt();
+    (accuracy, (centre - half_width).max(0.0), (centre + half_width).min(1.0))
+}
+
+/// Train each named classifier on the same training data and compute its
+/// accuracy on the test data, returning (name, accuracy) pairs in the order
+/// the models were given.
+pub fn compare_models<E, T>(models: Vec<NamedModel<E, T>>,
+                            train: &[E],
+                            train_labels: &[T],
+                            test: &[E],
+                            test_labels: &[T]) -> Vec<(String, f64)>
+    where E: Clone, T: PartialEq + Clone
+{
+    models.into_iter().map(|(name, mut model)| {
+        model.fit(train.to_vec(), train_labels.to_vec());
+        let accuracy = score(&*model, test, test_labels).expect("no predictions after fit()");
+        (name, accuracy)
+    }).collect()
+}
+
+/// Below this many discordant pairs, `mcnemar_test()` computes an exact
+/// binomial p-value instead of using the chi-square approximation.
+pub const MCNEMAR_EXACT_THRESHOLD: u64 = 25;
+
+/// The outcome of McNemar's test comparing two classifiers on the same
+/// test set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct McNemarResult {
+    /// The number of examples only classifier A got right.
+    pub a_only_correct: u64,
+    /// The number of examples only classifier B got right.
+    pub b_only_correct: u64,
+    /// The chi-square statistic with continuity correction,
+    /// `(|a - b| - 1)^2 / (a + b)`.
+    pub statistic: f64,
+    /// The two-sided p-value of the hypothesis that both classifiers have
+    /// the same error rate.
+    pub p_value: f64,
+    /// Whether the p-value is exact (binomial) rather than from the
+    /// chi-square approximation.
+    pub exact: bool,
+}
+
+/// Test whether two classifiers' predictions for the same examples differ
+/// in accuracy more than chance would explain (McNemar's test). Only the
+/// examples exactly one of them gets right count. With fewer than
+/// `MCNEMAR_EXACT_THRESHOLD` such examples the p-value comes from the exact
+/// binomial distribution, otherwise from the chi-square distribution with
+/// one degree of freedom.
+pub fn mcnemar_test<T: PartialEq>(y_true: &[T], pred_a: &[T], pred_b: &[T]) -> McNemarResult {
+    assert!(y_true.len() == pred_a.len() && y_true.len() == pred_b.len(), "prediction sequences differ in length");
+    let mut a_only = 0u64;
+    let mut b_only = 0u64;
+    for ((y, a), b) in y_true.iter().zip(pred_a.iter()).zip(pred_b.iter()) {
+        match (a == y, b == y) {
+            (true, false) => a_only += 1,
+            (false, true) => b_only += 1,
+            _ => {},
+        }
+    }
+    let n = a_only + b_only;
+    let statistic = if n == 0 {
+        0.0
+    } else {
+        let diff = (a_only as f64 - b_only as f64).abs() - 1.0;
+        diff.max(0.0).powi(2) / n as f64
+    };
+    let exact = n < MCNEMAR_EXACT_THRESHOLD;
+    let p_value = if exact {
+        // Two-sided binomial test of the smaller count against p = 1/2.
+        let mut tail = 0f64;
+        let mut coef = 1f64;
+        for i in 0..=a_only.min(b_only) {
+            if i > 0 {
+                coef *= (n - i + 1) as f64 / i as f64;
+            }
+            tail += coef;
+        }
+        (2.0 * tail / 2f64.powi(n as i32)).min(1.0)
+    } else {
+        erfc((statistic / 2.0).sqrt())
+    };
+    McNemarResult { a_only_correct: a_only, b_only_correct: b_only, statistic, p_value, exact }
+}
+
+/// The outcome of a 5x2cv paired t-test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairedTTestResult {
+    /// The t statistic, with 5 degrees of freedom.
+    pub statistic: f64,
+    /// The two-sided p-value of the hypothesis that both classifiers are
+    /// equally accurate.
+    pub p_value: f64,
+    /// The (A, B) test accuracies of each of the ten folds, in order.
+    pub fold_scores: Vec<(f64, f64)>,
+}
+
+/// Compare two classifiers with Dietterich's 5x2cv paired t-test. Five
+/// times, the data is split in half at random; each classifier is trained
+/// on either half and scored on the other, always on the same splits. The
+/// statistic compares the first fold's accuracy difference with the
+/// variance of the differences within each repetition.
+pub fn paired_cv_ttest<E, T>(a: &mut dyn Classifier<ExampleType = E, LabelType = T>,
+                             b: &mut dyn Classifier<ExampleType = E, LabelType = T>,
+                             data: &[E],
+                             labels: &[T],
+                             random_state: Option<RandomState>) -> PairedTTestResult
+    where E: Clone, T: PartialEq + Clone
+{
+    assert_eq!(data.len(), labels.len(), "data and labels differ in length");
+    let mut state = RandomState::resolve(random_state);
+    let select = |indices: &[usize]| -> (Vec<E>, Vec<T>) {
+        (indices.iter().map(|&i| data[i].clone()).collect(), indices.iter().map(|&i| labels[i].clone()).collect())
+    };
+    let mut fold_scores = Vec::with_capacity(10);
+    let mut variance_sum = 0f64;
+    for _ in 0..5 {
+        let (first, second) = train_validation_split(data.len(), 0.5, Some(state.split()));
+        let halves = [select(&first), select(&second)];
+        let mut diffs = [0f64; 2];
+        for (fold, diff) in diffs.iter_mut().enumerate() {
+            let (train_data, train_labels) = &halves[fold];
+            let (test_data, test_labels) = &halves[1 - fold];
+            a.fit(train_data.clone(), train_labels.clone());
+            b.fit(train_data.clone(), train_labels.clone());
+            let score_a = score(a, test_data, test_labels).expect("no predictions after fit()");
+            let score_b = score(b, test_data, test_labels).expect("no predictions after fit()");
+            fold_scores.push((score_a, score_b));
+            *diff = score_a - score_b;
+        }
+        let mean = (diffs[0] + diffs[1]) / 2.0;
+        variance_sum += (diffs[0] - mean).powi(2) + (diffs[1] - mean).powi(2);
+    }
+    let first_diff = fold_scores[0].0 - fold_scores[0].1;
+    let statistic = if first_diff == 0.0 { 0.0 } else { first_diff / (variance_sum / 5.0).sqrt() };
+    PairedTTestResult { statistic, p_value: student_t5_two_sided(statistic), fold_scores }
+}
+
+/// The complementary error function, with a fractional error below 1.2e-7
+/// everywhere (Numerical Recipes' `erfcc`).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23 + t * (1.000_023_68 + t * (0.374_091_96 + t * (0.096_784_18
+        + t * (-0.186_288_06 + t * (0.278_868_07 + t * (-1.135_203_98 + t * (1.488_515_87
+        + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let result = t * poly.exp();
+    if x >= 0.0 { result } else { 2.0 - result }
+}
+
+/// The z such that a standard normal variable lies within [-z, z] with
+/// probability `confidence`, found by bisection on `erfc()`.
+fn normal_critical_value(confidence: f64) -> f64 {
+    let (mut lo, mut hi) = (0f64, 40f64);
+    for _ in 0..100 {
+        let mid = (lo + hi) / 2.0;
+        if erfc(mid / std::f64::consts::SQRT_2) > 1.0 - confidence {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+/// The two-sided p-value of a t statistic with 5 degrees of freedom, from
+/// the closed form of Student's t distribution for odd degrees of freedom.
+fn student_t5_two_sided(t: f64) -> f64 {
+    if t.is_infinite() {
+        return 0.0;
+    }
+    let theta = (t.abs() / 5f64.sqrt()).atan();
+    let (sin, cos) = theta.sin_cos();
+    let central = 2.0 / PI * (theta + sin * (cos + 2.0 / 3.0 * cos.powi(3)));
+    (1.0 - central).max(0.0)
+}
+
+/// Counts of true against predicted labels, as from `confusion_matrix()`,
+/// or their total weights, as from `ConfusionMatrix::weighted()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfusionMatrix<T, C = u64> {
+    /// Every label, true or predicted, in order of first appearance in the
+    /// true labels, then in the predicted ones.
+    pub labels: Vec<T>,
+    /// `counts[i][j]` is the number (or weight) of examples of `labels[i]`
+    /// predicted as `labels[j]`.
+    pub counts: Vec<Vec<C>>,
+}
+
+impl<T: Hash + Eq + Clone> ConfusionMatrix<T, f64> {
+    /// Total the weights of the examples of each true label predicted as
+    /// each label, e.g. to weigh costly examples more in cost-sensitive
+    /// evaluation. With unit weights this is `confusion_matrix()` in
+    /// floating point.
+    pub fn weighted(y_true: &[T], y_pred: &[T], sample_weights: &[f64]) -> ConfusionMatrix<T, f64> {
+        assert_eq!(y_true.len(), sample_weights.len(), "labels and sample weights differ in length");
+        let (labels, index) = label_index(y_true, y_pred);
+        let mut counts = vec![vec![0f64; labels.len()]; labels.len()];
+        for ((y, p), &w) in y_true.iter().zip(y_pred.iter()).zip(sample_weights.iter()) {
+            counts[index[y]][index[p]] += w;
+        }
+        ConfusionMatrix { labels, counts }
+    }
+}
+
+/// Count how often each true label was predicted as each label.
+pub fn confusion_matrix<T: Hash + Eq + Clone>(y_true: &[T], y_pred: &[T]) -> ConfusionMatrix<T> {
+    let (labels, index) = label_index(y_true, y_pred);
+    let mut counts = vec![vec![0u64; labels.len()]; labels.len()];
+    for (y, p) in y_true.iter().zip(y_pred.iter()) {
+        counts[index[y]][index[p]] += 1;
+    }
+    ConfusionMatrix { labels, counts }
+}
+
+/// List every label of two prediction sequences in order of first
+/// appearance, along with each label's position in the list.
+fn label_index<'a, T: Hash + Eq + Clone>(y_true: &'a [T], y_pred: &'a [T]) -> (Vec<T>, HashMap<&'a T, usize>) {
+    assert_eq!(y_true.len(), y_pred.len(), "prediction sequences differ in length");
+    let mut index: HashMap<&T, usize> = HashMap::new();
+    let mut labels = Vec::new();
+    for label in y_true.iter().chain(y_pred.iter()) {
+        index.entry(label).or_insert_with(|| {
+            labels.push(label.clone());
+            labels.len() - 1
+        });
+    }
+    (labels, index)
+}
+
+/// Compute each class's recall: the fraction of its examples predicted
+/// correctly. Labels that are only ever predicted, never true, have no
+/// entry.
+pub fn per_class_accuracy<T: Hash + Eq + Clone>(y_true: &[T], y_pred: &[T]) -> HashMap<T, f64> {
+    let matrix = confusion_matrix(y_true, y_pred);
+    matrix.labels.into_iter().zip(matrix.counts.iter()).enumerate()
+        .filter_map(|(i, (label, row))| {
+            let total: u64 = row.iter().sum();
+            if total == 0 {
+                return None;
+            }
+            Some((label, row[i] as f64 / total as f64))
+        })
+        .collect()
+}
+
+/// A summary of predictions against true labels, as from
+/// `evaluation_report()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvaluationReport<T> {
+    /// The fraction of labels predicted correctly.
+    pub accuracy: f64,
+    /// The unweighted mean of every label's F1 score.
+    pub macro_f1: f64,
+    /// The counts behind the scores.
+    pub confusion: ConfusionMatrix<T>,
+}
+
+/// Summarize predictions against the true labels by accuracy, macro-F1 and
+/// confusion matrix. The F1 score of a label is the harmonic mean of its
+/// precision and recall, `2 TP / (2 TP + FP + FN)`, and is averaged over
+/// every label, true or predicted, so that each class counts equally
+/// however rare. A label never predicted correctly scores 0.
+pub fn evaluation_report<T: Hash + Eq + Clone>(y_true: &[T], y_pred: &[T]) -> EvaluationReport<T> {
+    assert!(!y_true.is_empty(), "evaluation needs at least one example");
+    let confusion = confusion_matrix(y_true, y_pred);
+    let counts = &confusion.counts;
+    let correct: u64 = (0..counts.len()).map(|i| counts[i][i]).sum();
+    let f1_sum: f64 = (0..counts.len()).map(|i| {
+        let true_count: u64 = counts[i].iter().sum();
+        let pred_count: u64 = counts.iter().map(|row| row[i]).sum();
+        2.0 * counts[i][i] as f64 / (true_count + pred_count) as f64
+    }).sum();
+    EvaluationReport {
+        accuracy: correct as f64 / y_true.len() as f64,
+        macro_f1: f1_sum / counts.len() as f64,
+        confusion,
+    }
+}
+
+/// Compute Cohen's kappa between two prediction sequences: their agreement
+/// corrected for the agreement expected by chance given each sequence's
+/// label frequencies. 1 means perfect agreement, 0 chance-level agreement.
+pub fn cohen_kappa<T: Hash + Eq>(a: &[T], b: &[T]) -> f64 {
+    let observed = prediction_agreement(a, b);
+    let n = a.len() as f64;
+    let ctr_a = Counter::with_iterator(a.iter());
+    let ctr_b = Counter::with_iterator(b.iter());
+    let expected = ctr_a.iter().fold(0f64, |acc, (label, &count)| {
+        let other = ctr_b.get(label).unwrap_or(0);
+        acc + (count as f64 / n) * (other as f64 / n)
+    });
+    if expected == 1.0 {
+        // Both sequences use a single, identical label.
+        return 1.0;
+    }
+    (observed - expected) / (1.0 - expected)
+}
+
+/// Compute the adjusted Rand index between two clusterings of the same
+/// datapoints: the fraction of pairs of points on which they agree (both
+/// together or both apart), corrected for chance. 1 means the same
+/// clustering, whatever the cluster names; random clusterings score near
+/// 0, and worse than random ones below it.
+pub fn adjusted_rand_index<T: Hash + Eq, U: Hash + Eq>(labels_true: &[T], labels_pred: &[U]) -> f64 {
+    let (joint, ctr_true, ctr_pred) = contingency(labels_true, labels_pred);
+    let pairs = |count: u64| (count * count.saturating_sub(1) / 2) as f64;
+    let index: f64 = joint.iter().map(|(_, &count)| pairs(count)).sum();
+    let sum_true: f64 = ctr_true.iter().map(|(_, &count)| pairs(count)).sum();
+    let sum_pred: f64 = ctr_pred.iter().map(|(_, &count)| pairs(count)).sum();
+    let expected = sum_true * sum_pred / pairs(labels_true.len() as u64);
+    let max = (sum_true + sum_pred) / 2.0;
+    if max == expected {
+        // Both clusterings are a single cluster, or all singletons.
+        return 1.0;
+    }
+    (index - expected) / (max - expected)
+}
+
+/// Compute the normalized mutual information between two clusterings of
+/// the same datapoints: how much knowing one tells about the other, in
+/// bits, divided by the mean of their entropies. 1 means the same
+/// clustering, whatever the cluster names, and 0 independent ones. Unlike
+/// `adjusted_rand_index()` it isn't corrected for chance, so random
+/// clusterings into many small clusters score above 0.
+pub fn normalized_mutual_info<T: Hash + Eq, U: Hash + Eq>(labels_true: &[T], labels_pred: &[U]) -> f64 {
+    let (joint, ctr_true, ctr_pred) = contingency(labels_true, labels_pred);
+    let n = labels_true.len() as f64;
+    let mutual_info: f64 = joint.iter().map(|(&(t, u), &count)| {
+        let expected = ctr_true.get(&t).unwrap_or(0) as f64 * ctr_pred.get(&u).unwrap_or(0) as f64 / n;
+        count as f64 / n * (count as f64 / expected).log2()
+    }).sum();
+    let mean_entropy = (entropy(labels_true) + entropy(labels_pred)) / 2.0;
+    if mean_entropy == 0.0 {
+        // Both clusterings are a single cluster.
+        return 1.0;
+    }
+    (mutual_info / mean_entropy).clamp(0.0, 1.0)
+}
+
+/// Count the co-occurrences of labels in two labelings of the same
+/// datapoints, along with each labeling's own counts.
+fn contingency<'a, T: Hash + Eq, U: Hash + Eq>(a: &'a [T], b: &'a [U]) -> (Counter<(&'a T, &'a U)>, Counter<&'a T>, Counter<&'a U>) {
+    assert_eq!(a.len(), b.len(), "labelings differ in length");
+    (Counter::with_iterator(a.iter().zip(b.iter())), Counter::with_iterator(a.iter()), Counter::with_iterator(b.iter()))
+}
+
+/// Compute the Shannon entropy of a label sequence's distribution, in
+/// bits. 0 means a single label; n equally frequent labels give log2(n).
+/// An empty sequence has entropy 0.
+pub fn entropy<T: Hash + Eq>(labels: &[T]) -> f64 {
+    let n = labels.len() as f64;
+    let ctr = Counter::with_iterator(labels.iter());
+    -ctr.iter().fold(0f64, |acc, (_, &count)| {
+        let p = count as f64 / n;
+        acc + p * p.log2()
+    })
+}
+
+/// Compute the information gain of splitting labeled examples on one
+/// feature at `threshold`: the entropy of `labels` minus the entropies of
+/// the two sides, weighted by their sizes. Examples with
+/// `feature <= threshold` go to the left. The gain is 0 for a split that
+/// tells nothing about the labels, and `entropy(labels)` for one that
+/// separates them completely.
+pub fn information_gain<T: Hash + Eq>(feature: &[f64], labels: &[T], threshold: f64) -> f64 {
+    assert_eq!(feature.len(), labels.len(), "feature and labels differ in length");
+    let (mut left, mut right) = (Vec::new(), Vec::new());
+    for (&v, label) in feature.iter().zip(labels.iter()) {
+        if v <= threshold {
+            left.push(label);
+        } else {
+            right.push(label);
+        }
+    }
+    let n = labels.len() as f64;
+    entropy(labels) - (left.len() as f64 / n) * entropy(&left) - (right.len() as f64 / n) * entropy(&right)
+}
+
+/// Compute the silhouette coefficient of every point of a clustering: how
+/// much closer, on average, the point is to the rest of its own cluster
+/// (a) than to the nearest other cluster (b), as `(b - a) / max(a, b)`.
+/// Values range from -1 (probably misassigned) to 1 (well inside its
+/// cluster). Points alone in their cluster get 0. There must be at least
+/// two clusters.
+pub fn silhouette_samples<T: PartialEq>(data: &[Vec<f64>], labels: &[T], metric: Metric) -> Vec<f64> {
+    assert_eq!(data.len(), labels.len(), "data and labels differ in length");
+    let mut clusters: Vec<&T> = Vec::new();
+    let assignment: Vec<usize> = labels.iter().map(|label| {
+        match clusters.iter().position(|&c| c == label) {
+            Some(c) => c,
+            None    => {
+                clusters.push(label);
+                clusters.len() - 1
+            },
+        }
+    }).collect();
+    assert!(clusters.len() >= 2, "silhouette needs at least two clusters");
+    let sizes: Vec<usize> = (0..clusters.len()).map(|c| assignment.iter().filter(|&&a| a == c).count()).collect();
+
+    let dist = pairwise_distances(data, metric);
+    dist.iter().zip(assignment.iter()).map(|(row, &own)| {
+        if sizes[own] == 1 {
+            return 0.0;
+        }
+        let mut sums = vec![0f64; clusters.len()];
+        for (d, &c) in row.iter().zip(assignment.iter()) {
+            sums[c] += d;
+        }
+        let a = sums[own] / (sizes[own] - 1) as f64;
+        let b = (0..clusters.len()).filter(|&c| c != own)
+            .map(|c| sums[c] / sizes[c] as f64)
+            .fold(f64::INFINITY, f64::min);
+        if a == b { 0.0 } else { (b - a) / a.max(b) }
+    }).collect()
+}
+
+/// Compute the mean silhouette coefficient of a clustering (see
+/// `silhouette_samples()`).
+pub fn silhouette_score<T: PartialEq>(data: &[Vec<f64>], labels: &[T], metric: Metric) -> f64 {
+    let samples = silhouette_samples(data, labels, metric);
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Compute the Davies-Bouldin index of a clustering: for each cluster, the
+/// worst ratio of the two clusters' scatter to the distance between their
+/// centroids over every other cluster, averaged over the clusters. A
+/// cluster's scatter is the mean Euclidean distance of its points to its
+/// centroid. Lower is better; 0 means every cluster is a single point.
+/// There must be at least two clusters.
+pub fn davies_bouldin_index<T: PartialEq>(data: &[Vec<f64>], labels: &[T]) -> f64 {
+    let clusters = clusters(data, labels);
+    assert!(clusters.len() >= 2, "Davies-Bouldin index needs at least two clusters");
+    let centroids: Vec<Vec<f64>> = clusters.iter().map(|(_, members)| column_means(members)).collect();
+    let scatter: Vec<f64> = clusters.iter().zip(centroids.iter()).map(|((_, members), centroid)| {
+        members.iter().map(|x| euclidean_distance(x, centroid)).sum::<f64>() / members.len() as f64
+    }).collect();
+    let k = clusters.len();
+    (0..k).map(|i| {
+        (0..k).filter(|&j| j != i)
+            .map(|j| (scatter[i] + scatter[j]) / euclidean_distance(&centroids[i], &centroids[j]))
+            .fold(0f64, f64::max)
+    }).sum::<f64>() / k as f64
+}
+
+/// Compute the Calinski-Harabasz score of a clustering: the dispersion of
+/// the cluster centroids around the overall mean, weighted by cluster size,
+/// over the dispersion of points around their own centroids, each divided
+/// by its degrees of freedom (k - 1 and n - k for k clusters of n points).
+/// Dispersions are sums of squared Euclidean distances. Higher is better;
+/// the score is infinite if every point lies on its centroid. There must be
+/// at least two clusters, and fewer clusters than points.
+pub fn calinski_harabasz_score<T: PartialEq>(data: &[Vec<f64>], labels: &[T]) -> f64 {
+    let clusters = clusters(data, labels);
+    let (n, k) = (data.len(), clusters.len());
+    assert!(k >= 2 && k < n, "Calinski-Harabasz score needs between 2 and n - 1 clusters");
+    let mean = column_means(data);
+    let (mut between, mut within) = (0f64, 0f64);
+    for (_, members) in &clusters {
+        let centroid = column_means(members);
+        between += members.len() as f64 * squared_distance(&centroid, &mean);
+        within += members.iter().map(|x| squared_distance(x, &centroid)).sum::<f64>();
+    }
+    (between / (k - 1) as f64) / (within / (n - k) as f64)
+}
+
+/// Group the datapoints by label, in order of first appearance.
+fn clusters<'a, T: PartialEq>(data: &[Vec<f64>], labels: &'a [T]) -> Vec<(&'a T, Vec<Vec<f64>>)> {
+    assert_eq!(data.len(), labels.len(), "data and labels differ in length");
+    let mut clusters: Vec<(&T, Vec<Vec<f64>>)> = Vec::new();
+    for (x, label) in data.iter().zip(labels.iter()) {
+        match clusters.iter().position(|&(c, _)| c == label) {
+            Some(c) => clusters[c].1.push(x.clone()),
+            None    => clusters.push((label, vec![x.clone()])),
+        }
+    }
+    clusters
+}
+
+/// Estimate the intrinsic dimension of `data`, the number of degrees of
+/// freedom it really varies in, with the maximum-likelihood estimator of
+/// Levina and Bickel (2004) over each point's `k` nearest neighbours. The
+/// per-point estimates are averaged. Data lying on a d-dimensional
+/// manifold gives estimates near d, however many features it has.
+/// Duplicate points should be removed first, as zero distances make the
+/// estimate degenerate.
+pub fn intrinsic_dimension(data: &[Vec<f64>], k: usize) -> f64 {
+    assert!(k >= 2 && k < data.len(), "k must be in [2, n)");
+    let dist = pairwise_distances(data, Metric::Euclidean);
+    let estimates: Vec<f64> = dist.into_iter().enumerate().map(|(i, mut row)| {
+        row.swap_remove(i);
+        row.sort_by(|a, b| a.total_cmp(b));
+        let t_k = row[k - 1];
+        let log_sum: f64 = row[..k - 1].iter().map(|&t_j| (t_k / t_j).ln()).sum();
+        (k - 1) as f64 / log_sum
+    }).collect();
+    estimates.iter().sum::<f64>() / estimates.len() as f64
+}
+
+/// Compute the Hopkins statistic of `data`, a measure of its clustering
+/// tendency. `sample_size` real points and as many points drawn uniformly
+/// from the data's bounding box are compared by their distance to the
+/// nearest data point. Values near 1 indicate clustered data, values around
+/// 0.5 data no more clustered than uniform noise.
+pub fn hopkins_statistic(data: &[Vec<f64>], sample_size: usize, random_state: Option<RandomState>) -> f64 {
+    assert!(sample_size > 0 && sample_size < data.len(), "sample size must be in [1, n)");
+    let dim = data[0].len();
+    let mut lo = data[0].clone();
+    let mut hi = data[0].clone();
+    for x in data {
+        for j in 0..dim {
+            lo[j] = lo[j].min(x[j]);
+            hi[j] = hi[j].max(x[j]);
+        }
+    }
+
+    let nearest = |x: &[f64], skip: Option<usize>| {
+        data.iter().enumerate()
+            .filter(|&(i, _)| Some(i) != skip)
+            .map(|(_, y)| euclidean_distance(x, y))
+            .fold(f64::INFINITY, f64::min)
+    };
+
+    let mut state = RandomState::resolve(random_state);
+    let rng = state.rng();
+    // Partial Fisher-Yates shuffle to sample real points without
+    // replacement.
+    let mut indices: Vec<usize> = (0..data.len()).collect();
+    let mut real_sum = 0f64;
+    let mut random_sum = 0f64;
+    for s in 0..sample_size {
+        let pick = s + rng.gen_range(data.len() - s);
+        indices.swap(s, pick);
+        let i = indices[s];
+        real_sum += nearest(&data[i], Some(i));
+
+        let point: Vec<f64> = (0..dim).map(|j| lo[j] + rng.next_f64() * (hi[j] - lo[j])).collect();
+        random_sum += nearest(&point, None);
+    }
+    random_sum / (random_sum + real_sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::knn::KNNClassifier;
+    use crate::util::Rng;
+
+    #[test]
+    fn test_identical_predictions() {
+        let a = vec!["x", "y", "z", "x", "y"];
+        assert_eq!(prediction_agreement(&a, &a), 1.0);
+        assert_eq!(cohen_kappa(&a, &a), 1.0);
+    }
+
+    #[test]
+    fn test_independent_predictions() {
+        // Every combination of labels occurs equally often, so the observed
+        // agreement is exactly what chance predicts.
+        let a = vec![0, 0, 1, 1];
+        let b = vec![0, 1, 0, 1];
+        assert_eq!(prediction_agreement(&a, &b), 0.5);
+        assert!(cohen_kappa(&a, &b).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_compare_models() {
+        let train: Vec<Vec<f64>> = vec![vec![0.0], vec![0.3], vec![0.35], vec![0.4], vec![0.5], vec![1.0]];
+        let labels = vec!["a", "b", "b", "b", "a", "a"];
+        let test = vec![vec![0.1], vec![0.38], vec![0.9]];
+        let truth = vec!["a", "b", "a"];
+
+        let models: Vec<NamedModel<Vec<f64>, &str>> = vec![
+            ("knn-1".to_string(), Box::new(KNNClassifier::new(1))),
+            ("knn-5".to_string(), Box::new(KNNClassifier::new(5))),
+        ];
+        let results = compare_models(models, &train, &labels, &test, &truth);
+
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["knn-1", "knn-5"]);
+        assert!(results.iter().all(|&(_, acc)| (0.0..=1.0).contains(&acc)));
+        assert_eq!(results[0].1, 1.0);
+
+        let mut knn = KNNClassifier::new(1);
+        assert_eq!(score(&knn, &test, &truth), None);
+        knn.fit(train, labels);
+        assert_eq!(score(&knn, &test, &truth), Some(1.0));
+    }
+
+    #[test]
+    fn test_mcnemar_identical() {
+        let truth = vec![0, 1, 1, 0, 1];
+        let preds = vec![0, 1, 0, 0, 0];
+        let result = mcnemar_test(&truth, &preds, &preds);
+        assert_eq!((result.a_only_correct, result.b_only_correct), (0, 0));
+        assert!((result.p_value - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_mcnemar_one_sided() {
+        // A is right on 20 examples B gets wrong, B on only 1.
+        let truth = vec![1; 40];
+        let mut pred_a = vec![1; 40];
+        let mut pred_b = vec![1; 40];
+        for p in pred_b.iter_mut().take(20) {
+            *p = 0;
+        }
+        pred_a[39] = 0;
+        let result = mcnemar_test(&truth, &pred_a, &pred_b);
+        assert_eq!((result.a_only_correct, result.b_only_correct), (20, 1));
+        assert!(result.exact);
+        // 2 * (1 + 21) / 2^21
+        assert!((result.p_value - 44.0 / 2097152.0).abs() < 1e-15);
+        assert!((result.statistic - 324.0 / 21.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_mcnemar_exact_switch() {
+        let discordant = |a: usize, b: usize| {
+            let truth = vec![1; a + b];
+            let pred_a: Vec<i32> = (0..a + b).map(|i| if i < a { 1 } else { 0 }).collect();
+            let pred_b: Vec<i32> = (0..a + b).map(|i| if i < a { 0 } else { 1 }).collect();
+            mcnemar_test(&truth, &pred_a, &pred_b)
+        };
+        let below = discordant(16, 8);
+        let above = discordant(17, 8);
+        assert!(below.exact);
+        assert!(!above.exact);
+        // Chi-square: (|17 - 8| - 1)^2 / 25 = 2.56, p = erfc(sqrt(1.28)).
+        assert!((above.statistic - 2.56).abs() < 1e-12);
+        assert!((above.p_value - 0.109_599).abs() < 1e-5, "{}", above.p_value);
+        // The exact p-value stays close to the approximation at the switch.
+        assert!((below.p_value - above.p_value).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_student_t5() {
+        assert!((student_t5_two_sided(0.0) - 1.0).abs() < 1e-12);
+        // The 97.5% quantile of t with 5 degrees of freedom.
+        assert!((student_t5_two_sided(2.570_582) - 0.05).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_paired_cv_ttest() {
+        let mut rng = Rng::new(5);
+        let data: Vec<Vec<f64>> = (0..60).map(|i| vec![(i % 2) as f64 * 2.0 + rng.next_f64()]).collect();
+        let labels: Vec<i32> = (0..60).map(|i| i % 2).collect();
+
+        let mut a = KNNClassifier::new(1);
+        let mut b = KNNClassifier::new(3);
+        let result = paired_cv_ttest(&mut a, &mut b, &data, &labels, None);
+        assert_eq!(result.fold_scores.len(), 10);
+        assert!(result.fold_scores.iter().all(|&(sa, sb)| sa == 1.0 && sb == 1.0));
+        assert_eq!(result.statistic, 0.0);
+        assert_eq!(result.p_value, 1.0);
+        assert_eq!(paired_cv_ttest(&mut a, &mut b, &data, &labels, None), result);
+
+        // With noisy labels, k = 1 overfits and the classifiers disagree.
+        let noisy: Vec<i32> = labels.iter().map(|&l| if rng.next_f64() < 0.3 { 1 - l } else { l }).collect();
+        let result = paired_cv_ttest(&mut a, &mut KNNClassifier::new(15), &data, &noisy, Some(RandomState::new(2)));
+        assert!(result.fold_scores.iter().any(|&(sa, sb)| sa != sb));
+        assert!(result.statistic.is_finite());
+        assert!(result.p_value > 0.0 && result.p_value <= 1.0);
+    }
+
+    #[test]
+    fn test_per_class_accuracy() {
+        let y_true = ["a", "a", "a", "a", "b", "b", "c", "c", "c"];
+        let y_pred = ["a", "a", "a", "b", "b", "c", "c", "c", "d"];
+        let matrix = confusion_matrix(&y_true, &y_pred);
+        assert_eq!(matrix.labels, vec!["a", "b", "c", "d"]);
+        assert_eq!(matrix.counts, vec![vec![3, 1, 0, 0], vec![0, 1, 1, 0], vec![0, 0, 2, 1], vec![0, 0, 0, 0]]);
+
+        let recall = per_class_accuracy(&y_true, &y_pred);
+        assert_eq!(recall.len(), 3);
+        assert_eq!(recall["a"], 0.75);
+        assert_eq!(recall["b"], 0.5);
+        assert_eq!(recall["c"], 2.0 / 3.0);
+    }
+
+    #[test]
+    fn test_accuracy_with_ci() {
+        assert!((normal_critical_value(0.95) - 1.959964).abs() < 1e-5);
+        // 80% accuracy on 20 and on 2000 predictions.
+        let small_true = vec![1; 20];
+        let small_pred: Vec<i32> = (0..20).map(|i| (i % 5 != 0) as i32).collect();
+        let large_true = vec![1; 2000];
+        let large_pred: Vec<i32> = (0..2000).map(|i| (i % 5 != 0) as i32).collect();
+        let (small_acc, small_lo, small_hi) = accuracy_with_ci(&small_true, &small_pred, 0.95);
+        let (large_acc, large_lo, large_hi) = accuracy_with_ci(&large_true, &large_pred, 0.95);
+        assert_eq!((small_acc, large_acc), (0.8, 0.8));
+        assert!(small_lo < 0.8 && small_hi > 0.8 && large_lo < 0.8 && large_hi > 0.8);
+        assert!(large_hi - large_lo < (small_hi - small_lo) / 5.0);
+        // The textbook Wilson interval for 16 of 20.
+        assert!((small_lo - 0.5840).abs() < 1e-4 && (small_hi - 0.9193).abs() < 1e-4, "{} {}", small_lo, small_hi);
+
+        // Perfect predictions still get an interval below 1.
+        let (acc, lo, hi) = accuracy_with_ci(&[1, 2, 3], &[1, 2, 3], 0.9);
+        assert_eq!((acc, hi), (1.0, 1.0));
+        assert!(lo < 1.0 && lo > 0.0);
+    }
+
+    #[test]
+    fn test_weighted_confusion_matrix() {
+        let y_true = ["a", "a", "a", "b", "b"];
+        let y_pred = ["a", "b", "a", "b", "a"];
+        let unit = ConfusionMatrix::weighted(&y_true, &y_pred, &[1.0; 5]);
+        assert_eq!(unit.labels, vec!["a", "b"]);
+        assert_eq!(unit.counts, vec![vec![2.0, 1.0], vec![1.0, 1.0]]);
+
+        // The a mistaken for b weighs five times as much.
+        let weighted = ConfusionMatrix::weighted(&y_true, &y_pred, &[1.0, 5.0, 1.0, 1.0, 1.0]);
+        assert_eq!(weighted.counts, vec![vec![2.0, 5.0], vec![1.0, 1.0]]);
+    }
+
+    #[test]
+    fn test_evaluation_report() {
+        let y_true = ["a", "a", "a", "a", "b", "b", "c", "c", "c"];
+        let y_pred = ["a", "a", "a", "b", "b", "c", "c", "c", "d"];
+        let report = evaluation_report(&y_true, &y_pred);
+        assert_eq!(report.accuracy, 6.0 / 9.0);
+        // F1 of a: 6/7, b: 2/4, c: 4/6, d: 0.
+        assert!((report.macro_f1 - (6.0 / 7.0 + 0.5 + 2.0 / 3.0) / 4.0).abs() < 1e-12);
+        assert_eq!(report.confusion, confusion_matrix(&y_true, &y_pred));
+    }
+
+    #[test]
+    fn test_information_gain() {
+        let feature = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        let labels = ["a", "a", "a", "a", "b", "b", "b", "b"];
+        assert_eq!(entropy(&labels), 1.0);
+        assert_eq!(information_gain(&feature, &labels, 4.5), 1.0);
+        // Each side keeps the even mix of the whole.
+        let mixed = ["a", "b", "a", "b", "a", "b", "a", "b"];
+        assert!(information_gain(&feature, &mixed, 4.5).abs() < 1e-12);
+        // A split leaving one side empty gains nothing either.
+        assert_eq!(information_gain(&feature, &labels, 10.0), 0.0);
+    }
+
+    #[test]
+    fn test_adjusted_rand_index() {
+        let labels = [0, 0, 1, 1, 2, 2, 2];
+        assert_eq!(adjusted_rand_index(&labels, &labels), 1.0);
+        assert_eq!(adjusted_rand_index(&labels, &["b", "b", "c", "c", "a", "a", "a"]), 1.0);
+        // The textbook example: index 2, expected 18 / 15, max 4.5.
+        let ari = adjusted_rand_index(&[0, 0, 0, 1, 1, 1], &[0, 0, 1, 1, 2, 2]);
+        assert!((ari - (2.0 - 1.2) / (4.5 - 1.2)).abs() < 1e-12, "{}", ari);
+
+        let mut rng = Rng::new(3);
+        let truth: Vec<usize> = (0..2000).map(|_| rng.gen_range(4)).collect();
+        let random: Vec<usize> = (0..2000).map(|_| rng.gen_range(4)).collect();
+        let ari = adjusted_rand_index(&truth, &random);
+        assert!(ari.abs() < 0.01, "{}", ari);
+    }
+
+    #[test]
+    fn test_normalized_mutual_info() {
+        let labels = [0, 0, 1, 1, 2, 2, 2];
+        assert!((normalized_mutual_info(&labels, &labels) - 1.0).abs() < 1e-12);
+        assert!((normalized_mutual_info(&labels, &["b", "b", "c", "c", "a", "a", "a"]) - 1.0).abs() < 1e-12);
+        // Knowing the first labeling pins down the second, but not the
+        // other way round: MI = 1 bit, entropies 1 and 2 bits.
+        let nmi = normalized_mutual_info(&[0, 0, 1, 1], &[0, 1, 2, 3]);
+        assert!((nmi - 1.0 / 1.5).abs() < 1e-12, "{}", nmi);
+
+        let mut rng = Rng::new(5);
+        let truth: Vec<usize> = (0..2000).map(|_| rng.gen_range(3)).collect();
+        let random: Vec<usize> = (0..2000).map(|_| rng.gen_range(3)).collect();
+        let nmi = normalized_mutual_info(&truth, &random);
+        assert!(nmi < 0.01, "{}", nmi);
+    }
+
+    #[test]
+    fn test_silhouette() {
+        let data = vec![vec![0.0], vec![1.0], vec![5.0], vec![6.0], vec![20.0], vec![2.5]];
+        let labels = vec!["a", "a", "b", "b", "c", "a"];
+        let samples = silhouette_samples(&data, &labels, Metric::Euclidean);
+
+        // Point 0: a = (1 + 2.5) / 2, b = (5 + 6) / 2 to cluster "b".
+        assert!((samples[0] - (5.5 - 1.75) / 5.5).abs() < 1e-12);
+        assert_eq!(samples[4], 0.0);
+        // Point 5 sits between "a" and "b": a = 2, b = 3.
+        assert!((samples[5] - 1.0 / 3.0).abs() < 1e-12);
+
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        assert_eq!(silhouette_score(&data, &labels, Metric::Euclidean), mean);
+    }
+
+    #[test]
+    fn test_davies_bouldin_index() {
+        let mut rng = Rng::new(8);
+        let blobs = |rng: &mut Rng, spacing: f64| -> Vec<Vec<f64>> {
+            (0..90).map(|i| vec![(i % 3) as f64 * spacing + rng.next_f64(), rng.next_f64()]).collect()
+        };
+        let labels: Vec<usize> = (0..90).map(|i| i % 3).collect();
+        let separated = davies_bouldin_index(&blobs(&mut rng, 10.0), &labels);
+        let overlapping = davies_bouldin_index(&blobs(&mut rng, 0.5), &labels);
+        assert!(separated < 0.2, "{}", separated);
+        assert!(overlapping > 5.0 * separated, "{} vs {}", overlapping, separated);
+
+        // Two clusters of two points each, one unit apart, with centroids
+        // four units apart.
+        let data = vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![4.0, 0.0], vec![4.0, 1.0]];
+        assert_eq!(davies_bouldin_index(&data, &["a", "a", "b", "b"]), 0.25);
+    }
+
+    #[test]
+    fn test_calinski_harabasz_score() {
+        let mut rng = Rng::new(12);
+        let blobs = |rng: &mut Rng, spacing: f64, spread: f64| -> Vec<Vec<f64>> {
+            (0..90).map(|i| vec![(i % 3) as f64 * spacing + spread * rng.next_f64(), spread * rng.next_f64()]).collect()
+        };
+        let labels: Vec<usize> = (0..90).map(|i| i % 3).collect();
+        let tight = calinski_harabasz_score(&blobs(&mut rng, 10.0, 0.5), &labels);
+        let loose = calinski_harabasz_score(&blobs(&mut rng, 10.0, 4.0), &labels);
+        let close = calinski_harabasz_score(&blobs(&mut rng, 1.0, 0.5), &labels);
+        assert!(tight > 10.0 * loose, "{} vs {}", tight, loose);
+        assert!(tight > 10.0 * close, "{} vs {}", tight, close);
+
+        // Centroids at 0 and 4 around a mean of 2: between = 4 * 4 = 16 over
+        // 1 degree of freedom, within = 4 * 0.25 = 1 over 2.
+        let data = vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![4.0, 0.0], vec![4.0, 1.0]];
+        assert_eq!(calinski_harabasz_score(&data, &["a", "a", "b", "b"]), 32.0);
+    }
+
+    #[test]
+    fn test_intrinsic_dimension() {
+        // A 2-D square mapped linearly into 5 dimensions.
+        let mut rng = Rng::new(4);
+        let data: Vec<Vec<f64>> = (0..500).map(|_| {
+            let (u, v) = (rng.next_f64(), rng.next_f64());
+            vec![u, v, u + v, u - 2.0 * v, 3.0 * u]
+        }).collect();
+        let estimate = intrinsic_dimension(&data, 10);
+        assert!((estimate - 2.0).abs() < 0.3, "estimate {}", estimate);
+
+        // A 1-D helix in 3 dimensions.
+        let helix: Vec<Vec<f64>> = (0..400).map(|_| {
+            let t = rng.next_f64() * 12.0;
+            vec![t.cos(), t.sin(), 0.2 * t]
+        }).collect();
+        let estimate = intrinsic_dimension(&helix, 10);
+        assert!((estimate - 1.0).abs() < 0.2, "estimate {}", estimate);
+    }
+
+    #[test]
+    fn test_hopkins_statistic() {
+        let mut rng = Rng::new(7);
+        let mut clustered = Vec::new();
+        for &(cx, cy) in &[(0.0, 0.0), (10.0, 10.0), (0.0, 10.0)] {
+            for _ in 0..50 {
+                clustered.push(vec![cx + rng.next_f64() * 0.5, cy + rng.next_f64() * 0.5]);
+            }
+        }
+        let uniform: Vec<Vec<f64>> = (0..150).map(|_| {
+            vec![rng.next_f64() * 10.0, rng.next_f64() * 10.0]
+        }).collect();
+
+        let h_clustered = hopkins_statistic(&clustered, 20, Some(RandomState::new(1)));
+        let h_uniform = hopkins_statistic(&uniform, 20, Some(RandomState::new(1)));
+        assert!(h_clustered > 0.9, "clustered: {}", h_clustered);
+        assert!(h_uniform > 0.35 && h_uniform < 0.65, "uniform: {}", h_uniform);
+    }
+
+    #[test]
+    fn test_partial_agreement() {
+        let a = vec![1, 1, 1, 0, 0, 0];
+        let b = vec![1, 1, 0, 0, 0, 1];
+        assert!((prediction_agreement(&a, &b) - 4.0 / 6.0).abs() < 1e-12);
+        assert!((cohen_kappa(&a, &b) - 1.0 / 3.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,607 @@
+use super::core::Classifier;
+use super::metrics::score;
+use super::parallel;
+use super::util::RandomState;
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Errors raised while evaluating estimators.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CvError {
+    /// Training or scoring panicked. `candidate` names the grid point, if
+    /// any; `fold` is zero-based.
+    Panicked { candidate: Option<String>, fold: usize, message: String },
+}
+
+impl fmt::Display for CvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CvError::Panicked { candidate: Some(ref candidate), fold, ref message } =>
+                write!(f, "candidate {:?}, fold {} panicked: {}", candidate, fold, message),
+            CvError::Panicked { candidate: None, fold, ref message } =>
+                write!(f, "fold {} panicked: {}", fold, message),
+        }
+    }
+}
+
+impl Error for CvError {}
+
+/// Split the indices `0..n` into `n_folds` shuffled folds of (nearly) equal
+/// size, returning a (training indices, test indices) pair per fold. Each
+/// index is in the test set of exactly one fold; both lists are in
+/// increasing order.
+pub fn k_fold(n: usize, n_folds: usize, random_state: Option<RandomState>) -> Vec<(Vec<usize>, Vec<usize>)> {
+    assert!(n_folds >= 2 && n_folds <= n, "n_folds must be in [2, n]");
+    let mut state = RandomState::resolve(random_state);
+    let rng = state.rng();
+    let mut indices: Vec<usize> = (0..n).collect();
+    for i in (1..n).rev() {
+        indices.swap(i, rng.gen_range(i + 1));
+    }
+    let mut fold_of = vec![0; n];
+    for (pos, &i) in indices.iter().enumerate() {
+        fold_of[i] = pos % n_folds;
+    }
+    (0..n_folds).map(|fold| {
+        let (test, train): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| fold_of[i] == fold);
+        (train, test)
+    }).collect()
+}
+
+/// Split labeled examples into `n_folds` folds that keep groups together:
+/// all examples sharing a value in `groups` (say, rows of one user) land in
+/// the test set of the same fold, so no group is seen in both training and
+/// testing. Returns a (training indices, test indices) pair per fold, both
+/// in increasing order. Groups are dealt, largest first, to the fold with
+/// the fewest examples so far, which balances the fold sizes as well as
+/// the groups allow; the split is deterministic.
+pub fn group_kfold_split<E, T, G>(data: &[E], labels: &[T], groups: &[G], n_folds: usize) -> Vec<(Vec<usize>, Vec<usize>)>
+    where G: Hash + Eq
+{
+    assert_eq!(data.len(), labels.len(), "data and labels differ in length");
+    assert_eq!(data.len(), groups.len(), "data and groups differ in length");
+    // Each group's rows, in order of first appearance.
+    let mut index: HashMap<&G, usize> = HashMap::new();
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    for (i, group) in groups.iter().enumerate() {
+        let g = *index.entry(group).or_insert_with(|| {
+            members.push(Vec::new());
+            members.len() - 1
+        });
+        members[g].push(i);
+    }
+    assert!(n_folds >= 2 && n_folds <= members.len(), "n_folds must be in [2, number of groups]");
+    // Largest first; the sort is stable, so equal sizes keep their order.
+    members.sort_by_key(|rows| Reverse(rows.len()));
+
+    let mut fold_of = vec![0; groups.len()];
+    let mut sizes = vec![0; n_folds];
+    for rows in &members {
+        let fold = (0..n_folds).min_by_key(|&f| sizes[f]).unwrap();
+        sizes[fold] += rows.len();
+        for &i in rows {
+            fold_of[i] = fold;
+        }
+    }
+    (0..n_folds).map(|fold| {
+        let (test, train): (Vec<usize>, Vec<usize>) = (0..groups.len()).partition(|&i| fold_of[i] == fold);
+        (train, test)
+    }).collect()
+}
+
+/// Estimate a classifier's accuracy by k-fold cross-validation, returning
+/// the test accuracy of each fold. Every fold trains a fresh clone of
+/// `clf`. With the `parallel` feature the folds run on separate threads;
+/// the folds are drawn up front, so the scores are identical either way. A
+/// panic inside a fold is returned as an error naming the fold.
+pub fn cross_val_score<C, E, T>(clf: &C,
+                                data: &[E],
+                                labels: &[T],
+                                n_folds: usize,
+                                random_state: Option<RandomState>) -> Result<Vec<f64>, CvError>
+    where C: Classifier<ExampleType = E, LabelType = T> + Clone + Sync,
+          E: Clone + Sync,
+          T: PartialEq + Clone + Sync
+{
+    let folds = k_fold(data.len(), n_folds, random_state);
+    let tasks: Vec<(usize, &C, Option<RandomState>)> = (0..n_folds).map(|fold| (fold, clf, None)).collect();
+    run_tasks(&tasks, &folds, data, labels, None, false).into_iter().enumerate().map(|(fold, result)| {
+        result.map(|(_, test)| test).map_err(|message| CvError::Panicked { candidate: None, fold, message })
+    }).collect()
+}
+
+/// Compute a learning curve: how a classifier's accuracy changes with the
+/// amount of training data. For each fraction in `fractions`, every fold of
+/// a k-fold split trains a fresh clone of `clf` on that fraction of its
+/// training indices, drawn at random, and is scored on those same examples
+/// and on the fold's test set. Returns (fraction, mean training accuracy,
+/// mean test accuracy) per fraction. The subsets of one fold are nested, so
+/// larger fractions only add examples. A training accuracy far above the
+/// test accuracy points to overfitting; both being low to underfitting.
+/// Runs in parallel like `cross_val_score()`; a panic is returned as an
+/// error naming the fraction and fold.
+pub fn learning_curve<C, E, T>(clf: &C,
+                               data: &[E],
+                               labels: &[T],
+                               fractions: &[f64],
+                               n_folds: usize,
+                               random_state: Option<RandomState>) -> Result<Vec<(f64, f64, f64)>, CvError>
+    where C: Classifier<ExampleType = E, LabelType = T> + Clone + Sync,
+          E: Clone + Sync,
+          T: PartialEq + Clone + Sync
+{
+    assert!(fractions.iter().all(|&f| f > 0.0 && f <= 1.0), "fractions must be in (0, 1]");
+    let mut state = RandomState::resolve(random_state);
+    let folds = k_fold(data.len(), n_folds, Some(state.split()));
+    let rng = state.rng();
+    let shuffled: Vec<Vec<usize>> = folds.iter().map(|(train, _)| {
+        let mut train = train.clone();
+        for i in (1..train.len()).rev() {
+            train.swap(i, rng.gen_range(i + 1));
+        }
+        train
+    }).collect();
+
+    // One (subset, test set) pair per fraction and fold.
+    let mut subsets = Vec::with_capacity(fractions.len() * n_folds);
+    for &fraction in fractions {
+        for ((_, test), train) in folds.iter().zip(shuffled.iter()) {
+            let size = ((fraction * train.len() as f64 + 0.5) as usize).clamp(1, train.len());
+            let mut subset = train[..size].to_vec();
+            subset.sort();
+            subsets.push((subset, test.clone()));
+        }
+    }
+    let tasks: Vec<(usize, &C, Option<RandomState>)> = (0..subsets.len()).map(|i| (i, clf, None)).collect();
+    let results = run_tasks(&tasks, &subsets, data, labels, None, true);
+
+    let mut curve = Vec::with_capacity(fractions.len());
+    for (&fraction, chunk) in fractions.iter().zip(results.chunks(n_folds)) {
+        let (mut train_sum, mut test_sum) = (0f64, 0f64);
+        for (fold, result) in chunk.iter().enumerate() {
+            match result {
+                Ok((train, test)) => {
+                    train_sum += train.unwrap_or(0.0);
+                    test_sum += test;
+                },
+                Err(message) => return Err(CvError::Panicked {
+                    candidate: Some(format!("fraction={}", fraction)),
+                    fold,
+                    message: message.clone(),
+                }),
+            }
+        }
+        curve.push((fraction, train_sum / n_folds as f64, test_sum / n_folds as f64));
+    }
+    Ok(curve)
+}
+
+/// Gives a classifier the random state it should use, e.g. by setting the
+/// `random_state` of its `EarlyStopping`.
+pub type RandomStateSetter<C> = Box<dyn Fn(&mut C, RandomState) + Send + Sync>;
+
+/// Picks the best of several configured classifiers by cross-validated
+/// accuracy. Every candidate is evaluated on the same folds.
+pub struct GridSearch<C> {
+    candidates: Vec<(String, C)>,
+    n_folds: usize,
+    random_state: Option<RandomState>,
+    task_random_state: Option<RandomStateSetter<C>>,
+    early_stopping: Option<f64>,
+    scores: Option<Vec<Vec<f64>>>,
+}
+
+impl<C> GridSearch<C> {
+    /// Construct a new GridSearch over named candidates, using `n_folds`
+    /// folds.
+    pub fn new(candidates: Vec<(String, C)>, n_folds: usize) -> GridSearch<C> {
+        GridSearch { candidates, n_folds, random_state: None, task_random_state: None, early_stopping: None, scores: None }
+    }
+
+    /// Set the randomness used to draw the folds, and the ones given to
+    /// the tasks by `set_task_random_state()`.
+    pub fn set_random_state(&mut self, random_state: Option<RandomState>) {
+        self.random_state = random_state;
+    }
+
+    /// Give every (candidate, fold) task its own randomness, for candidates
+    /// with random choices of their own: `setter` is called on each task's
+    /// fresh clone of its candidate with a state split off the search's,
+    /// in candidate then fold order, so the scores don't depend on which
+    /// thread runs which task. None (the default) leaves the clones as
+    /// they are, every fold then sharing its candidate's own state.
+    pub fn set_task_random_state(&mut self, setter: Option<RandomStateSetter<C>>) {
+        self.task_random_state = setter;
+    }
+
+    /// Stop evaluating a candidate once the mean of its folds so far falls
+    /// more than `margin` below the best mean of the candidates evaluated
+    /// in full, or evaluate every fold with None (the default). A stopped
+    /// candidate keeps the scores of the folds it ran, and as these are
+    /// behind the best, `best()` never picks it. This saves time on sweeps
+    /// where many candidates are clearly worse, at the risk of dropping one
+    /// whose first folds were unlucky; a larger margin is more cautious.
+    /// Candidates are then evaluated in order, one fold at a time.
+    pub fn set_early_stopping(&mut self, margin: Option<f64>) {
+        assert!(margin.is_none_or(|m| m >= 0.0), "early stopping margin must be non-negative");
+        self.early_stopping = margin;
+    }
+
+    /// Cross-validate every candidate. With the `parallel` feature all
+    /// (candidate, fold) pairs run on separate threads, each training a
+    /// fresh clone of its candidate, unless stopping early; the scores are
+    /// identical to a serial run. A panic is returned as an error naming
+    /// the first failing candidate and fold.
+    pub fn fit<E, T>(&mut self, data: &[E], labels: &[T]) -> Result<(), CvError>
+        where C: Classifier<ExampleType = E, LabelType = T> + Clone + Sync,
+              E: Clone + Sync,
+              T: PartialEq + Clone + Sync
+    {
+        self.scores = None;
+        let mut state = RandomState::resolve(self.random_state.clone());
+        let folds = k_fold(data.len(), self.n_folds, Some(state.split()));
+        let setter = self.task_random_state.as_ref();
+        let tasks: Vec<(usize, &C, Option<RandomState>)> = self.candidates.iter()
+            .flat_map(|(_, clf)| (0..self.n_folds).map(move |fold| (fold, clf)))
+            .map(|(fold, clf)| (fold, clf, setter.map(|_| state.split())))
+            .collect();
+        let scores = match self.early_stopping {
+            Some(margin) => self.fit_early_stopping(&tasks, &folds, data, labels, margin)?,
+            None         => self.fit_all(&tasks, &folds, data, labels)?,
+        };
+        self.scores = Some(scores);
+        Ok(())
+    }
+
+    /// Cross-validate every candidate, all (candidate, fold) pairs at once.
+    fn fit_all<E, T>(&self,
+                     tasks: &[(usize, &C, Option<RandomState>)],
+                     folds: &[(Vec<usize>, Vec<usize>)],
+                     data: &[E],
+                     labels: &[T]) -> Result<Vec<Vec<f64>>, CvError>
+        where C: Classifier<ExampleType = E, LabelType = T> + Clone + Sync,
+              E: Clone + Sync,
+              T: PartialEq + Clone + Sync
+    {
+        let results = run_tasks(tasks, folds, data, labels, self.task_random_state.as_ref(), false);
+        let mut scores = vec![Vec::with_capacity(self.n_folds); self.candidates.len()];
+        for (task, result) in results.into_iter().enumerate() {
+            let (candidate, fold) = (task / self.n_folds, task % self.n_folds);
+            match result {
+                Ok((_, score)) => scores[candidate].push(score),
+                Err(message) => return Err(CvError::Panicked {
+                    candidate: Some(self.candidates[candidate].0.clone()),
+                    fold,
+                    message,
+                }),
+            }
+        }
+        Ok(scores)
+    }
+
+    /// Cross-validate the candidates in order, one fold at a time, dropping
+    /// each once it falls `margin` behind the best complete one.
+    fn fit_early_stopping<E, T>(&self,
+                                tasks: &[(usize, &C, Option<RandomState>)],
+                                folds: &[(Vec<usize>, Vec<usize>)],
+                                data: &[E],
+                                labels: &[T],
+                                margin: f64) -> Result<Vec<Vec<f64>>, CvError>
+        where C: Classifier<ExampleType = E, LabelType = T> + Clone + Sync,
+              E: Clone + Sync,
+              T: PartialEq + Clone + Sync
+    {
+        let mut scores = Vec::with_capacity(self.candidates.len());
+        let mut best_mean: Option<f64> = None;
+        for ((name, _), tasks) in self.candidates.iter().zip(tasks.chunks(self.n_folds)) {
+            let mut own = Vec::with_capacity(self.n_folds);
+            for (fold, task) in tasks.iter().enumerate() {
+                match run_tasks(std::slice::from_ref(task), folds, data, labels, self.task_random_state.as_ref(), false).remove(0) {
+                    Ok((_, score)) => own.push(score),
+                    Err(message) => return Err(CvError::Panicked { candidate: Some(name.clone()), fold, message }),
+                }
+                let mean = own.iter().sum::<f64>() / own.len() as f64;
+                if best_mean.is_some_and(|best| mean < best - margin) {
+                    break;
+                }
+            }
+            if own.len() == self.n_folds {
+                let mean = own.iter().sum::<f64>() / own.len() as f64;
+                best_mean = Some(best_mean.map_or(mean, |best| best.max(mean)));
+            }
+            scores.push(own);
+        }
+        Ok(scores)
+    }
+
+    /// Get each candidate's name and fold scores, in the order the
+    /// candidates were given. A candidate stopped early has fewer scores
+    /// than there are folds. Return None if called before `fit()`.
+    pub fn results(&self) -> Option<Vec<(&str, &[f64])>> {
+        let scores = self.scores.as_ref()?;
+        Some(self.candidates.iter().zip(scores.iter()).map(|((name, _), s)| (name.as_str(), &s[..])).collect())
+    }
+
+    /// Get the candidate with the highest mean score, with its name and
+    /// mean score. Ties go to the candidate given first. Return None if
+    /// called before `fit()`.
+    pub fn best(&self) -> Option<(&str, &C, f64)> {
+        let scores = self.scores.as_ref()?;
+        let mut best: Option<(usize, f64)> = None;
+        for (i, s) in scores.iter().enumerate() {
+            let mean = s.iter().sum::<f64>() / s.len() as f64;
+            if best.is_none_or(|(_, best_mean)| mean > best_mean) {
+                best = Some((i, mean));
+            }
+        }
+        best.map(|(i, mean)| (self.candidates[i].0.as_str(), &self.candidates[i].1, mean))
+    }
+}
+
+/// Train a clone of each task's classifier on its fold's training indices
+/// and score it on the test indices, and also on the training indices if
+/// `score_train` is set, catching panics. A task's random state, if any, is
+/// given to its clone through `set_random_state`. Results are (training
+/// score, test score) pairs in task order.
+fn run_tasks<C, E, T>(tasks: &[(usize, &C, Option<RandomState>)],
+                      folds: &[(Vec<usize>, Vec<usize>)],
+                      data: &[E],
+                      labels: &[T],
+                      set_random_state: Option<&RandomStateSetter<C>>,
+                      score_train: bool) -> Vec<Result<(Option<f64>, f64), String>>
+    where C: Classifier<ExampleType = E, LabelType = T> + Clone + Sync,
+          E: Clone + Sync,
+          T: PartialEq + Clone + Sync
+{
+    parallel::map_rows(tasks, |(fold, clf, state)| {
+        let (train, test) = &folds[*fold];
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut clf = (*clf).clone();
+            if let (Some(set), Some(state)) = (set_random_state, state) {
+                set(&mut clf, state.clone());
+            }
+            let train_data: Vec<E> = train.iter().map(|&i| data[i].clone()).collect();
+            let train_labels: Vec<T> = train.iter().map(|&i| labels[i].clone()).collect();
+            let train_score = if score_train {
+                clf.fit(train_data.clone(), train_labels.clone());
+                Some(score(&clf, &train_data, &train_labels).expect("no predictions after fit()"))
+            } else {
+                clf.fit(train_data, train_labels);
+                None
+            };
+            let test_data: Vec<E> = test.iter().map(|&i| data[i].clone()).collect();
+            let test_labels: Vec<T> = test.iter().map(|&i| labels[i].clone()).collect();
+            (train_score, score(&clf, &test_data, &test_labels).expect("no predictions after fit()"))
+        })).map_err(|payload| panic_message(&*payload))
+    })
+}
+
+/// Get the message of a panic, if it has one.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::EarlyStopping;
+    use crate::knn::KNNClassifier;
+    use crate::linear::Perceptron;
+    use crate::util::Rng;
+
+    fn noisy_data() -> (Vec<Vec<f64>>, Vec<i32>) {
+        let mut rng = Rng::new(3);
+        let data: Vec<Vec<f64>> = (0..80).map(|i| vec![(i % 2) as f64 + rng.next_f64(), rng.next_f64()]).collect();
+        let labels = (0..80).map(|i| if rng.next_f64() < 0.2 { 1 - i % 2 } else { i % 2 }).collect();
+        (data, labels)
+    }
+
+    #[test]
+    fn test_k_fold() {
+        let folds = k_fold(10, 3, None);
+        assert_eq!(folds.len(), 3);
+        let mut tested: Vec<usize> = folds.iter().flat_map(|(_, test)| test.iter().cloned()).collect();
+        tested.sort();
+        assert_eq!(tested, (0..10).collect::<Vec<_>>());
+        for (train, test) in &folds {
+            assert!(test.len() == 3 || test.len() == 4);
+            assert_eq!(train.len() + test.len(), 10);
+            assert!(train.iter().all(|i| !test.contains(i)));
+        }
+    }
+
+    #[test]
+    fn test_group_kfold_split() {
+        let (data, labels) = noisy_data();
+        // Groups of uneven size: 1, 2, ..., 12 rows, and the remaining two.
+        let mut groups = Vec::new();
+        for g in 1..=12 {
+            groups.extend(std::iter::repeat_n(g, g));
+        }
+        groups.extend([13, 13]);
+        assert_eq!(groups.len(), data.len());
+
+        let folds = group_kfold_split(&data, &labels, &groups, 4);
+        assert_eq!(folds.len(), 4);
+        let mut tested: Vec<usize> = folds.iter().flat_map(|(_, test)| test.iter().cloned()).collect();
+        tested.sort();
+        assert_eq!(tested, (0..80).collect::<Vec<_>>());
+        for (train, test) in &folds {
+            assert_eq!(train.len() + test.len(), 80);
+            // The groups can't be split evenly, but nearly so.
+            assert!(test.len() >= 18 && test.len() <= 22, "{}", test.len());
+            assert!(train.iter().all(|&i| test.iter().all(|&j| groups[i] != groups[j])));
+        }
+    }
+
+    #[test]
+    fn test_grid_search_matches_serial() {
+        let (data, labels) = noisy_data();
+        let ks = [1, 3, 7, 15];
+        let candidates = ks.iter().map(|&k| (format!("k={}", k), KNNClassifier::new(k))).collect();
+        let mut search = GridSearch::new(candidates, 5);
+        assert!(search.best().is_none());
+        search.set_random_state(Some(RandomState::new(8)));
+        search.fit(&data, &labels).unwrap();
+
+        // The same evaluation, one fold at a time on this thread, on folds
+        // drawn from the first state split off the search's.
+        let fold_state = RandomState::new(8).split();
+        let folds = k_fold(data.len(), 5, Some(fold_state.clone()));
+        for (&k, (name, scores)) in ks.iter().zip(search.results().unwrap()) {
+            assert_eq!(name, format!("k={}", k));
+            let serial: Vec<f64> = folds.iter().map(|(train, test)| {
+                let mut clf = KNNClassifier::new(k);
+                clf.fit(train.iter().map(|&i| data[i].clone()).collect(), train.iter().map(|&i| labels[i]).collect());
+                let test_data: Vec<Vec<f64>> = test.iter().map(|&i| data[i].clone()).collect();
+                let test_labels: Vec<i32> = test.iter().map(|&i| labels[i]).collect();
+                score(&clf, &test_data, &test_labels).unwrap()
+            }).collect();
+            assert_eq!(scores, &serial[..]);
+            assert_eq!(cross_val_score(&KNNClassifier::new(k), &data, &labels, 5, Some(fold_state.clone())).unwrap(), serial);
+        }
+        let (_, _, best_mean) = search.best().unwrap();
+        assert!(best_mean > 0.5);
+    }
+
+    #[test]
+    fn test_task_random_state() {
+        let (data, labels) = noisy_data();
+        let early_stopping = |random_state| EarlyStopping {
+            validation_fraction: 0.25,
+            patience: 2,
+            min_delta: 0.0,
+            random_state: Some(random_state),
+        };
+        let epochs = [5, 20];
+        let candidates = epochs.iter().map(|&n| (format!("epochs={}", n), Perceptron::new(n, 1.0))).collect();
+        let mut search = GridSearch::new(candidates, 4);
+        search.set_random_state(Some(RandomState::new(5)));
+        search.set_task_random_state(Some(Box::new(move |clf: &mut Perceptron<i32>, random_state| {
+            clf.set_early_stopping(Some(early_stopping(random_state)));
+        })));
+        search.fit(&data, &labels).unwrap();
+
+        // Each task holds out its own validation rows, drawn from the
+        // states split off after the folds', candidate by candidate.
+        let mut state = RandomState::new(5);
+        let folds = k_fold(data.len(), 4, Some(state.split()));
+        for (&n, (_, scores)) in epochs.iter().zip(search.results().unwrap()) {
+            let serial: Vec<f64> = folds.iter().map(|(train, test)| {
+                let mut clf = Perceptron::new(n, 1.0);
+                clf.set_early_stopping(Some(early_stopping(state.split())));
+                clf.fit(train.iter().map(|&i| data[i].clone()).collect(), train.iter().map(|&i| labels[i]).collect());
+                let test_data: Vec<Vec<f64>> = test.iter().map(|&i| data[i].clone()).collect();
+                let test_labels: Vec<i32> = test.iter().map(|&i| labels[i]).collect();
+                score(&clf, &test_data, &test_labels).unwrap()
+            }).collect();
+            assert_eq!(scores, &serial[..]);
+        }
+
+        // Evaluated a fold at a time, as when stopping early, with a margin
+        // too wide to stop anything, each task gets the same state.
+        let full: Vec<Vec<f64>> = search.results().unwrap().iter().map(|(_, s)| s.to_vec()).collect();
+        search.set_early_stopping(Some(1.0));
+        search.fit(&data, &labels).unwrap();
+        let stopped: Vec<Vec<f64>> = search.results().unwrap().iter().map(|(_, s)| s.to_vec()).collect();
+        assert_eq!(stopped, full);
+    }
+
+    /// Predicts 0 for everything, and panics in `fit()` if told to.
+    #[derive(Clone)]
+    struct Faulty {
+        panics: bool,
+    }
+
+    impl Classifier for Faulty {
+        type ExampleType = Vec<f64>;
+        type LabelType = i32;
+
+        fn fit(&mut self, _data: Vec<Vec<f64>>, _labels: Vec<i32>) {
+            if self.panics {
+                panic!("faulty estimator");
+            }
+        }
+
+        fn predict(&self, data: &[Vec<f64>]) -> Option<Vec<i32>> {
+            Some(vec![0; data.len()])
+        }
+
+        fn predict_one(&self, _x: &Vec<f64>) -> Option<i32> {
+            Some(0)
+        }
+    }
+
+    #[test]
+    fn test_panic_becomes_error() {
+        let (data, labels) = noisy_data();
+        let candidates = vec![
+            ("good".to_string(), Faulty { panics: false }),
+            ("bad".to_string(), Faulty { panics: true }),
+        ];
+        let mut search = GridSearch::new(candidates, 4);
+        let err = search.fit(&data, &labels).unwrap_err();
+        assert_eq!(err, CvError::Panicked {
+            candidate: Some("bad".to_string()),
+            fold: 0,
+            message: "faulty estimator".to_string(),
+        });
+        assert!(search.results().is_none());
+
+        let err = cross_val_score(&Faulty { panics: true }, &data, &labels, 4, None).unwrap_err();
+        assert_eq!(err.to_string(), "fold 0 panicked: faulty estimator");
+    }
+
+    #[test]
+    fn test_early_stopping() {
+        let (data, labels) = noisy_data();
+        let mut search = GridSearch::new(vec![
+            ("k=7".to_string(), KNNClassifier::new(7)),
+            ("k=1".to_string(), KNNClassifier::new(1)),
+        ], 8);
+        search.set_random_state(Some(RandomState::new(4)));
+        search.fit(&data, &labels).unwrap();
+        let full = search.results().unwrap().iter().map(|(_, s)| s.to_vec()).collect::<Vec<_>>();
+
+        search.set_early_stopping(Some(0.0));
+        search.fit(&data, &labels).unwrap();
+        let results = search.results().unwrap();
+        assert_eq!(results[0].1, &full[0][..]);
+        assert!(results[1].1.len() < 8, "{:?}", results[1].1);
+        assert_eq!(results[1].1, &full[1][..results[1].1.len()]);
+        assert_eq!(search.best().unwrap().0, "k=7");
+    }
+
+    #[test]
+    fn test_learning_curve() {
+        let (data, labels) = noisy_data();
+        let fractions = [0.1, 0.5, 1.0];
+        let curve = learning_curve(&KNNClassifier::new(1), &data, &labels, &fractions, 4, Some(RandomState::new(2))).unwrap();
+        assert_eq!(curve.len(), 3);
+        for (&(fraction, train, test), &expected) in curve.iter().zip(fractions.iter()) {
+            assert_eq!(fraction, expected);
+            assert!((0.0..=1.0).contains(&train) && (0.0..=1.0).contains(&test));
+            // 1-NN recalls its own training examples perfectly.
+            assert_eq!(train, 1.0);
+        }
+        // With the whole training set, the test scores are those of plain
+        // cross-validation on the same folds.
+        let mut state = RandomState::new(2);
+        let scores = cross_val_score(&KNNClassifier::new(1), &data, &labels, 4, Some(state.split())).unwrap();
+        assert!((curve[2].2 - scores.iter().sum::<f64>() / 4.0).abs() < 1e-12);
+
+        let err = learning_curve(&Faulty { panics: true }, &data, &labels, &[0.5], 4, None).unwrap_err();
+        assert_eq!(err.to_string(), "candidate \"fraction=0.5\", fold 0 panicked: faulty estimator");
+    }
+}
@@ -0,0 +1,308 @@
+use std::fmt::Debug;
+
+/// An update rule for gradient-trained parameters. Estimators keep optimizers
+/// boxed, and clone them along with themselves, so they must be cloneable
+/// and shareable across threads.
+pub trait Optimizer: OptimizerClone + Debug + Send + Sync {
+    /// Update `params` in place given the gradient of the loss at them.
+    fn step(&mut self, params: &mut [f64], grads: &[f64]);
+
+    /// The current learning rate.
+    fn learning_rate(&self) -> f64;
+
+    /// Change the learning rate for the following steps.
+    fn set_learning_rate(&mut self, learning_rate: f64);
+}
+
+/// Lets boxed optimizers be cloned. Implemented for every optimizer that is
+/// `Clone`.
+pub trait OptimizerClone {
+    /// Clone the optimizer, state included, into a new box.
+    fn clone_box(&self) -> Box<dyn Optimizer>;
+}
+
+impl<O> OptimizerClone for O where O: Optimizer + Clone + 'static {
+    fn clone_box(&self) -> Box<dyn Optimizer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Optimizer> {
+    fn clone(&self) -> Box<dyn Optimizer> {
+        self.clone_box()
+    }
+}
+
+/// Plain stochastic gradient descent: `p -= lr * g`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sgd {
+    learning_rate: f64,
+}
+
+impl Sgd {
+    /// Construct a new Sgd optimizer.
+    pub fn new(learning_rate: f64) -> Sgd {
+        Sgd { learning_rate }
+    }
+}
+
+impl Optimizer for Sgd {
+    fn step(&mut self, params: &mut [f64], grads: &[f64]) {
+        assert_eq!(params.len(), grads.len(), "params and grads differ in length");
+        for (p, g) in params.iter_mut().zip(grads.iter()) {
+            *p -= self.learning_rate * g;
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+/// Stochastic gradient descent with momentum: each step moves along a
+/// velocity that accumulates past gradients, `v = momentum * v - lr * g`,
+/// `p += v`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Momentum {
+    learning_rate: f64,
+    momentum: f64,
+    velocity: Vec<f64>,
+}
+
+impl Momentum {
+    /// Construct a new Momentum optimizer. `momentum` is the fraction of the
+    /// velocity kept from one step to the next.
+    pub fn new(learning_rate: f64, momentum: f64) -> Momentum {
+        Momentum { learning_rate, momentum, velocity: Vec::new() }
+    }
+}
+
+impl Optimizer for Momentum {
+    fn step(&mut self, params: &mut [f64], grads: &[f64]) {
+        assert_eq!(params.len(), grads.len(), "params and grads differ in length");
+        if self.velocity.is_empty() {
+            self.velocity = vec![0f64; params.len()];
+        }
+        assert_eq!(self.velocity.len(), params.len(), "number of params changed between steps");
+        for ((p, g), v) in params.iter_mut().zip(grads.iter()).zip(self.velocity.iter_mut()) {
+            *v = self.momentum * *v - self.learning_rate * g;
+            *p += *v;
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+/// The Adam optimizer (Kingma & Ba, 2015). It keeps running averages of
+/// each parameter's gradient and squared gradient, corrects them for their
+/// bias towards zero in early steps, and scales each parameter's step by
+/// them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adam {
+    learning_rate: f64,
+    beta1: f64,
+    beta2: f64,
+    epsilon: f64,
+    t: i32,
+    m: Vec<f64>,
+    v: Vec<f64>,
+}
+
+impl Adam {
+    /// Construct a new Adam optimizer with the usual decay rates 0.9 and
+    /// 0.999.
+    pub fn new(learning_rate: f64) -> Adam {
+        Adam::with_betas(learning_rate, 0.9, 0.999)
+    }
+
+    /// Construct a new Adam optimizer with the given decay rates for the
+    /// first and second moment estimates.
+    pub fn with_betas(learning_rate: f64, beta1: f64, beta2: f64) -> Adam {
+        Adam { learning_rate, beta1, beta2, epsilon: 1e-8, t: 0, m: Vec::new(), v: Vec::new() }
+    }
+
+    /// The running first and second moment estimates, one of each per
+    /// parameter. Both are empty before the first step.
+    pub fn moments(&self) -> (&[f64], &[f64]) {
+        (&self.m, &self.v)
+    }
+}
+
+impl Optimizer for Adam {
+    fn step(&mut self, params: &mut [f64], grads: &[f64]) {
+        assert_eq!(params.len(), grads.len(), "params and grads differ in length");
+        if self.m.is_empty() {
+            self.m = vec![0f64; params.len()];
+            self.v = vec![0f64; params.len()];
+        }
+        assert_eq!(self.m.len(), params.len(), "number of params changed between steps");
+        self.t += 1;
+        let m_correction = 1.0 - self.beta1.powi(self.t);
+        let v_correction = 1.0 - self.beta2.powi(self.t);
+        for (i, (p, g)) in params.iter_mut().zip(grads.iter()).enumerate() {
+            self.m[i] = self.beta1 * self.m[i] + (1.0 - self.beta1) * g;
+            self.v[i] = self.beta2 * self.v[i] + (1.0 - self.beta2) * g * g;
+            let m_hat = self.m[i] / m_correction;
+            let v_hat = self.v[i] / v_correction;
+            *p -= self.learning_rate * m_hat / (v_hat.sqrt() + self.epsilon);
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+/// How the learning rate changes over the steps of training. The rate at
+/// step `t`, counted from zero, for a base rate `lr` is:
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// `lr`.
+    Constant,
+    /// `lr / (t + 1)^power`.
+    InverseScaling { power: f64 },
+    /// `lr * gamma^(t / step_size)`, with integer division: the rate drops
+    /// by a factor `gamma` every `step_size` steps.
+    StepDecay { step_size: usize, gamma: f64 },
+}
+
+impl Schedule {
+    /// Get the learning rate at step `t` for base rate `base`.
+    pub fn rate(&self, base: f64, t: usize) -> f64 {
+        match *self {
+            Schedule::Constant => base,
+            Schedule::InverseScaling { power } => base / ((t + 1) as f64).powf(power),
+            Schedule::StepDecay { step_size, gamma } => base * gamma.powi((t / step_size) as i32),
+        }
+    }
+}
+
+/// Wraps any optimizer to set its learning rate from a schedule before each
+/// step. The optimizer's learning rate when wrapped is the base rate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheduled<O> {
+    optimizer: O,
+    schedule: Schedule,
+    base_rate: f64,
+    t: usize,
+}
+
+impl<O> Scheduled<O> where O: Optimizer {
+    /// Construct a new Scheduled optimizer.
+    ///
+    /// # Panics
+    ///
+    /// If `schedule` is a `StepDecay` with a `step_size` of 0.
+    pub fn new(optimizer: O, schedule: Schedule) -> Scheduled<O> {
+        if let Schedule::StepDecay { step_size, .. } = schedule {
+            assert!(step_size > 0, "step size must be positive");
+        }
+        let base_rate = optimizer.learning_rate();
+        Scheduled { optimizer, schedule, base_rate, t: 0 }
+    }
+
+    /// The wrapped optimizer.
+    pub fn inner(&self) -> &O {
+        &self.optimizer
+    }
+}
+
+impl<O> Optimizer for Scheduled<O> where O: Optimizer + Clone + 'static {
+    fn step(&mut self, params: &mut [f64], grads: &[f64]) {
+        self.optimizer.set_learning_rate(self.schedule.rate(self.base_rate, self.t));
+        self.optimizer.step(params, grads);
+        self.t += 1;
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.schedule.rate(self.base_rate, self.t)
+    }
+
+    /// Change the base rate that the schedule scales.
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.base_rate = learning_rate;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CENTRE: [f64; 3] = [1.0, -2.0, 0.5];
+    const CURVATURE: [f64; 3] = [1.0, 4.0, 0.5];
+
+    /// Minimize sum(a_i * (x_i - c_i)^2) from the origin and return the
+    /// final point.
+    fn minimize(optimizer: &mut dyn Optimizer, steps: usize) -> Vec<f64> {
+        let mut params = vec![0f64; 3];
+        for _ in 0..steps {
+            let grads: Vec<f64> = (0..3).map(|i| 2.0 * CURVATURE[i] * (params[i] - CENTRE[i])).collect();
+            optimizer.step(&mut params, &grads);
+        }
+        params
+    }
+
+    fn assert_converged(params: &[f64]) {
+        for (p, c) in params.iter().zip(CENTRE.iter()) {
+            assert!((p - c).abs() < 1e-6, "{:?}", params);
+        }
+    }
+
+    #[test]
+    fn test_sgd() {
+        assert_converged(&minimize(&mut Sgd::new(0.1), 500));
+    }
+
+    #[test]
+    fn test_momentum() {
+        assert_converged(&minimize(&mut Momentum::new(0.05, 0.8), 500));
+    }
+
+    #[test]
+    fn test_adam() {
+        let mut adam = Adam::new(0.1);
+        assert_eq!(adam.moments(), (&[][..], &[][..]));
+        let params = minimize(&mut Scheduled::new(adam.clone(), Schedule::StepDecay { step_size: 200, gamma: 0.1 }), 1000);
+        assert_converged(&params);
+
+        minimize(&mut adam, 1);
+        assert_eq!(adam.moments().0.len(), 3);
+        assert_eq!(adam.moments().1.len(), 3);
+    }
+
+    #[test]
+    fn test_schedules() {
+        let rates = |schedule: Schedule| -> Vec<f64> { (0..5).map(|t| schedule.rate(1.0, t)).collect() };
+        assert_eq!(rates(Schedule::Constant), vec![1.0; 5]);
+        assert_eq!(rates(Schedule::InverseScaling { power: 1.0 }), vec![1.0, 0.5, 1.0 / 3.0, 0.25, 0.2]);
+        assert_eq!(rates(Schedule::StepDecay { step_size: 2, gamma: 0.5 }), vec![1.0, 1.0, 0.5, 0.5, 0.25]);
+
+        let mut sgd = Scheduled::new(Sgd::new(0.4), Schedule::InverseScaling { power: 0.5 });
+        let mut params = vec![0f64];
+        sgd.step(&mut params, &[1.0]);
+        assert_eq!(sgd.inner().learning_rate(), 0.4);
+        sgd.step(&mut params, &[1.0]);
+        assert!((sgd.inner().learning_rate() - 0.4 / 2f64.sqrt()).abs() < 1e-15);
+        assert!((sgd.learning_rate() - 0.4 / 3f64.sqrt()).abs() < 1e-15);
+    }
+
+    #[test]
+    #[should_panic(expected = "step size must be positive")]
+    fn test_step_decay_needs_step_size() {
+        Scheduled::new(Sgd::new(0.1), Schedule::StepDecay { step_size: 0, gamma: 0.5 });
+    }
+}
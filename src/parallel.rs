@@ -0,0 +1,62 @@
+//! Row-parallel helpers. With the `parallel` feature enabled, work is split
+//! into contiguous blocks of rows, each handled by a scoped thread; without
+//! it everything runs on the calling thread. Each row is computed exactly as
+//! in the serial path, so results are bit-identical either way.
+//!
+//! The number of threads defaults to the available parallelism and can be
+//! overridden with the `OXIDE_NUM_THREADS` environment variable.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "parallel")]
+use std::env;
+#[cfg(feature = "parallel")]
+use std::panic;
+#[cfg(feature = "parallel")]
+use std::thread;
+
+/// The number of threads used by the parallel code paths.
+#[cfg(feature = "parallel")]
+pub fn num_threads() -> usize {
+    env::var("OXIDE_NUM_THREADS").ok()
+        .and_then(|val| val.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+/// The number of threads used by the parallel code paths.
+#[cfg(not(feature = "parallel"))]
+pub fn num_threads() -> usize {
+    1
+}
+
+/// Apply `f` to every item, returning the results in input order.
+#[cfg(feature = "parallel")]
+pub fn map_rows<T, R, F>(items: &[T], f: F) -> Vec<R>
+    where T: Sync, R: Send, F: Fn(&T) -> R + Sync
+{
+    let threads = num_threads().min(items.len());
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let block = items.len().div_ceil(threads);
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = items.chunks(block).map(|chunk| {
+            scope.spawn(move || chunk.iter().map(f).collect::<Vec<R>>())
+        }).collect();
+        let mut ret = Vec::with_capacity(items.len());
+        for handle in handles {
+            ret.extend(handle.join().unwrap_or_else(|e| panic::resume_unwind(e)));
+        }
+        ret
+    })
+}
+
+/// Apply `f` to every item, returning the results in input order.
+#[cfg(not(feature = "parallel"))]
+pub fn map_rows<T, R, F>(items: &[T], f: F) -> Vec<R>
+    where F: Fn(&T) -> R
+{
+    items.iter().map(f).collect()
+}
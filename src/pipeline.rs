@@ -0,0 +1,508 @@
+use super::core::{Classifier, ProbabilisticClassifier};
+use super::preprocessing::{hash_of, Transformer};
+#[cfg(feature = "serde")]
+use super::preprocessing::SavedTransformer;
+#[cfg(feature = "serde")]
+use serde::{ser::Error, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+/// An in-memory cache of fitted pipeline steps. Each entry holds a fitted
+/// transformer and its output on the training data, keyed by a hash of the
+/// transformer's configuration and of its input. A step whose own settings
+/// and upstream steps are unchanged therefore finds its entry again, while
+/// changing either gives a new key. Clones share the same cache, also
+/// across threads, so one `Memory` can serve every candidate pipeline of a
+/// search, e.g. clones of one Pipeline in `GridSearch`. Different steps are
+/// fitted concurrently, while a pipeline needing an entry another is still
+/// fitting waits for it instead of fitting it again. When full, the least
+/// recently used entry is evicted.
+#[derive(Clone)]
+pub struct Memory {
+    cache: Arc<Mutex<Cache>>,
+}
+
+/// A fitted transformer together with its output on the data it was fitted
+/// on.
+#[derive(Clone)]
+struct FittedStep {
+    transformer: Box<dyn Transformer>,
+    output: Vec<Vec<f64>>,
+}
+
+struct Cache {
+    capacity: usize,
+    // Ordered from least to most recently used.
+    entries: Vec<(u64, FittedStep)>,
+    // Steps being fitted, each filled in by whichever pipeline fits it.
+    pending: HashMap<u64, Arc<OnceLock<FittedStep>>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Memory {
+    /// Construct an empty cache holding at most `capacity` fitted steps.
+    pub fn new(capacity: usize) -> Memory {
+        Memory {
+            cache: Arc::new(Mutex::new(Cache { capacity, entries: Vec::new(), pending: HashMap::new(), hits: 0, misses: 0 })),
+        }
+    }
+
+    /// The number of cached steps.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Check whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of fits served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+
+    /// The number of fits that had to be computed.
+    pub fn misses(&self) -> u64 {
+        self.lock().misses
+    }
+
+    /// Drop every cached step.
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    /// Lock the cache. A panic while it was locked, e.g. in a transformer
+    /// being fitted, leaves the entries consistent, so the lock is taken
+    /// regardless.
+    fn lock(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Cache {
+    /// Look up a fitted step, marking it as recently used.
+    fn get(&mut self, key: u64) -> Option<FittedStep> {
+        let i = self.entries.iter().position(|entry| entry.0 == key)?;
+        let entry = self.entries.remove(i);
+        let found = entry.1.clone();
+        self.entries.push(entry);
+        Some(found)
+    }
+
+    /// Store a fitted step, evicting the least recently used ones if the
+    /// cache is full.
+    fn insert(&mut self, key: u64, step: FittedStep) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            self.entries.remove(0);
+        }
+        self.entries.push((key, step));
+    }
+}
+
+/// Hash the exact contents of a dataset.
+fn fingerprint(data: &[Vec<f64>]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write_usize(data.len());
+    for x in data {
+        hasher.write_usize(x.len());
+        for v in x {
+            hasher.write_u64(v.to_bits());
+        }
+    }
+    hasher.finish()
+}
+
+/// A chain of transformers followed by a classifier. Fitting fits each
+/// transformer on the output of the previous one and the classifier on the
+/// output of the last; predicting runs new data through the same chain.
+///
+/// With the `serde` feature, a fitted Pipeline can be serialized as a whole,
+/// provided every step can be saved (see `Transformer::save()`) and the
+/// classifier is serializable. The cache isn't saved.
+///
+/// Clones share the cache of the original, if any.
+#[derive(Clone)]
+pub struct Pipeline<C> {
+    steps: Vec<(String, Box<dyn Transformer>)>,
+    classifier: C,
+    memory: Option<Memory>,
+}
+
+impl<C> Pipeline<C> where C: Classifier<ExampleType = Vec<f64>> {
+    /// Construct a new Pipeline from named transformers and a classifier.
+    pub fn new(steps: Vec<(String, Box<dyn Transformer>)>, classifier: C) -> Pipeline<C> {
+        Pipeline::<C>{ steps, classifier, memory: None }
+    }
+
+    /// Construct a new Pipeline that looks up its fitted transformers in
+    /// `memory` before fitting them, and stores them there afterwards.
+    /// Transformers without a `config_hash()` are always fitted.
+    pub fn with_memory(steps: Vec<(String, Box<dyn Transformer>)>, classifier: C, memory: Memory) -> Pipeline<C> {
+        Pipeline::<C>{ steps, classifier, memory: Some(memory) }
+    }
+
+    /// The final classifier.
+    pub fn classifier(&self) -> &C {
+        &self.classifier
+    }
+
+    /// Fit one step on `data` and return its output, going through the
+    /// cache if there is one.
+    fn fit_step(&mut self, i: usize, data: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        let transformer = &mut self.steps[i].1;
+        let (memory, config) = match (&self.memory, transformer.config_hash()) {
+            (Some(memory), Some(config)) => (memory, config),
+            _ => return transformer.fit_transform(&data),
+        };
+        let key = hash_of(&(config, fingerprint(&data)));
+        // The lock is only held for the bookkeeping. A step that isn't
+        // cached gets a pending slot, which the first pipeline to need it
+        // fills while any others wait on that slot alone.
+        let slot = {
+            let mut cache = memory.lock();
+            if let Some(fitted) = cache.get(key) {
+                cache.hits += 1;
+                *transformer = fitted.transformer;
+                return fitted.output;
+            }
+            cache.pending.entry(key).or_default().clone()
+        };
+        let mut fitted_here = false;
+        let fitted = slot.get_or_init(|| {
+            fitted_here = true;
+            let output = transformer.fit_transform(&data);
+            FittedStep { transformer: transformer.clone(), output }
+        });
+        let mut cache = memory.lock();
+        if fitted_here {
+            cache.misses += 1;
+            cache.pending.remove(&key);
+            cache.insert(key, fitted.clone());
+        } else {
+            cache.hits += 1;
+            *transformer = fitted.transformer.clone();
+        }
+        fitted.output.clone()
+    }
+
+    /// Run datapoints through the fitted transformers. Return None if called
+    /// before `fit()`.
+    fn transform(&self, data: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+        let mut out = data.to_vec();
+        for (_, transformer) in &self.steps {
+            out = transformer.transform(&out)?;
+        }
+        Some(out)
+    }
+}
+
+impl<C> Classifier for Pipeline<C> where C: Classifier<ExampleType = Vec<f64>> {
+    type ExampleType = Vec<f64>;
+    type LabelType = C::LabelType;
+
+    /// Fit the transformers in order, then the classifier on their output.
+    fn fit(&mut self, data: Vec<Vec<f64>>, labels: Vec<C::LabelType>) {
+        let mut data = data;
+        for i in 0..self.steps.len() {
+            data = self.fit_step(i, data);
+        }
+        self.classifier.fit(data, labels);
+    }
+
+    /// Predict the labels of datapoints. Return None if `predict()` is
+    /// called before `fit()`.
+    fn predict(&self, data: &[Vec<f64>]) -> Option<Vec<C::LabelType>> {
+        self.classifier.predict(&self.transform(data)?)
+    }
+
+    /// Predict the label for one datapoint. Return None if `predict_one()`
+    /// is called before `fit()`.
+    fn predict_one(&self, x: &Vec<f64>) -> Option<C::LabelType> {
+        let transformed = self.transform(std::slice::from_ref(x))?;
+        self.classifier.predict_one(&transformed[0])
+    }
+}
+
+/// A Pipeline as serialized.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SavedPipeline<C> {
+    steps: Vec<(String, SavedTransformer)>,
+    classifier: C,
+}
+
+/// A Pipeline as serialized, borrowing its classifier.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct SavedPipelineRef<'a, C> {
+    steps: Vec<(&'a str, SavedTransformer)>,
+    classifier: &'a C,
+}
+
+#[cfg(feature = "serde")]
+impl<C: Serialize> Serialize for Pipeline<C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut steps = Vec::with_capacity(self.steps.len());
+        for (name, transformer) in &self.steps {
+            let saved = transformer.save().ok_or_else(|| S::Error::custom(format!("step {:?} can't be saved", name)))?;
+            steps.push((name.as_str(), saved));
+        }
+        SavedPipelineRef { steps, classifier: &self.classifier }.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, C: Deserialize<'de>> Deserialize<'de> for Pipeline<C> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Pipeline<C>, D::Error> {
+        let saved = SavedPipeline::deserialize(deserializer)?;
+        let steps = saved.steps.into_iter().map(|(name, t)| (name, t.into_transformer())).collect();
+        Ok(Pipeline { steps, classifier: saved.classifier, memory: None })
+    }
+}
+
+impl<C> ProbabilisticClassifier for Pipeline<C> where C: ProbabilisticClassifier<ExampleType = Vec<f64>> {
+    fn predict_proba(&self, x: &Vec<f64>) -> Option<Vec<(C::LabelType, f64)>> {
+        let transformed = self.transform(std::slice::from_ref(x))?;
+        self.classifier.predict_proba(&transformed[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::knn::KNNClassifier;
+    use crate::model_selection::GridSearch;
+    use crate::preprocessing::StandardScaler;
+    use crate::util::{RandomState, Rng};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Multiplies every value by a factor, counting how often it is fitted.
+    #[derive(Clone)]
+    struct CountingScaler {
+        factor: f64,
+        fits: Arc<AtomicUsize>,
+        fitted: bool,
+    }
+
+    impl Transformer for CountingScaler {
+        fn fit(&mut self, _data: &[Vec<f64>]) {
+            self.fits.fetch_add(1, Ordering::SeqCst);
+            self.fitted = true;
+        }
+
+        fn transform(&self, data: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+            if !self.fitted {
+                return None;
+            }
+            Some(data.iter().map(|x| x.iter().map(|v| v * self.factor).collect()).collect())
+        }
+
+        fn config_hash(&self) -> Option<u64> {
+            Some(hash_of(&("CountingScaler", self.factor.to_bits())))
+        }
+    }
+
+    fn pipeline(factor: f64, fits: &Arc<AtomicUsize>, k: usize, memory: &Memory) -> Pipeline<KNNClassifier<&'static str>> {
+        let steps: Vec<(String, Box<dyn Transformer>)> = vec![
+            ("scale".to_string(), Box::new(CountingScaler { factor, fits: fits.clone(), fitted: false })),
+            ("standardize".to_string(), Box::new(StandardScaler::new())),
+        ];
+        Pipeline::with_memory(steps, KNNClassifier::new(k), memory.clone())
+    }
+
+    fn toy_data() -> (Vec<Vec<f64>>, Vec<&'static str>) {
+        let data = vec![vec![0.0, 1.0], vec![0.2, 0.8], vec![1.0, 0.0], vec![0.9, 0.1], vec![0.1, 0.9]];
+        (data, vec!["a", "a", "b", "b", "a"])
+    }
+
+    #[test]
+    fn test_pipeline_predict() {
+        let (data, labels) = toy_data();
+        let steps: Vec<(String, Box<dyn Transformer>)> = vec![("scale".to_string(), Box::new(StandardScaler::new()))];
+        let mut pipe = Pipeline::new(steps, KNNClassifier::new(1));
+        assert_eq!(pipe.predict_one(&data[0]), None);
+
+        pipe.fit(data.clone(), labels.clone());
+        assert_eq!(pipe.predict(&data).unwrap(), labels);
+        assert_eq!(pipe.predict_proba(&vec![0.95, 0.05]).unwrap(), vec![("a", 0.0), ("b", 1.0)]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_round_trip() {
+        use crate::preprocessing::{ColumnTransformer, OneHotEncoder, Remainder};
+
+        let data = vec![
+            vec![0.0, 10.0, 1.0], vec![0.5, 12.0, 0.0], vec![3.0, 30.0, 1.0],
+            vec![3.5, 28.0, 2.0], vec![0.2, 11.0, 2.0], vec![2.8, 33.0, 0.0],
+        ];
+        let labels: Vec<String> = ["a", "a", "b", "b", "a", "b"].iter().map(|l| l.to_string()).collect();
+        let columns = ColumnTransformer::new(vec![
+            ("numeric".to_string(), vec![0, 1], Box::new(StandardScaler::new()) as Box<dyn Transformer>),
+            ("category".to_string(), vec![2], Box::new(OneHotEncoder::new())),
+        ], Remainder::Drop).unwrap();
+        let mut pipe = Pipeline::new(vec![("columns".to_string(), Box::new(columns) as Box<dyn Transformer>)], KNNClassifier::new(3));
+        pipe.fit(data.clone(), labels);
+
+        let json = serde_json::to_string(&pipe).unwrap();
+        let loaded: Pipeline<KNNClassifier<String>> = serde_json::from_str(&json).unwrap();
+        let test = vec![vec![0.1, 9.0, 0.0], vec![3.2, 31.0, 1.0], vec![1.6, 20.0, 2.0]];
+        assert_eq!(loaded.predict(&test), pipe.predict(&test));
+        for x in &test {
+            assert_eq!(loaded.predict_proba(x), pipe.predict_proba(x));
+        }
+
+        // Steps of unknown types can't be saved.
+        let fits = Arc::new(AtomicUsize::new(0));
+        let (data, labels) = toy_data();
+        let mut custom = pipeline(2.0, &fits, 1, &Memory::new(0));
+        custom.fit(data, labels);
+        assert!(serde_json::to_string(&custom).is_err());
+    }
+
+    #[test]
+    fn test_memory_reuses_fitted_steps() {
+        let (data, labels) = toy_data();
+        let fits = Arc::new(AtomicUsize::new(0));
+        let memory = Memory::new(8);
+
+        let mut pipe = pipeline(2.0, &fits, 1, &memory);
+        pipe.fit(data.clone(), labels.clone());
+        let predictions = pipe.predict(&data).unwrap();
+        assert_eq!(fits.load(Ordering::SeqCst), 1);
+        assert_eq!(memory.len(), 2);
+        assert_eq!((memory.hits(), memory.misses()), (0, 2));
+
+        // Cached steps predict like freshly fitted ones.
+        let mut cached = pipeline(2.0, &fits, 1, &memory);
+        cached.fit(data.clone(), labels.clone());
+        assert_eq!(fits.load(Ordering::SeqCst), 1);
+        assert_eq!(memory.hits(), 2);
+        let mut uncached = pipeline(2.0, &fits, 1, &Memory::new(0));
+        uncached.fit(data.clone(), labels.clone());
+        assert_eq!(uncached.predict(&data).unwrap(), predictions);
+        assert_eq!(cached.predict(&data).unwrap(), predictions);
+
+        // Changing the transformer's own parameter misses for it and for
+        // the step after it, whose input has changed.
+        fits.store(0, Ordering::SeqCst);
+        let mut pipe = pipeline(3.0, &fits, 1, &memory);
+        pipe.fit(data.clone(), labels.clone());
+        assert_eq!(fits.load(Ordering::SeqCst), 1);
+        assert_eq!(memory.len(), 4);
+        assert_eq!(memory.misses(), 4);
+
+        memory.clear();
+        assert!(memory.is_empty());
+    }
+
+    #[test]
+    fn test_memory_in_grid_search() {
+        let mut rng = Rng::new(4);
+        let data: Vec<Vec<f64>> = (0..40).map(|i| vec![(i % 2) as f64 + rng.next_f64(), rng.next_f64()]).collect();
+        let labels: Vec<&str> = (0..40).map(|i| ["a", "b"][i % 2]).collect();
+        let fits = Arc::new(AtomicUsize::new(0));
+        let memory = Memory::new(16);
+
+        // Every candidate is a clone of one cached pipeline with its own k.
+        // Each fold fits the transformers once, for the first candidate
+        // that gets to it; every other candidate finds them in the cache,
+        // also when the (candidate, fold) pairs run in parallel.
+        let ks = [1, 3, 5, 7];
+        let base = pipeline(2.0, &fits, 1, &memory);
+        let candidates = ks.iter().map(|&k| {
+            let mut pipe = base.clone();
+            pipe.classifier = KNNClassifier::new(k);
+            (format!("k={}", k), pipe)
+        }).collect();
+        let mut search = GridSearch::new(candidates, 4);
+        search.set_random_state(Some(RandomState::new(2)));
+        search.fit(&data, &labels).unwrap();
+        assert_eq!(fits.load(Ordering::SeqCst), 4);
+        assert_eq!(memory.len(), 8);
+        assert_eq!((memory.hits(), memory.misses()), (2 * 4 * 3, 2 * 4));
+
+        // The scores are those of uncached pipelines.
+        let uncached = ks.iter().map(|&k| (format!("k={}", k), pipeline(2.0, &fits, k, &Memory::new(0)))).collect();
+        let mut plain = GridSearch::new(uncached, 4);
+        plain.set_random_state(Some(RandomState::new(2)));
+        plain.fit(&data, &labels).unwrap();
+        assert_eq!(search.results(), plain.results());
+    }
+
+    /// Waits in `fit()` until as many fits as `expected` have started, up
+    /// to a timeout, and records whether they all did.
+    #[derive(Clone)]
+    struct Rendezvous {
+        id: u64,
+        started: Arc<AtomicUsize>,
+        expected: usize,
+        met: Arc<AtomicUsize>,
+    }
+
+    impl Transformer for Rendezvous {
+        fn fit(&mut self, _data: &[Vec<f64>]) {
+            self.started.fetch_add(1, Ordering::SeqCst);
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while self.started.load(Ordering::SeqCst) < self.expected && Instant::now() < deadline {
+                thread::yield_now();
+            }
+            if self.started.load(Ordering::SeqCst) >= self.expected {
+                self.met.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn transform(&self, data: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+            Some(data.to_vec())
+        }
+
+        fn config_hash(&self) -> Option<u64> {
+            Some(hash_of(&("Rendezvous", self.id)))
+        }
+    }
+
+    #[test]
+    fn test_memory_fits_different_steps_concurrently() {
+        let (data, labels) = toy_data();
+        let (started, met) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let memory = Memory::new(4);
+        let pipelines: Vec<Pipeline<KNNClassifier<&str>>> = (0..2).map(|id| {
+            let step = Rendezvous { id, started: started.clone(), expected: 2, met: met.clone() };
+            let steps: Vec<(String, Box<dyn Transformer>)> = vec![("meet".to_string(), Box::new(step))];
+            Pipeline::with_memory(steps, KNNClassifier::new(1), memory.clone())
+        }).collect();
+        // Each fit only returns early once the other has started, which it
+        // can't if the first holds the cache while fitting.
+        thread::scope(|scope| {
+            for mut pipe in pipelines {
+                let (data, labels) = (data.clone(), labels.clone());
+                scope.spawn(move || pipe.fit(data, labels));
+            }
+        });
+        assert_eq!(met.load(Ordering::SeqCst), 2);
+        assert_eq!((memory.len(), memory.misses()), (2, 2));
+    }
+
+    #[test]
+    fn test_memory_evicts_least_recently_used() {
+        let (data, labels) = toy_data();
+        let fits = Arc::new(AtomicUsize::new(0));
+        let memory = Memory::new(2);
+
+        pipeline(2.0, &fits, 1, &memory).fit(data.clone(), labels.clone());
+        pipeline(3.0, &fits, 1, &memory).fit(data.clone(), labels.clone());
+        assert_eq!(memory.len(), 2);
+        // Both entries of the first pipeline have been evicted.
+        pipeline(2.0, &fits, 1, &memory).fit(data, labels);
+        assert_eq!(fits.load(Ordering::SeqCst), 3);
+    }
+}
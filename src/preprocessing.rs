@@ -0,0 +1,632 @@
+use super::util::column_means;
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A learned transformation of feature vectors, such as scaling or
+/// encoding. Transformers are `Send` and `Sync`, so that pipelines holding
+/// them can be cross-validated in parallel.
+pub trait Transformer: TransformerClone + Send + Sync {
+    /// Learn the parameters of the transformation from `data`.
+    fn fit(&mut self, data: &[Vec<f64>]);
+
+    /// Transform datapoints. Return None if `transform()` is called before
+    /// `fit()`.
+    fn transform(&self, data: &[Vec<f64>]) -> Option<Vec<Vec<f64>>>;
+
+    /// Learn the transformation from `data` and apply it.
+    fn fit_transform(&mut self, data: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        self.fit(data);
+        self.transform(data).unwrap()
+    }
+
+    /// Names of the output features, if the transformer can tell. Input
+    /// features are referred to as `x0`, `x1`, ...
+    fn feature_names(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    /// A hash of the transformer's configuration, not of its learned
+    /// state, so that a cache can tell whether fitting it again would give
+    /// the same result. Transformers returning None are never cached.
+    fn config_hash(&self) -> Option<u64> {
+        None
+    }
+
+    /// The transformer's configuration and learned state, for serializing
+    /// it. Transformers returning None can't be saved.
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedTransformer> {
+        None
+    }
+}
+
+/// Cloning of boxed transformers. Implemented for every `Transformer` that
+/// is `Clone`.
+pub trait TransformerClone {
+    /// Clone the transformer, learned state included, into a new box.
+    fn clone_box(&self) -> Box<dyn Transformer>;
+}
+
+impl<T> TransformerClone for T where T: Transformer + Clone + 'static {
+    fn clone_box(&self) -> Box<dyn Transformer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Transformer> {
+    fn clone(&self) -> Box<dyn Transformer> {
+        self.clone_box()
+    }
+}
+
+/// Hash a value with the standard library's default hasher.
+pub(crate) fn hash_of<H: Hash + ?Sized>(value: &H) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Errors raised when a composite transformer is set up inconsistently.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// A column was assigned to two transformers.
+    OverlappingColumns { column: usize, first: String, second: String },
+    /// A composite transformer was given no members.
+    Empty,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::OverlappingColumns { column, ref first, ref second } =>
+                write!(f, "column {} is assigned to both {:?} and {:?}", column, first, second),
+            ConfigError::Empty => write!(f, "no transformers given"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Scales each feature to zero mean and unit variance. Constant features
+/// are only centered.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StandardScaler {
+    means: Option<Vec<f64>>,
+    stds: Option<Vec<f64>>,
+}
+
+impl StandardScaler {
+    /// Construct a new StandardScaler.
+    pub fn new() -> StandardScaler {
+        StandardScaler::default()
+    }
+}
+
+impl Transformer for StandardScaler {
+    fn fit(&mut self, data: &[Vec<f64>]) {
+        let means = column_means(data);
+        let mut stds = vec![0f64; means.len()];
+        for x in data {
+            for ((s, v), m) in stds.iter_mut().zip(x.iter()).zip(means.iter()) {
+                *s += (v - m) * (v - m);
+            }
+        }
+        for s in stds.iter_mut() {
+            *s = (*s / data.len() as f64).sqrt();
+            if *s == 0.0 {
+                *s = 1.0;
+            }
+        }
+        self.means = Some(means);
+        self.stds = Some(stds);
+    }
+
+    fn transform(&self, data: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+        let means = self.means.as_ref()?;
+        let stds = self.stds.as_ref()?;
+        Some(data.iter().map(|x| {
+            x.iter().zip(means.iter()).zip(stds.iter()).map(|((v, m), s)| (v - m) / s).collect()
+        }).collect())
+    }
+
+    fn feature_names(&self) -> Option<Vec<String>> {
+        let means = self.means.as_ref()?;
+        Some((0..means.len()).map(|j| format!("x{}", j)).collect())
+    }
+
+    fn config_hash(&self) -> Option<u64> {
+        Some(hash_of("StandardScaler"))
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedTransformer> {
+        Some(SavedTransformer::StandardScaler(self.clone()))
+    }
+}
+
+/// Encodes categorical features, given as numeric category codes, as one
+/// indicator column per category seen at `fit()`. Categories not seen at
+/// `fit()` encode as all zeros.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OneHotEncoder {
+    categories: Option<Vec<Vec<f64>>>,
+}
+
+impl OneHotEncoder {
+    /// Construct a new OneHotEncoder.
+    pub fn new() -> OneHotEncoder {
+        OneHotEncoder::default()
+    }
+}
+
+impl Transformer for OneHotEncoder {
+    fn fit(&mut self, data: &[Vec<f64>]) {
+        let dim = data.first().map_or(0, |x| x.len());
+        let categories = (0..dim).map(|j| {
+            let mut values: Vec<f64> = data.iter().map(|x| x[j]).collect();
+            values.sort_by(|a, b| a.total_cmp(b));
+            values.dedup();
+            values
+        }).collect();
+        self.categories = Some(categories);
+    }
+
+    fn transform(&self, data: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+        let categories = self.categories.as_ref()?;
+        Some(data.iter().map(|x| {
+            let mut row = Vec::new();
+            for (v, cats) in x.iter().zip(categories.iter()) {
+                row.extend(cats.iter().map(|c| if c == v { 1f64 } else { 0f64 }));
+            }
+            row
+        }).collect())
+    }
+
+    fn feature_names(&self) -> Option<Vec<String>> {
+        let categories = self.categories.as_ref()?;
+        Some(categories.iter().enumerate().flat_map(|(j, cats)| {
+            cats.iter().map(move |c| format!("x{}_{}", j, c))
+        }).collect())
+    }
+
+    fn config_hash(&self) -> Option<u64> {
+        Some(hash_of("OneHotEncoder"))
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedTransformer> {
+        Some(SavedTransformer::OneHotEncoder(self.clone()))
+    }
+}
+
+/// Encodes ordered categorical features, given as numeric category codes,
+/// as the rank of each code in an explicit per-column ordering, e.g. codes
+/// for "low", "medium" and "high" as 0, 1 and 2 whatever the codes are.
+/// Codes missing from a column's ordering encode as the unknown value, -1
+/// by default. The ordering is fixed at construction, so `fit()` only checks
+/// that the data has one column per ordering.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OrdinalEncoder {
+    categories: Vec<Vec<f64>>,
+    unknown_value: f64,
+}
+
+impl OrdinalEncoder {
+    /// Construct a new OrdinalEncoder from the category codes of each
+    /// column, in increasing order.
+    pub fn new(categories: Vec<Vec<f64>>) -> OrdinalEncoder {
+        OrdinalEncoder { categories, unknown_value: -1.0 }
+    }
+
+    /// Encode codes missing from a column's ordering as `value`, e.g.
+    /// `f64::NAN` to flag them.
+    pub fn set_unknown_value(&mut self, value: f64) {
+        self.unknown_value = value;
+    }
+}
+
+impl Transformer for OrdinalEncoder {
+    fn fit(&mut self, data: &[Vec<f64>]) {
+        assert!(data.iter().all(|x| x.len() == self.categories.len()), "data must have one column per category ordering");
+    }
+
+    fn transform(&self, data: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+        Some(data.iter().map(|x| {
+            x.iter().zip(self.categories.iter()).map(|(v, cats)| {
+                cats.iter().position(|c| c == v).map_or(self.unknown_value, |rank| rank as f64)
+            }).collect()
+        }).collect())
+    }
+
+    fn feature_names(&self) -> Option<Vec<String>> {
+        Some((0..self.categories.len()).map(|j| format!("x{}", j)).collect())
+    }
+
+    fn config_hash(&self) -> Option<u64> {
+        let categories: Vec<Vec<u64>> = self.categories.iter()
+            .map(|cats| cats.iter().map(|c| c.to_bits()).collect())
+            .collect();
+        Some(hash_of(&("OrdinalEncoder", categories, self.unknown_value.to_bits())))
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedTransformer> {
+        Some(SavedTransformer::OrdinalEncoder(self.clone()))
+    }
+}
+
+/// What a `ColumnTransformer` does with columns not assigned to any of its
+/// transformers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Remainder {
+    /// Leave them out of the output.
+    Drop,
+    /// Copy them to the output unchanged, after the transformed columns.
+    Passthrough,
+}
+
+/// Applies different transformers to different subsets of columns, e.g. a
+/// scaler to the numeric columns and a one-hot encoder to the categorical
+/// ones. The outputs are concatenated in the order the transformers were
+/// given, followed by the remaining columns if they are passed through.
+#[derive(Clone)]
+pub struct ColumnTransformer {
+    transformers: Vec<(String, Vec<usize>, Box<dyn Transformer>)>,
+    remainder: Remainder,
+    remainder_columns: Option<Vec<usize>>,
+}
+
+impl ColumnTransformer {
+    /// Construct a ColumnTransformer from (name, column indices,
+    /// transformer) entries. Fail if a column is assigned more than once.
+    pub fn new(transformers: Vec<(String, Vec<usize>, Box<dyn Transformer>)>,
+               remainder: Remainder) -> Result<ColumnTransformer, ConfigError> {
+        let mut owners: Vec<(usize, &str)> = Vec::new();
+        for (name, columns, _) in &transformers {
+            for &column in columns {
+                if let Some(&(_, first)) = owners.iter().find(|&&(c, _)| c == column) {
+                    return Err(ConfigError::OverlappingColumns {
+                        column,
+                        first: first.to_string(),
+                        second: name.clone(),
+                    });
+                }
+                owners.push((column, name));
+            }
+        }
+        Ok(ColumnTransformer { transformers, remainder, remainder_columns: None })
+    }
+}
+
+/// Pick the given columns out of every row.
+fn select_columns(data: &[Vec<f64>], columns: &[usize]) -> Vec<Vec<f64>> {
+    data.iter().map(|x| columns.iter().map(|&j| x[j]).collect()).collect()
+}
+
+impl Transformer for ColumnTransformer {
+    fn fit(&mut self, data: &[Vec<f64>]) {
+        for (_, columns, transformer) in self.transformers.iter_mut() {
+            transformer.fit(&select_columns(data, columns));
+        }
+        let dim = data.first().map_or(0, |x| x.len());
+        let assigned: Vec<usize> = self.transformers.iter().flat_map(|(_, c, _)| c.iter().cloned()).collect();
+        self.remainder_columns = Some((0..dim).filter(|j| !assigned.contains(j)).collect());
+    }
+
+    fn transform(&self, data: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+        let remainder_columns = self.remainder_columns.as_ref()?;
+        let mut out: Vec<Vec<f64>> = vec![Vec::new(); data.len()];
+        for (_, columns, transformer) in &self.transformers {
+            let part = transformer.transform(&select_columns(data, columns))?;
+            for (row, part_row) in out.iter_mut().zip(part) {
+                row.extend(part_row);
+            }
+        }
+        if self.remainder == Remainder::Passthrough {
+            for (row, x) in out.iter_mut().zip(data.iter()) {
+                row.extend(remainder_columns.iter().map(|&j| x[j]));
+            }
+        }
+        Some(out)
+    }
+
+    /// Names of the output features as `name__feature`, where a member's
+    /// `x0`, `x1`, ... refer to its own columns. Passed-through columns keep
+    /// their input names.
+    fn feature_names(&self) -> Option<Vec<String>> {
+        let remainder_columns = self.remainder_columns.as_ref()?;
+        let mut names = prefixed_names(self.transformers.iter().map(|(name, _, t)| (name, t)))?;
+        if self.remainder == Remainder::Passthrough {
+            names.extend(remainder_columns.iter().map(|j| format!("x{}", j)));
+        }
+        Some(names)
+    }
+
+    fn config_hash(&self) -> Option<u64> {
+        let mut members = Vec::with_capacity(self.transformers.len());
+        for (name, columns, transformer) in &self.transformers {
+            members.push((name, columns, transformer.config_hash()?));
+        }
+        Some(hash_of(&("ColumnTransformer", members, self.remainder == Remainder::Passthrough)))
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedTransformer> {
+        let mut transformers = Vec::with_capacity(self.transformers.len());
+        for (name, columns, transformer) in &self.transformers {
+            transformers.push((name.clone(), columns.clone(), transformer.save()?));
+        }
+        Some(SavedTransformer::ColumnTransformer {
+            transformers,
+            remainder: self.remainder,
+            remainder_columns: self.remainder_columns.clone(),
+        })
+    }
+}
+
+/// Collect the output feature names of named transformers, prefixing each
+/// with its transformer's name. Return None if any member has no names.
+fn prefixed_names<'a, I>(members: I) -> Option<Vec<String>>
+    where I: Iterator<Item = (&'a String, &'a Box<dyn Transformer>)>
+{
+    let mut names = Vec::new();
+    for (name, transformer) in members {
+        names.extend(transformer.feature_names()?.iter().map(|f| format!("{}__{}", name, f)));
+    }
+    Some(names)
+}
+
+/// Applies several transformers to the same input and concatenates their
+/// outputs, e.g. to combine scaled raw features with derived ones. Outputs
+/// appear in the order the transformers were given, each optionally
+/// multiplied by a weight.
+#[derive(Clone)]
+pub struct FeatureUnion {
+    transformers: Vec<(String, Box<dyn Transformer>, f64)>,
+}
+
+impl FeatureUnion {
+    /// Construct a FeatureUnion from named transformers, all with weight 1.
+    /// Fail if no transformers are given.
+    pub fn new(transformers: Vec<(String, Box<dyn Transformer>)>) -> Result<FeatureUnion, ConfigError> {
+        FeatureUnion::with_weights(transformers.into_iter().map(|(name, t)| (name, t, 1f64)).collect())
+    }
+
+    /// Construct a FeatureUnion from named transformers whose outputs are
+    /// multiplied by the given weights. Fail if no transformers are given.
+    pub fn with_weights(transformers: Vec<(String, Box<dyn Transformer>, f64)>) -> Result<FeatureUnion, ConfigError> {
+        if transformers.is_empty() {
+            return Err(ConfigError::Empty);
+        }
+        Ok(FeatureUnion { transformers })
+    }
+}
+
+impl Transformer for FeatureUnion {
+    fn fit(&mut self, data: &[Vec<f64>]) {
+        for (_, transformer, _) in self.transformers.iter_mut() {
+            transformer.fit(data);
+        }
+    }
+
+    fn transform(&self, data: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+        let mut out: Vec<Vec<f64>> = vec![Vec::new(); data.len()];
+        for (_, transformer, weight) in &self.transformers {
+            let part = transformer.transform(data)?;
+            for (row, part_row) in out.iter_mut().zip(part) {
+                row.extend(part_row.iter().map(|v| v * weight));
+            }
+        }
+        Some(out)
+    }
+
+    /// Names of the output features as `name__feature`.
+    fn feature_names(&self) -> Option<Vec<String>> {
+        prefixed_names(self.transformers.iter().map(|(name, t, _)| (name, t)))
+    }
+
+    fn config_hash(&self) -> Option<u64> {
+        let mut members = Vec::with_capacity(self.transformers.len());
+        for (name, transformer, weight) in &self.transformers {
+            members.push((name, transformer.config_hash()?, weight.to_bits()));
+        }
+        Some(hash_of(&("FeatureUnion", members)))
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedTransformer> {
+        let mut transformers = Vec::with_capacity(self.transformers.len());
+        for (name, transformer, weight) in &self.transformers {
+            transformers.push((name.clone(), transformer.save()?, *weight));
+        }
+        Some(SavedTransformer::FeatureUnion(transformers))
+    }
+}
+
+/// The configuration and learned state of one of the crate's transformers,
+/// as returned by `Transformer::save()`. Boxed transformers can't be
+/// serialized directly, as their concrete type is unknown; this enum can,
+/// and `into_transformer()` turns it back into a box.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SavedTransformer {
+    StandardScaler(StandardScaler),
+    OneHotEncoder(OneHotEncoder),
+    OrdinalEncoder(OrdinalEncoder),
+    ColumnTransformer {
+        transformers: Vec<(String, Vec<usize>, SavedTransformer)>,
+        remainder: Remainder,
+        remainder_columns: Option<Vec<usize>>,
+    },
+    FeatureUnion(Vec<(String, SavedTransformer, f64)>),
+}
+
+#[cfg(feature = "serde")]
+impl SavedTransformer {
+    /// Rebuild the transformer, learned state included.
+    pub fn into_transformer(self) -> Box<dyn Transformer> {
+        match self {
+            SavedTransformer::StandardScaler(t) => Box::new(t),
+            SavedTransformer::OneHotEncoder(t)  => Box::new(t),
+            SavedTransformer::OrdinalEncoder(t) => Box::new(t),
+            SavedTransformer::ColumnTransformer { transformers, remainder, remainder_columns } => {
+                let transformers = transformers.into_iter()
+                    .map(|(name, columns, t)| (name, columns, t.into_transformer()))
+                    .collect();
+                Box::new(ColumnTransformer { transformers, remainder, remainder_columns })
+            },
+            SavedTransformer::FeatureUnion(transformers) => {
+                let transformers = transformers.into_iter()
+                    .map(|(name, t, weight)| (name, t.into_transformer(), weight))
+                    .collect();
+                Box::new(FeatureUnion { transformers })
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mixed_data() -> Vec<Vec<f64>> {
+        // Columns: two numeric features, a category code, and an id.
+        vec![
+            vec![1.0, 10.0, 0.0, 100.0],
+            vec![2.0, 20.0, 2.0, 101.0],
+            vec![3.0, 30.0, 1.0, 102.0],
+            vec![4.0, 40.0, 2.0, 103.0],
+        ]
+    }
+
+    fn column_transformer(remainder: Remainder) -> ColumnTransformer {
+        ColumnTransformer::new(vec![
+            ("numeric".to_string(), vec![0, 1], Box::new(StandardScaler::new())),
+            ("category".to_string(), vec![2], Box::new(OneHotEncoder::new())),
+        ], remainder).unwrap()
+    }
+
+    #[test]
+    fn test_standard_scaler() {
+        let mut scaler = StandardScaler::new();
+        assert_eq!(scaler.transform(&[vec![1.0]]), None);
+        let out = scaler.fit_transform(&[vec![1.0, 5.0], vec![3.0, 5.0]]);
+        assert_eq!(out, vec![vec![-1.0, 0.0], vec![1.0, 0.0]]);
+    }
+
+    #[test]
+    fn test_one_hot_encoder() {
+        let mut encoder = OneHotEncoder::new();
+        encoder.fit(&[vec![2.0], vec![0.0], vec![2.0]]);
+        let out = encoder.transform(&[vec![0.0], vec![2.0], vec![1.0]]).unwrap();
+        assert_eq!(out, vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![0.0, 0.0]]);
+    }
+
+    #[test]
+    fn test_ordinal_encoder() {
+        // Codes 7, 3 and 5 stand for low, medium and high.
+        let mut encoder = OrdinalEncoder::new(vec![vec![7.0, 3.0, 5.0]]);
+        let data = [vec![5.0], vec![7.0], vec![3.0], vec![5.0]];
+        assert_eq!(encoder.fit_transform(&data), vec![vec![2.0], vec![0.0], vec![1.0], vec![2.0]]);
+        let reversed: Vec<Vec<f64>> = data.iter().rev().cloned().collect();
+        assert_eq!(encoder.fit_transform(&reversed), vec![vec![2.0], vec![1.0], vec![0.0], vec![2.0]]);
+
+        assert_eq!(encoder.transform(&[vec![4.0]]).unwrap(), vec![vec![-1.0]]);
+        encoder.set_unknown_value(f64::NAN);
+        assert!(encoder.transform(&[vec![4.0]]).unwrap()[0][0].is_nan());
+    }
+
+    #[test]
+    fn test_column_transformer_drop() {
+        let data = mixed_data();
+        let mut ct = column_transformer(Remainder::Drop);
+        assert_eq!(ct.transform(&data), None);
+
+        let out = ct.fit_transform(&data);
+        let mut scaler = StandardScaler::new();
+        let scaled = scaler.fit_transform(&select_columns(&data, &[0, 1]));
+        assert_eq!(out.len(), 4);
+        for (row, s) in out.iter().zip(scaled.iter()) {
+            assert_eq!(row.len(), 2 + 3);
+            assert_eq!(&row[..2], &s[..]);
+        }
+        assert_eq!(&out[0][2..], &[1.0, 0.0, 0.0]);
+        assert_eq!(&out[1][2..], &[0.0, 0.0, 1.0]);
+        assert_eq!(&out[2][2..], &[0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_column_transformer_passthrough() {
+        let data = mixed_data();
+        let mut ct = column_transformer(Remainder::Passthrough);
+        let out = ct.fit_transform(&data);
+        for (row, x) in out.iter().zip(data.iter()) {
+            assert_eq!(row.len(), 2 + 3 + 1);
+            assert_eq!(row[5], x[3]);
+        }
+    }
+
+    #[test]
+    fn test_column_transformer_feature_names() {
+        let mut ct = column_transformer(Remainder::Passthrough);
+        assert_eq!(ct.feature_names(), None);
+        ct.fit(&mixed_data());
+        assert_eq!(ct.feature_names().unwrap(), vec![
+            "numeric__x0", "numeric__x1", "category__x0_0", "category__x0_1", "category__x0_2", "x3",
+        ]);
+    }
+
+    #[test]
+    fn test_feature_union() {
+        let data = mixed_data();
+        let mut union = FeatureUnion::with_weights(vec![
+            ("scaled".to_string(), Box::new(StandardScaler::new()) as Box<dyn Transformer>, 1.0),
+            ("onehot".to_string(), Box::new(OneHotEncoder::new()), 2.0),
+        ]).unwrap();
+        assert_eq!(union.transform(&data), None);
+        let out = union.fit_transform(&data);
+
+        let scaled = StandardScaler::new().fit_transform(&data);
+        let onehot = OneHotEncoder::new().fit_transform(&data);
+        for ((row, s), o) in out.iter().zip(scaled.iter()).zip(onehot.iter()) {
+            assert_eq!(row.len(), s.len() + o.len());
+            assert_eq!(&row[..s.len()], &s[..]);
+            let weighted: Vec<f64> = o.iter().map(|v| v * 2.0).collect();
+            assert_eq!(&row[s.len()..], &weighted[..]);
+        }
+
+        let names = union.feature_names().unwrap();
+        assert_eq!(names.len(), out[0].len());
+        assert_eq!(names[0], "scaled__x0");
+        assert_eq!(names[4], "onehot__x0_1");
+    }
+
+    #[test]
+    fn test_feature_union_empty() {
+        assert!(FeatureUnion::new(Vec::new()).err() == Some(ConfigError::Empty));
+    }
+
+    #[test]
+    fn test_column_transformer_overlap() {
+        let result = ColumnTransformer::new(vec![
+            ("a".to_string(), vec![0, 1], Box::new(StandardScaler::new()) as Box<dyn Transformer>),
+            ("b".to_string(), vec![2, 1], Box::new(OneHotEncoder::new())),
+        ], Remainder::Drop);
+        match result {
+            Err(e) => assert_eq!(e, ConfigError::OverlappingColumns {
+                column: 1,
+                first: "a".to_string(),
+                second: "b".to_string(),
+            }),
+            Ok(_) => panic!("overlapping columns were accepted"),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+/// Turns text documents into vectors of term counts (a bag of words). Text
+/// is lowercased and split into tokens at every character that isn't
+/// alphanumeric. Each term seen at `fit()` gets a column, in alphabetical
+/// order; terms not seen at `fit()` are ignored.
+///
+/// By default the terms are single tokens. With `set_ngram_range()` they
+/// can also be n-grams: runs of consecutive tokens, joined by a single
+/// space, as in "new york". N-grams never span documents, but they do span
+/// the punctuation between tokens, which is discarded like any other
+/// separator.
+#[derive(Debug, Clone)]
+pub struct CountVectorizer {
+    ngram_range: (usize, usize),
+    vocabulary: Option<HashMap<String, usize>>,
+}
+
+impl Default for CountVectorizer {
+    fn default() -> CountVectorizer {
+        CountVectorizer { ngram_range: (1, 1), vocabulary: None }
+    }
+}
+
+impl CountVectorizer {
+    /// Construct a new CountVectorizer.
+    pub fn new() -> CountVectorizer {
+        CountVectorizer::default()
+    }
+
+    /// Use n-grams of `min_n` to `max_n` tokens, inclusive, as terms. Takes
+    /// effect at the next `fit()`.
+    pub fn set_ngram_range(&mut self, (min_n, max_n): (usize, usize)) {
+        assert!(min_n >= 1 && min_n <= max_n, "ngram_range must satisfy 1 <= min_n <= max_n");
+        self.ngram_range = (min_n, max_n);
+    }
+
+    /// Construct a fitted CountVectorizer from a vocabulary mapping each
+    /// term to its column, e.g. one exported with `vocabulary()`. It
+    /// transforms documents exactly as the vectorizer the vocabulary came
+    /// from. The n-gram range is taken from the shortest and longest terms.
+    ///
+    /// # Panics
+    ///
+    /// If the columns are not exactly `0..vocabulary.len()`.
+    pub fn from_vocabulary(vocabulary: HashMap<String, usize>) -> CountVectorizer {
+        let mut seen = vec![false; vocabulary.len()];
+        for &column in vocabulary.values() {
+            assert!(column < seen.len() && !seen[column], "vocabulary columns must be 0..n, each used once");
+            seen[column] = true;
+        }
+        let lengths = vocabulary.keys().map(|term| term.split(' ').count());
+        let ngram_range = (lengths.clone().min().unwrap_or(1), lengths.max().unwrap_or(1));
+        CountVectorizer { ngram_range, vocabulary: Some(vocabulary) }
+    }
+
+    /// Learn the vocabulary of `docs`.
+    pub fn fit<S: AsRef<str>>(&mut self, docs: &[S]) {
+        let mut terms: Vec<String> = docs.iter().flat_map(|doc| self.terms(doc.as_ref())).collect();
+        terms.sort();
+        terms.dedup();
+        self.vocabulary = Some(terms.into_iter().enumerate().map(|(column, term)| (term, column)).collect());
+    }
+
+    /// Count the vocabulary terms in each document. Return None if called
+    /// before `fit()`.
+    pub fn transform<S: AsRef<str>>(&self, docs: &[S]) -> Option<Vec<Vec<f64>>> {
+        let vocabulary = self.vocabulary.as_ref()?;
+        Some(docs.iter().map(|doc| {
+            let mut counts = vec![0f64; vocabulary.len()];
+            for term in self.terms(doc.as_ref()) {
+                if let Some(&column) = vocabulary.get(&term) {
+                    counts[column] += 1.0;
+                }
+            }
+            counts
+        }).collect())
+    }
+
+    /// Learn the vocabulary of `docs` and count its terms in them.
+    pub fn fit_transform<S: AsRef<str>>(&mut self, docs: &[S]) -> Vec<Vec<f64>> {
+        self.fit(docs);
+        self.transform(docs).unwrap()
+    }
+
+    /// The learned mapping from term to column. Return None if called
+    /// before `fit()`.
+    pub fn vocabulary(&self) -> Option<&HashMap<String, usize>> {
+        self.vocabulary.as_ref()
+    }
+
+    /// Get the terms of a document: its n-grams for every n in the range,
+    /// shortest first.
+    fn terms(&self, doc: &str) -> Vec<String> {
+        let tokens = tokenize(doc);
+        let (min_n, max_n) = self.ngram_range;
+        (min_n..=max_n).flat_map(|n| tokens.windows(n).map(|window| window.join(" "))).collect()
+    }
+}
+
+/// Split a document into lowercase alphanumeric tokens.
+fn tokenize(doc: &str) -> Vec<String> {
+    doc.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts() {
+        let mut vectorizer = CountVectorizer::new();
+        assert_eq!(vectorizer.transform(&["a"]), None);
+        let counts = vectorizer.fit_transform(&["The cat sat.", "the dog, the CAT"]);
+        // Columns: cat, dog, sat, the.
+        assert_eq!(counts, vec![vec![1.0, 0.0, 1.0, 1.0], vec![1.0, 1.0, 0.0, 2.0]]);
+        assert_eq!(vectorizer.transform(&["a bird and a cat"]).unwrap(), vec![vec![1.0, 0.0, 0.0, 0.0]]);
+    }
+
+    #[test]
+    fn test_vocabulary_round_trip() {
+        let train = ["spam spam eggs", "ham and eggs", "green eggs and ham"];
+        let test = ["eggs, ham, spam and toast", "nothing known here"];
+        let mut vectorizer = CountVectorizer::new();
+        vectorizer.fit(&train);
+
+        let rebuilt = CountVectorizer::from_vocabulary(vectorizer.vocabulary().unwrap().clone());
+        assert_eq!(rebuilt.vocabulary(), vectorizer.vocabulary());
+        assert_eq!(rebuilt.transform(&test), vectorizer.transform(&test));
+        assert_eq!(rebuilt.transform(&train), vectorizer.transform(&train));
+    }
+
+    #[test]
+    fn test_bigrams() {
+        let docs = ["I moved to New York.", "New ideas, York ham"];
+        let mut vectorizer = CountVectorizer::new();
+        vectorizer.set_ngram_range((1, 2));
+        let counts = vectorizer.fit_transform(&docs);
+        let vocabulary = vectorizer.vocabulary().unwrap();
+        let new_york = vocabulary["new york"];
+        assert_eq!((counts[0][new_york], counts[1][new_york]), (1.0, 0.0));
+        // Unigrams are kept, and bigrams run across punctuation.
+        assert_eq!(counts[1][vocabulary["york"]], 1.0);
+        assert!(vocabulary.contains_key("ideas york"));
+        assert!(!vocabulary.contains_key("york new"));
+
+        let rebuilt = CountVectorizer::from_vocabulary(vocabulary.clone());
+        assert_eq!(rebuilt.transform(&docs).unwrap(), counts);
+
+        vectorizer.set_ngram_range((2, 2));
+        vectorizer.fit(&docs);
+        assert!(vectorizer.vocabulary().unwrap().keys().all(|term| term.contains(' ')));
+    }
+}
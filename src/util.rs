@@ -1,17 +1,51 @@
+#[cfg(feature = "std")]
 use std::collections::HashMap;
-use std::collections::hash_map::Iter;
-use std::hash::Hash;
-use std::num;
+#[cfg(feature = "std")]
+use std::collections::hash_map;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, Hash};
+#[cfg(not(feature = "std"))]
+use alloc::collections::{btree_map, BTreeMap};
+#[cfg(not(feature = "std"))]
+use alloc::vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use core::marker::PhantomData;
+#[cfg(not(feature = "std"))]
+use core::ops::{Deref, DerefMut};
+use super::float;
+use super::parallel;
 
 /// Compute the Euclidean distance between two vectors.
 pub fn euclidean_distance(v1: &[f64], v2: &[f64]) -> f64 {
-    squared_distance(v1, v2).sqrt()
+    float::sqrt(squared_distance(v1, v2))
 }
 
 /// Compute the squared norm of the vector difference of v1 and v2.
 pub fn squared_distance(v1: &[f64], v2: &[f64]) -> f64 {
-    let delta: Vec<_> = v1.iter().zip(v2.iter()).map(|(a, b)| { a - b }).collect();
-    dot_product(&delta, &delta)
+    v1.iter().zip(v2.iter()).fold(0f64, |acc, (a, b)| {
+        acc + (a - b) * (a - b)
+    })
+}
+
+/// Compute each dimension's share of the squared distance between v1 and
+/// v2, `(a_i - b_i)²`. They sum to `squared_distance(v1, v2)`, which shows
+/// which features make two points close or far.
+pub fn squared_distance_contributions(v1: &[f64], v2: &[f64]) -> Vec<f64> {
+    v1.iter().zip(v2.iter()).map(|(a, b)| (a - b) * (a - b)).collect()
+}
+
+/// Compute the cosine distance between two vectors: one minus the cosine
+/// of the angle between them, from 0 for the same direction to 2 for
+/// opposite ones. Only direction matters, not magnitude. A zero vector is
+/// at distance 1 from any other vector, and 0 from itself.
+pub fn cosine_distance(v1: &[f64], v2: &[f64]) -> f64 {
+    let norms = float::sqrt(dot_product(v1, v1) * dot_product(v2, v2));
+    if norms == 0.0 {
+        return if v1.iter().chain(v2.iter()).all(|&v| v == 0.0) { 0.0 } else { 1.0 };
+    }
+    1.0 - dot_product(v1, v2) / norms
 }
 
 /// Compute the dot product of two vectors.
@@ -21,15 +55,545 @@ pub fn dot_product(v1: &[f64], v2: &[f64]) -> f64 {
     })
 }
 
-/// An item counter, similar to Python's collections.Counter.
-pub struct Counter<T> {
-    map: HashMap<T, u64>
+/// Multiply the matrix `m`, given as rows, by the column vector `v`.
+pub fn matvec(m: &[Vec<f64>], v: &[f64]) -> Vec<f64> {
+    m.iter().map(|row| {
+        assert_eq!(row.len(), v.len(), "matrix columns and vector length differ");
+        dot_product(row, v)
+    }).collect()
+}
+
+/// Multiply the matrices `a` and `b`, given as rows. `a` must have as many
+/// columns as `b` has rows.
+pub fn matmul(a: &[Vec<f64>], b: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let cols = b.first().map_or(0, |row| row.len());
+    assert!(b.iter().all(|row| row.len() == cols), "rows of b differ in length");
+    let b_t: Vec<Vec<f64>> = (0..cols).map(|j| b.iter().map(|row| row[j]).collect()).collect();
+    a.iter().map(|row| {
+        assert_eq!(row.len(), b.len(), "columns of a and rows of b differ");
+        b_t.iter().map(|col| dot_product(row, col)).collect()
+    }).collect()
+}
+
+/// Compute the mean of each column of `data`. All rows must have the same
+/// length.
+pub fn column_means(data: &[Vec<f64>]) -> Vec<f64> {
+    let dim = data.first().map_or(0, |x| x.len());
+    let mut means = vec![0f64; dim];
+    for x in data {
+        for (m, v) in means.iter_mut().zip(x.iter()) {
+            *m += v;
+        }
+    }
+    for m in means.iter_mut() {
+        *m /= data.len() as f64;
+    }
+    means
+}
+
+/// Compute the sample covariance matrix of the columns of `data`: entry
+/// (i, j) is the covariance of features i and j, with the diagonal holding
+/// the variances. It divides by `n - 1` (Bessel's correction), giving an
+/// unbiased estimate of the population covariance the rows were drawn
+/// from; multiply by `(n - 1) / n` for the covariance of the rows
+/// themselves. All rows must have the same length, and there must be at
+/// least two.
+pub fn covariance_matrix(data: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    assert!(data.len() >= 2, "covariance needs at least two rows");
+    let means = column_means(data);
+    let dim = means.len();
+    let mut cov = vec![vec![0f64; dim]; dim];
+    for x in data {
+        for (row, (xi, mi)) in cov.iter_mut().zip(x.iter().zip(means.iter())) {
+            for (c, (xj, mj)) in row.iter_mut().zip(x.iter().zip(means.iter())) {
+                *c += (xi - mi) * (xj - mj);
+            }
+        }
+    }
+    let denom = (data.len() - 1) as f64;
+    for c in cov.iter_mut().flat_map(|row| row.iter_mut()) {
+        *c /= denom;
+    }
+    cov
+}
+
+/// Invert a square matrix by Gauss-Jordan elimination with partial
+/// pivoting. Return None if the matrix is singular, or so close to it that
+/// a pivot is negligible next to the largest entry.
+pub fn invert_matrix(m: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = m.len();
+    assert!(m.iter().all(|row| row.len() == n), "matrix must be square");
+    let scale = m.iter().flat_map(|row| row.iter()).fold(0f64, |acc, v| acc.max(v.abs()));
+    let tolerance = scale * n as f64 * f64::EPSILON;
+    // Row-reduce [m | I] to [I | m^-1].
+    let mut a: Vec<Vec<f64>> = m.to_vec();
+    let mut inv: Vec<Vec<f64>> = (0..n).map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() <= tolerance {
+            return None;
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let p = a[col][col];
+        for j in 0..n {
+            a[col][j] /= p;
+            inv[col][j] /= p;
+        }
+        for i in 0..n {
+            if i == col || a[i][col] == 0.0 {
+                continue;
+            }
+            let factor = a[i][col];
+            for j in 0..n {
+                a[i][j] -= factor * a[col][j];
+                inv[i][j] -= factor * inv[col][j];
+            }
+        }
+    }
+    Some(inv)
+}
+
+/// Compute the `q`-th quantile (`0 <= q <= 1`) of `values`, interpolating
+/// linearly between the two nearest ranks. Return NaN if `values` is empty.
+pub fn quantile(values: &[f64], q: f64) -> f64 {
+    assert!((0.0..=1.0).contains(&q), "quantile must be in [0, 1]");
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let pos = q * (sorted.len() - 1) as f64;
+    let lo = pos as usize;
+    let hi = (lo + 1).min(sorted.len() - 1);
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f64)
+}
+
+/// Running count, mean and variance of a stream of values, updated one
+/// value at a time with Welford's method, which avoids the cancellation of
+/// the naive sum-of-squares formula.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RunningStats {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    /// Construct a new RunningStats with no values.
+    pub fn new() -> RunningStats {
+        RunningStats::default()
+    }
+
+    /// Add a value.
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// The number of values added.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The mean of the values added, or 0 if there are none.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The population variance of the values added, or 0 if there are none.
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.m2 / self.count as f64 }
+    }
+}
+
+/// Compute the Levenshtein (edit) distance between two sequences: the
+/// number of insertions, deletions and substitutions needed to turn `a`
+/// into `b`. Unlike the vector metrics, the sequences may differ in length.
+pub fn levenshtein_distance<T: PartialEq>(a: &[T], b: &[T]) -> f64 {
+    // Only one row of the DP table is kept; `diag` holds the entry above and
+    // to the left of the one being computed.
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let above = row[j + 1];
+            let subst = if x == y { diag } else { diag + 1 };
+            row[j + 1] = subst.min(above + 1).min(row[j] + 1);
+            diag = above;
+        }
+    }
+    row[b.len()] as f64
+}
+
+/// Compute the Jaccard distance between two sets, given as slices without
+/// repeated elements: one minus the size of their intersection over the
+/// size of their union. Disjoint sets are at distance 1, equal ones at 0;
+/// two empty sets count as equal.
+pub fn jaccard_distance<T: PartialEq>(a: &[T], b: &[T]) -> f64 {
+    let common = a.iter().filter(|x| b.contains(x)).count();
+    let union = a.len() + b.len() - common;
+    if union == 0 {
+        return 0.0;
+    }
+    1.0 - common as f64 / union as f64
+}
+
+/// A distance metric between two examples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Metric {
+    /// Euclidean distance between equal-length vectors.
+    Euclidean,
+    /// Edit distance between sequences of possibly differing length.
+    Levenshtein,
+    /// Cosine distance between equal-length vectors, which ignores their
+    /// magnitudes.
+    Cosine,
+}
+
+impl Metric {
+    /// Whether the metric accepts examples of differing lengths.
+    pub fn allows_varying_length(&self) -> bool {
+        match *self {
+            Metric::Euclidean   => false,
+            Metric::Levenshtein => true,
+            Metric::Cosine      => false,
+        }
+    }
+
+    /// Compute the distance between `v1` and `v2` under this metric.
+    pub fn distance(&self, v1: &[f64], v2: &[f64]) -> f64 {
+        match *self {
+            Metric::Euclidean   => euclidean_distance(v1, v2),
+            Metric::Levenshtein => levenshtein_distance(v1, v2),
+            Metric::Cosine      => cosine_distance(v1, v2),
+        }
+    }
+}
+
+/// Compute the matrix of distances between every pair of rows in `data`.
+/// Rows are computed in parallel when the `parallel` feature is enabled.
+pub fn pairwise_distances(data: &[Vec<f64>], metric: Metric) -> Vec<Vec<f64>> {
+    parallel::map_rows(data, |x| {
+        data.iter().map(|y| metric.distance(x, y)).collect()
+    })
+}
+
+/// Compute the cosine similarity between every pair of rows in `data`: the
+/// cosine of the angle between them, from 1 for the same direction to -1
+/// for opposite ones. Each row is normalized once, and each pair's dot
+/// product computed once for both halves of the symmetric matrix. A zero
+/// row has similarity 0 to every row, itself included.
+pub fn cosine_similarity_matrix(data: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let normalized: Vec<Option<Vec<f64>>> = data.iter().map(|x| {
+        let norm = float::sqrt(dot_product(x, x));
+        if norm > 0.0 { Some(x.iter().map(|v| v / norm).collect()) } else { None }
+    }).collect();
+    let n = data.len();
+    let mut similarity = vec![vec![0f64; n]; n];
+    for (i, x) in normalized.iter().enumerate() {
+        let x = match x {
+            Some(x) => x,
+            None    => continue,
+        };
+        similarity[i][i] = 1.0;
+        for (j, y) in normalized.iter().enumerate().skip(i + 1) {
+            if let Some(y) = y {
+                let s = dot_product(x, y);
+                similarity[i][j] = s;
+                similarity[j][i] = s;
+            }
+        }
+    }
+    similarity
+}
+
+/// Group the rows of a dataset by their label, returning the row indices
+/// belonging to each label.
+#[cfg(feature = "std")]
+pub fn group_by_label<T: Hash + Eq + Clone>(data: &[Vec<f64>], labels: &[T]) -> HashMap<T, Vec<usize>> {
+    assert_eq!(data.len(), labels.len(), "data and labels differ in length");
+    let mut groups: HashMap<T, Vec<usize>> = HashMap::new();
+    for (i, label) in labels.iter().enumerate() {
+        groups.entry(label.clone()).or_default().push(i);
+    }
+    groups
+}
+
+/// A small, seedable pseudo-random number generator (SplitMix64). It is
+/// fast and reproducible across platforms, but not cryptographically secure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Construct a generator from a seed. Equal seeds give equal sequences.
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Draw a uniformly random `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Draw a uniformly random `f64` in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Draw a standard normal `f64` (mean 0, variance 1), by the Box-Muller
+    /// transform.
+    pub fn next_gaussian(&mut self) -> f64 {
+        // 1 - u is in (0, 1], keeping the logarithm finite.
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        float::sqrt(-2.0 * float::ln(u1)) * float::cos(2.0 * core::f64::consts::PI * u2)
+    }
+
+    /// Draw a uniformly random integer in `[0, n)`. `n` must be positive.
+    pub fn gen_range(&mut self, n: usize) -> usize {
+        assert!(n > 0, "empty range");
+        // Rejection sampling avoids the bias of a plain modulo.
+        let n = n as u64;
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return (x % n) as usize;
+            }
+        }
+    }
 }
 
-impl<T: Hash + Eq> Counter<T> {
+/// The seed used by every stochastic function and estimator of the crate
+/// when it isn't given a `RandomState`.
+pub const DEFAULT_SEED: u64 = 0x5eed;
+
+/// The source of randomness for the stochastic parts of the crate. Every
+/// one of them takes a `random_state: Option<RandomState>`, where `None`
+/// means `RandomState::new(DEFAULT_SEED)`, so runs are reproducible unless
+/// entropy is asked for explicitly with `RandomState::from_entropy()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RandomState {
+    rng: Rng,
+}
+
+impl RandomState {
+    /// Construct a RandomState from a seed.
+    pub fn new(seed: u64) -> RandomState {
+        RandomState { rng: Rng::new(seed) }
+    }
+
+    /// Construct a RandomState seeded from the operating system's entropy
+    /// and the clock, for runs that should differ.
+    #[cfg(feature = "std")]
+    pub fn from_entropy() -> RandomState {
+        use std::hash::{BuildHasher, Hasher};
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+            hasher.write_u128(now.as_nanos());
+        }
+        RandomState::new(hasher.finish())
+    }
+
+    /// Use `random_state`, or the default one if it is None.
+    pub fn resolve(random_state: Option<RandomState>) -> RandomState {
+        random_state.unwrap_or_default()
+    }
+
+    /// Derive an independent child state, e.g. for a parallel sub-task.
+    /// Each call advances this state once, so the n-th child is the same
+    /// however much randomness its siblings go on to use.
+    pub fn split(&mut self) -> RandomState {
+        RandomState::new(self.rng.next_u64())
+    }
+
+    /// The generator to draw random numbers from.
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+}
+
+impl Default for RandomState {
+    fn default() -> RandomState {
+        RandomState::new(DEFAULT_SEED)
+    }
+}
+
+/// Draw a uniform random sample of `k` items from a stream of unknown
+/// length (reservoir sampling). If the stream has fewer than `k` items, all
+/// of them are returned.
+pub fn reservoir_sample<T: Clone>(it: impl Iterator<Item=T>, k: usize, random_state: Option<RandomState>) -> Vec<T> {
+    let mut state = RandomState::resolve(random_state);
+    let rng = state.rng();
+    let mut reservoir = Vec::with_capacity(k);
+    for (i, item) in it.enumerate() {
+        if i < k {
+            reservoir.push(item);
+        } else {
+            let j = rng.gen_range(i + 1);
+            if j < k {
+                reservoir[j] = item;
+            }
+        }
+    }
+    reservoir
+}
+
+/// Randomly split the indices `0..n` into training and validation indices,
+/// holding out `validation_fraction` of them (rounded, but at least one and
+/// leaving at least one for training when `n > 1`). Both lists are in
+/// increasing order.
+pub fn train_validation_split(n: usize, validation_fraction: f64, random_state: Option<RandomState>) -> (Vec<usize>, Vec<usize>) {
+    assert!((0.0..1.0).contains(&validation_fraction), "validation fraction must be in [0, 1)");
+    let mut n_val = (n as f64 * validation_fraction + 0.5) as usize;
+    if validation_fraction > 0.0 && n > 1 {
+        n_val = n_val.clamp(1, n - 1);
+    }
+    let mut state = RandomState::resolve(random_state);
+    let rng = state.rng();
+    let mut indices: Vec<usize> = (0..n).collect();
+    // Partial Fisher-Yates shuffle: the first n_val slots are the sample.
+    for i in 0..n_val {
+        let j = i + rng.gen_range(n - i);
+        indices.swap(i, j);
+    }
+    let mut validation = indices[..n_val].to_vec();
+    let mut train = indices[n_val..].to_vec();
+    validation.sort_unstable();
+    train.sort_unstable();
+    (train, validation)
+}
+
+/// Compute the ratio of the most to the least frequent label's count: 1 for
+/// perfectly balanced labels, and growing as the classes get more skewed.
+/// Return 1 for no labels.
+pub fn imbalance_ratio<T: CounterKey>(labels: &[T]) -> f64 {
+    let ctr = Counter::with_iterator(labels.iter());
+    let counts = ctr.iter().map(|(_, &count)| count);
+    match (counts.clone().max(), counts.min()) {
+        (Some(max), Some(min)) => max as f64 / min as f64,
+        _                      => 1.0,
+    }
+}
+
+/// Compute the effective number of classes of a label sequence: two to the
+/// power of its entropy in bits (the exponential of its entropy in nats).
+/// k equally frequent labels give k, and labels dominated by one give
+/// about 1. Return 1 for no labels.
+pub fn effective_num_classes<T: CounterKey>(labels: &[T]) -> f64 {
+    let n = labels.len() as f64;
+    let ctr = Counter::with_iterator(labels.iter());
+    let entropy = -ctr.iter().fold(0f64, |acc, (_, &count)| {
+        let p = count as f64 / n;
+        acc + p * float::ln(p)
+    });
+    float::exp(entropy)
+}
+
+/// Bound on the items a `Counter` can hold: `Hash + Eq` with the `std`
+/// feature, where counts live in a `HashMap`, and `Ord` without it, where
+/// they live in a `BTreeMap`.
+#[cfg(feature = "std")]
+pub trait CounterKey: Hash + Eq {}
+#[cfg(feature = "std")]
+impl<T: Hash + Eq> CounterKey for T {}
+
+/// Bound on the items a `Counter` can hold: `Hash + Eq` with the `std`
+/// feature, where counts live in a `HashMap`, and `Ord` without it, where
+/// they live in a `BTreeMap`.
+#[cfg(not(feature = "std"))]
+pub trait CounterKey: Ord {}
+#[cfg(not(feature = "std"))]
+impl<T: Ord> CounterKey for T {}
+
+/// Bound on the hasher of a `Counter`: a `BuildHasher` with the `std`
+/// feature, and anything without it, where there is nothing to hash.
+#[cfg(feature = "std")]
+pub trait CounterHasher: BuildHasher {}
+#[cfg(feature = "std")]
+impl<S: BuildHasher> CounterHasher for S {}
+
+/// Bound on the hasher of a `Counter`: a `BuildHasher` with the `std`
+/// feature, and anything without it, where there is nothing to hash.
+#[cfg(not(feature = "std"))]
+pub trait CounterHasher {}
+#[cfg(not(feature = "std"))]
+impl<S> CounterHasher for S {}
+
+/// The hasher a `Counter` uses unless told otherwise: the standard
+/// library's SipHash with the `std` feature, and a placeholder without it.
+#[cfg(feature = "std")]
+pub type DefaultCounterHasher = hash_map::RandomState;
+/// The hasher a `Counter` uses unless told otherwise: the standard
+/// library's SipHash with the `std` feature, and a placeholder without it.
+#[cfg(not(feature = "std"))]
+pub type DefaultCounterHasher = ();
+
+#[cfg(feature = "std")]
+type CounterMap<T, S> = HashMap<T, u64, S>;
+
+/// A `BTreeMap` that ignores the hasher it is given, so that `Counter` has
+/// the same shape with and without the `std` feature.
+#[cfg(not(feature = "std"))]
+struct CounterMap<T, S> {
+    map: BTreeMap<T, u64>,
+    hasher: PhantomData<S>,
+}
+
+#[cfg(not(feature = "std"))]
+impl<T: Ord, S> CounterMap<T, S> {
+    fn with_hasher(_hasher: S) -> CounterMap<T, S> {
+        CounterMap { map: BTreeMap::new(), hasher: PhantomData }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T, S> Deref for CounterMap<T, S> {
+    type Target = BTreeMap<T, u64>;
+
+    fn deref(&self) -> &BTreeMap<T, u64> {
+        &self.map
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T, S> DerefMut for CounterMap<T, S> {
+    fn deref_mut(&mut self) -> &mut BTreeMap<T, u64> {
+        &mut self.map
+    }
+}
+
+/// Iterator over the items of a `Counter` and their counts.
+#[cfg(feature = "std")]
+pub type CounterIter<'a, T> = hash_map::Iter<'a, T, u64>;
+/// Iterator over the items of a `Counter` and their counts.
+#[cfg(not(feature = "std"))]
+pub type CounterIter<'a, T> = btree_map::Iter<'a, T, u64>;
+
+/// An item counter, similar to Python's collections.Counter. With the
+/// `std` feature, counts are kept in a `HashMap` using the hasher `S`; a
+/// faster one than the default SipHash, such as FxHash, can speed up hot
+/// counting loops where hash flooding is not a concern.
+pub struct Counter<T, S = DefaultCounterHasher> {
+    map: CounterMap<T, S>
+}
+
+impl<T: CounterKey> Counter<T> {
     /// Construct an empty Counter.
     pub fn new() -> Counter<T> {
-        Counter::<T> { map: HashMap::new() }
+        Counter::default()
     }
 
     /// Construct a Counter from an iterator.
@@ -40,6 +604,13 @@ impl<T: Hash + Eq> Counter<T> {
         }
         ctr
     }
+}
+
+impl<T: CounterKey, S: CounterHasher> Counter<T, S> {
+    /// Construct an empty Counter that hashes items with `hasher`.
+    pub fn with_hasher(hasher: S) -> Counter<T, S> {
+        Counter { map: CounterMap::with_hasher(hasher) }
+    }
 
     /// Insert an item in the counter, increasing its count by one.
     pub fn insert(&mut self, item: T) {
@@ -50,15 +621,32 @@ impl<T: Hash + Eq> Counter<T> {
         self.map.insert(item, new_val);
     }
 
+    /// Decrease the count of an item by `n`, removing it from the counter
+    /// once its count reaches zero.
+    pub fn subtract(&mut self, item: &T, n: u64) {
+        let new_val = match self.map.get(item) {
+            Some(&val) => val.saturating_sub(n),
+            None       => return,
+        };
+        if new_val == 0 {
+            self.map.remove(item);
+        } else if let Some(val) = self.map.get_mut(item) {
+            *val = new_val;
+        }
+    }
+
+    /// Decrease the count of an item by one.
+    pub fn decrement(&mut self, item: &T) {
+        self.subtract(item, 1);
+    }
+
     /// Get the count of an item.
     pub fn get(&self, item: &T) -> Option<u64> {
-        match self.map.get(item) {
-            Some(val) => Some(*val),
-            None      => None,
-        }
+        self.map.get(item).cloned()
     }
 
-    /// Get the most frequent item and its frequency.
+    /// Get the most frequent item and its frequency. If several items share
+    /// the highest count, which of them is returned is unspecified.
     pub fn most_frequent(&self) -> Option<(&T, u64)> {
         if self.map.is_empty() {
             return None;
@@ -76,11 +664,87 @@ impl<T: Hash + Eq> Counter<T> {
     }
 
     /// Get an iterator over the counter.
-    pub fn iter(&self) -> Iter<T, u64> {
+    pub fn iter(&self) -> CounterIter<'_, T> {
         self.map.iter()
     }
 }
 
+impl<T: CounterKey, S: CounterHasher + Default> Default for Counter<T, S> {
+    fn default() -> Counter<T, S> {
+        Counter::with_hasher(S::default())
+    }
+}
+
+/// A `Counter` that keeps track of its most frequent item as items are
+/// inserted, making `most_frequent()` O(1) instead of a scan over all
+/// distinct items. Reducing the count of the current maximum falls back to
+/// a rescan.
+pub struct MaxCounter<T> {
+    counter: Counter<T>,
+    max: Option<(T, u64)>,
+}
+
+impl<T: CounterKey + Clone> MaxCounter<T> {
+    /// Construct an empty MaxCounter.
+    pub fn new() -> MaxCounter<T> {
+        MaxCounter::<T> { counter: Counter::new(), max: None }
+    }
+
+    /// Construct a MaxCounter from an iterator.
+    pub fn with_iterator<U>(it: U) -> MaxCounter<T> where U: Iterator<Item=T> {
+        let mut ctr = MaxCounter::new();
+        for item in it {
+            ctr.insert(item);
+        }
+        ctr
+    }
+
+    /// Insert an item in the counter, increasing its count by one.
+    pub fn insert(&mut self, item: T) {
+        let new_val = self.counter.get(&item).unwrap_or(0) + 1;
+        if self.max.as_ref().is_none_or(|&(_, freq)| new_val > freq) {
+            self.max = Some((item.clone(), new_val));
+        }
+        self.counter.insert(item);
+    }
+
+    /// Decrease the count of an item by `n`, removing it from the counter
+    /// once its count reaches zero.
+    pub fn subtract(&mut self, item: &T, n: u64) {
+        self.counter.subtract(item, n);
+        if self.max.as_ref().is_some_and(|(max_item, _)| max_item == item) {
+            self.max = self.counter.most_frequent().map(|(item, freq)| (item.clone(), freq));
+        }
+    }
+
+    /// Decrease the count of an item by one.
+    pub fn decrement(&mut self, item: &T) {
+        self.subtract(item, 1);
+    }
+
+    /// Get the count of an item.
+    pub fn get(&self, item: &T) -> Option<u64> {
+        self.counter.get(item)
+    }
+
+    /// Get the most frequent item and its frequency. As with `Counter`,
+    /// which of several items sharing the highest count is returned is
+    /// unspecified.
+    pub fn most_frequent(&self) -> Option<(&T, u64)> {
+        self.max.as_ref().map(|(item, freq)| (item, *freq))
+    }
+
+    /// Get an iterator over the counter.
+    pub fn iter(&self) -> CounterIter<'_, T> {
+        self.counter.iter()
+    }
+}
+
+impl<T: CounterKey + Clone> Default for MaxCounter<T> {
+    fn default() -> MaxCounter<T> {
+        MaxCounter::new()
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -99,6 +763,113 @@ mod tests {
         assert_eq!(ctr.most_frequent().unwrap(), (&true, 2u64));
     }
 
+    #[test]
+    fn test_imbalance_ratio() {
+        let labels: Vec<u8> = (0..100).map(|i| if i < 90 { 0 } else { 1 }).collect();
+        assert_eq!(imbalance_ratio(&labels), 9.0);
+        assert_eq!(imbalance_ratio(&[1, 2, 3, 1, 2, 3]), 1.0);
+        assert_eq!(imbalance_ratio::<u8>(&[]), 1.0);
+    }
+
+    #[test]
+    fn test_effective_num_classes() {
+        let balanced: Vec<u8> = (0..100).map(|i| i % 4).collect();
+        assert!((effective_num_classes(&balanced) - 4.0).abs() < 1e-12);
+        let skewed: Vec<u16> = (0..1000).map(|i| if i < 995 { 0 } else { 1 + i % 3 }).collect();
+        let effective = effective_num_classes(&skewed);
+        assert!(effective > 1.0 && effective < 1.1, "{}", effective);
+        assert_eq!(effective_num_classes::<u8>(&[]), 1.0);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_counter_custom_hasher() {
+        use std::hash::{BuildHasherDefault, Hasher};
+
+        /// FNV-1a, a fast non-cryptographic hash.
+        struct Fnv(u64);
+
+        impl Default for Fnv {
+            fn default() -> Fnv {
+                Fnv(0xcbf2_9ce4_8422_2325)
+            }
+        }
+
+        impl Hasher for Fnv {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+
+            fn write(&mut self, bytes: &[u8]) {
+                for &b in bytes {
+                    self.0 = (self.0 ^ b as u64).wrapping_mul(0x100_0000_01b3);
+                }
+            }
+        }
+
+        let mut ctr: Counter<&str, BuildHasherDefault<Fnv>> = Counter::default();
+        for word in "the cat and the hat and the bat".split(' ') {
+            ctr.insert(word);
+        }
+        ctr.decrement(&"bat");
+        assert_eq!(ctr.get(&"the"), Some(3));
+        assert_eq!(ctr.get(&"and"), Some(2));
+        assert_eq!(ctr.get(&"bat"), None);
+        assert_eq!(ctr.most_frequent(), Some((&"the", 3)));
+        assert_eq!(ctr.iter().count(), 4);
+    }
+
+    #[test]
+    fn test_counter_subtract() {
+        let mut ctr = Counter::with_iterator(vec!['a', 'a', 'a', 'b'].into_iter());
+        ctr.subtract(&'a', 2);
+        assert_eq!(ctr.get(&'a'), Some(1));
+        ctr.decrement(&'b');
+        assert_eq!(ctr.get(&'b'), None);
+        ctr.decrement(&'c');
+        assert_eq!(ctr.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_max_counter_matches_counter() {
+        // Run random operation sequences against a MaxCounter and a plain
+        // Counter and check that they always agree.
+        for seed in 0..200 {
+            let mut rng = Rng::new(seed);
+            let mut fast: MaxCounter<usize> = MaxCounter::new();
+            let mut naive: Counter<usize> = Counter::new();
+            for _ in 0..100 {
+                let item = rng.gen_range(6);
+                match rng.gen_range(4) {
+                    0 => {
+                        let n = rng.gen_range(3) as u64;
+                        fast.subtract(&item, n);
+                        naive.subtract(&item, n);
+                    },
+                    1 => {
+                        fast.decrement(&item);
+                        naive.decrement(&item);
+                    },
+                    _ => {
+                        fast.insert(item);
+                        naive.insert(item);
+                    },
+                }
+                for i in 0..6 {
+                    assert_eq!(fast.get(&i), naive.get(&i));
+                }
+                match (fast.most_frequent(), naive.most_frequent()) {
+                    (Some((item, freq)), Some((_, naive_freq))) => {
+                        assert_eq!(freq, naive_freq);
+                        assert_eq!(naive.get(item), Some(freq));
+                    },
+                    (None, None) => {},
+                    (fast, naive) => panic!("{:?} != {:?}", fast, naive),
+                }
+            }
+        }
+    }
+
     #[test]
     fn dot_works() {
         let x = vec![1f64, 2.0, 3.0];
@@ -106,4 +877,297 @@ mod tests {
         let dot = dot_product(&x, &y);
         assert_eq!(dot, 9f64);
     }
+
+    #[test]
+    fn test_squared_distance_contributions() {
+        let v1 = [1.0, -2.0, 0.5, 3.0];
+        let v2 = [4.0, -1.0, 0.5, 1.5];
+        let contributions = squared_distance_contributions(&v1, &v2);
+        assert_eq!(contributions, vec![9.0, 1.0, 0.0, 2.25]);
+        assert_eq!(contributions.iter().sum::<f64>(), squared_distance(&v1, &v2));
+    }
+
+    #[test]
+    fn test_column_means() {
+        let data = vec![vec![1.0, 2.0], vec![3.0, 6.0]];
+        assert_eq!(column_means(&data), vec![2.0, 4.0]);
+        assert!(column_means(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_covariance_matrix() {
+        let data = vec![vec![1.0, 2.0, 0.0], vec![2.0, 1.0, 0.0], vec![3.0, 6.0, 0.0], vec![6.0, 3.0, 0.0]];
+        let cov = covariance_matrix(&data);
+        // Means are 3 and 3; the first two features' deviations are
+        // (-2, -1), (-1, -2), (0, 3), (3, 0).
+        assert_eq!(cov[0][0], 14.0 / 3.0);
+        assert_eq!(cov[0][1], 4.0 / 3.0);
+        assert_eq!(cov[1][1], 14.0 / 3.0);
+        assert_eq!(cov[2], vec![0.0; 3]);
+        for (i, row) in cov.iter().enumerate() {
+            for (j, &c) in row.iter().enumerate() {
+                assert_eq!(c, cov[j][i]);
+            }
+        }
+    }
+
+    fn assert_matrix_eq(a: &[Vec<f64>], b: &[Vec<f64>]) {
+        for (ra, rb) in a.iter().zip(b.iter()) {
+            for (x, y) in ra.iter().zip(rb.iter()) {
+                assert!((x - y).abs() < 1e-12, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invert_matrix() {
+        let inv = invert_matrix(&[vec![4.0, 7.0], vec![2.0, 6.0]]).unwrap();
+        assert_matrix_eq(&inv, &[vec![0.6, -0.7], vec![-0.2, 0.4]]);
+
+        // Needs a row swap: the first pivot is zero.
+        let m = vec![vec![0.0, 1.0, 4.0], vec![1.0, 2.0, 3.0], vec![5.0, 6.0, 0.0]];
+        let inv = invert_matrix(&m).unwrap();
+        assert_matrix_eq(&inv, &[vec![18.0, -24.0, 5.0], vec![-15.0, 20.0, -4.0], vec![4.0, -5.0, 1.0]]);
+
+        assert_eq!(invert_matrix(&[vec![1.0, 2.0], vec![2.0, 4.0]]), None);
+        assert_eq!(invert_matrix(&[vec![0.0]]), None);
+    }
+
+    #[test]
+    fn test_matvec_matmul() {
+        let a = vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]];
+        let b = vec![vec![7.0, 8.0], vec![9.0, 10.0], vec![11.0, 12.0]];
+        assert_eq!(matvec(&a, &[1.0, 0.0, -1.0]), vec![-2.0, -2.0]);
+        assert_eq!(matmul(&a, &b), vec![vec![58.0, 64.0], vec![139.0, 154.0]]);
+        assert_eq!(matmul(&b, &a), vec![vec![39.0, 54.0, 69.0], vec![49.0, 68.0, 87.0], vec![59.0, 82.0, 105.0]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "columns of a and rows of b differ")]
+    fn test_matmul_dimension_mismatch() {
+        let a = vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]];
+        matmul(&a, &a);
+    }
+
+    #[test]
+    fn test_cosine_distance() {
+        assert!(cosine_distance(&[1.0, 2.0], &[3.0, 6.0]).abs() < 1e-12);
+        assert!((cosine_distance(&[1.0, 0.0], &[0.0, 5.0]) - 1.0).abs() < 1e-12);
+        assert!((cosine_distance(&[1.0, 1.0], &[-2.0, -2.0]) - 2.0).abs() < 1e-12);
+        assert_eq!(cosine_distance(&[0.0, 0.0], &[1.0, 0.0]), 1.0);
+        assert_eq!(Metric::Cosine.distance(&[0.0, 0.0], &[0.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_quantile() {
+        let values = vec![4.0, 1.0, 3.0, 2.0, 5.0];
+        assert_eq!(quantile(&values, 0.0), 1.0);
+        assert_eq!(quantile(&values, 0.5), 3.0);
+        assert_eq!(quantile(&values, 1.0), 5.0);
+        assert_eq!(quantile(&[1.0, 2.0], 0.25), 1.25);
+        assert!(quantile(&[], 0.5).is_nan());
+    }
+
+    #[test]
+    fn test_running_stats() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        let mut stats = RunningStats::new();
+        assert_eq!((stats.mean(), stats.variance()), (0.0, 0.0));
+        for &v in &values {
+            stats.push(v);
+        }
+        assert_eq!(stats.count(), 8);
+        assert_eq!(stats.mean(), 5.0);
+        assert!((stats.variance() - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_levenshtein_distance() {
+        assert_eq!(levenshtein_distance(b"kitten", b"sitting"), 3f64);
+        assert_eq!(levenshtein_distance(b"", b"abc"), 3f64);
+        assert_eq!(levenshtein_distance(b"same", b"same"), 0f64);
+
+        let x = vec![1f64, 2.0, 3.0, 4.0];
+        let y = vec![1f64, 3.0, 4.0, 5.0, 6.0];
+        assert_eq!(levenshtein_distance(&x, &y), 3f64);
+        assert_eq!(Metric::Levenshtein.distance(&x, &y), 3f64);
+    }
+
+    #[test]
+    fn test_jaccard_distance() {
+        assert_eq!(jaccard_distance(&[1, 2, 3], &[3, 2, 1]), 0.0);
+        assert_eq!(jaccard_distance(&[1, 2], &[3, 4]), 1.0);
+        assert_eq!(jaccard_distance(&[1, 2, 3], &[2, 3, 4]), 0.5);
+        assert_eq!(jaccard_distance::<usize>(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn test_pairwise_distances() {
+        let data = vec![
+            vec![0.0, 0.0],
+            vec![3.0, 4.0],
+            vec![0.0, 1.0],
+        ];
+        let dists = pairwise_distances(&data, Metric::Euclidean);
+
+        assert_eq!(dists.len(), 3);
+        for (i, row) in dists.iter().enumerate() {
+            assert_eq!(row[i], 0f64);
+            for (j, &dist) in row.iter().enumerate() {
+                assert_eq!(dist, dists[j][i]);
+            }
+        }
+        assert_eq!(dists[0][1], 5f64);
+        assert_eq!(dists[0][2], 1f64);
+    }
+
+    #[test]
+    fn test_cosine_similarity_matrix() {
+        let data = vec![
+            vec![1.0, 0.0],
+            vec![3.0, 4.0],
+            vec![0.0, 0.0],
+            vec![-2.0, 0.0],
+        ];
+        let similarity = cosine_similarity_matrix(&data);
+        assert_eq!(similarity.len(), 4);
+        for i in 0..4 {
+            for j in 0..4 {
+                assert_eq!(similarity[i][j], similarity[j][i]);
+                if i != 2 && j != 2 {
+                    assert!((similarity[i][j] - (1.0 - cosine_distance(&data[i], &data[j]))).abs() < 1e-12);
+                }
+            }
+        }
+        assert_eq!(similarity[0][1], 0.6);
+        assert_eq!(similarity[0][3], -1.0);
+        assert_eq!(similarity[1][1], 1.0);
+        assert_eq!(similarity[2], vec![0.0; 4]);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_pairwise_distances_parallel_matches_serial() {
+        let data: Vec<Vec<f64>> = (0..97).map(|i| {
+            let i = i as f64;
+            vec![(i * 0.37).sin(), (i * 1.3).cos() * 10.0, i.sqrt()]
+        }).collect();
+        let dists = pairwise_distances(&data, Metric::Euclidean);
+
+        for (i, x) in data.iter().enumerate() {
+            for (j, y) in data.iter().enumerate() {
+                assert_eq!(dists[i][j].to_bits(), euclidean_distance(x, y).to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn test_rng_reproducible() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            let x = a.next_f64();
+            assert_eq!(x, b.next_f64());
+            assert!((0.0..1.0).contains(&x));
+            assert!(a.gen_range(7) < 7);
+            b.gen_range(7);
+        }
+    }
+
+    #[test]
+    fn test_next_gaussian() {
+        let mut stats = RunningStats::new();
+        let mut rng = Rng::new(4);
+        for _ in 0..20000 {
+            stats.push(rng.next_gaussian());
+        }
+        assert!(stats.mean().abs() < 0.03);
+        assert!((stats.variance() - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_train_validation_split() {
+        let (train, val) = train_validation_split(10, 0.3, Some(RandomState::new(4)));
+        assert_eq!((train.len(), val.len()), (7, 3));
+        let mut all: Vec<usize> = train.iter().chain(val.iter()).cloned().collect();
+        all.sort();
+        assert_eq!(all, (0..10).collect::<Vec<_>>());
+        assert_eq!(train_validation_split(10, 0.3, Some(RandomState::new(4))), (train, val));
+        assert_eq!(train_validation_split(10, 0.3, None), train_validation_split(10, 0.3, None));
+
+        assert_eq!(train_validation_split(3, 0.01, None).1.len(), 1);
+        assert_eq!(train_validation_split(2, 0.9, None).0.len(), 1);
+        assert_eq!(train_validation_split(5, 0.0, None), (vec![0, 1, 2, 3, 4], vec![]));
+    }
+
+    #[test]
+    fn test_random_state_split() {
+        let mut parent = RandomState::new(9);
+        let mut first = parent.split();
+        let mut second = parent.split();
+
+        // Replaying the parent gives the same children even if the first
+        // child is drained before the second is derived.
+        let mut replay = RandomState::new(9);
+        let mut first_again = replay.split();
+        for _ in 0..100 {
+            first_again.rng().next_u64();
+        }
+        let mut second_again = replay.split();
+        let draws = |state: &mut RandomState| -> Vec<u64> { (0..5).map(|_| state.rng().next_u64()).collect() };
+        assert_eq!(draws(&mut second), draws(&mut second_again));
+        assert_ne!(draws(&mut first), draws(&mut second));
+
+        #[cfg(feature = "std")]
+        assert_ne!(RandomState::from_entropy().rng().next_u64(), RandomState::from_entropy().rng().next_u64());
+    }
+
+    #[test]
+    fn test_reservoir_sample() {
+        assert_eq!(reservoir_sample(0..3, 5, None), vec![0, 1, 2]);
+        assert_eq!(reservoir_sample(0..100, 10, None).len(), 10);
+
+        // Every element of the stream should be included with probability
+        // k / n = 0.3.
+        let runs = 20000;
+        let mut counts = [0usize; 10];
+        for seed in 0..runs {
+            for item in reservoir_sample(0..10, 3, Some(RandomState::new(seed))) {
+                counts[item] += 1;
+            }
+        }
+        let expected = runs as f64 * 0.3;
+        for &count in counts.iter() {
+            assert!((count as f64 - expected).abs() < 0.05 * expected);
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[test]
+    fn test_counter_ordered_without_std() {
+        let ctr = Counter::with_iterator(vec![3, 1, 2, 1, 3, 3].into_iter());
+        let items: Vec<(&i32, &u64)> = ctr.iter().collect();
+        assert_eq!(items, vec![(&1, &2), (&2, &1), (&3, &3)]);
+        assert_eq!(ctr.most_frequent().unwrap(), (&3, 3u64));
+        assert_eq!(euclidean_distance(&[0.0, 3.0], &[4.0, 0.0]), 5f64);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_group_by_label() {
+        let data = vec![
+            vec![0.0, 0.0],
+            vec![1.0, 1.0],
+            vec![2.0, 2.0],
+            vec![3.0, 3.0],
+            vec![4.0, 4.0],
+        ];
+        let labels = vec!["a", "b", "a", "c", "b"];
+        let groups = group_by_label(&data, &labels);
+
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[&"a"], vec![0, 2]);
+        assert_eq!(groups[&"b"], vec![1, 4]);
+        assert_eq!(groups[&"c"], vec![3]);
+    }
 }
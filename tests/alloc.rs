@@ -0,0 +1,69 @@
+//! Checks that steady-state prediction into caller-provided buffers doesn't
+//! touch the heap. A counting global allocator records allocations made on
+//! the current thread.
+
+#![cfg(feature = "std")]
+
+extern crate oxide;
+
+use oxide::core::Classifier;
+use oxide::knn::KNNClassifier;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|n| n.get())
+}
+
+#[test]
+fn test_predict_into_steady_state_allocations() {
+    let mut clf = KNNClassifier::new(3);
+    let train: Vec<Vec<f64>> = (0..50).map(|i| vec![i as f64, (i % 7) as f64]).collect();
+    let labels: Vec<u32> = (0..50).map(|i| i / 10).collect();
+    clf.fit(train, labels);
+    clf.set_prior_correction(true);
+
+    let test: Vec<Vec<f64>> = (0..20).map(|i| vec![i as f64 * 2.5, 3.0]).collect();
+    let mut out = Vec::new();
+    let mut indices = Vec::new();
+    let mut distances = Vec::new();
+
+    // The first calls size the buffers.
+    clf.predict_into(&test, &mut out).unwrap();
+    clf.kneighbors_into(&test[0], &mut indices, &mut distances).unwrap();
+
+    let before = allocations();
+    for _ in 0..10 {
+        clf.predict_into(&test, &mut out).unwrap();
+        for x in &test {
+            clf.kneighbors_into(x, &mut indices, &mut distances).unwrap();
+        }
+    }
+    assert_eq!(allocations(), before);
+    assert_eq!(out, clf.predict(&test).unwrap());
+}
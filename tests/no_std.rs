@@ -0,0 +1,58 @@
+//! Exercises the primitives available without the standard library, from a
+//! `no_std` crate, against a build with `--no-default-features --features
+//! libm`: the distance helpers, the BTreeMap-backed `Counter`, and the float
+//! math routed through `libm`.
+
+#![cfg(not(feature = "std"))]
+#![no_std]
+
+extern crate alloc;
+extern crate oxide;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use oxide::util::{cosine_distance, cosine_similarity_matrix, euclidean_distance, pairwise_distances, quantile,
+                  squared_distance, Counter, Metric, Rng, RunningStats};
+
+#[test]
+fn test_distances() {
+    assert_eq!(euclidean_distance(&[0.0, 3.0], &[4.0, 0.0]), 5.0);
+    assert_eq!(squared_distance(&[1.0, 2.0], &[3.0, 5.0]), 13.0);
+    assert!((cosine_distance(&[1.0, 0.0], &[1.0, 1.0]) - 0.292_893_218_813_452_5).abs() < 1e-12);
+    assert_eq!(Metric::Levenshtein.distance(&[1.0, 2.0, 3.0], &[1.0, 3.0]), 1.0);
+
+    let data = vec![vec![0.0, 0.0], vec![3.0, 4.0], vec![0.0, 1.0]];
+    let dists = pairwise_distances(&data, Metric::Euclidean);
+    assert_eq!(dists[0], vec![0.0, 5.0, 1.0]);
+    assert!((dists[1][2] - 4.242_640_687_119_285).abs() < 1e-12);
+    assert_eq!(dists[1][2], dists[2][1]);
+    assert_eq!(cosine_similarity_matrix(&data)[0], vec![0.0; 3]);
+    assert_eq!(cosine_similarity_matrix(&data)[1][2], 0.8);
+}
+
+#[test]
+fn test_counter_is_ordered() {
+    let ctr = Counter::with_iterator(vec!["b", "a", "c", "a", "b", "b"].into_iter());
+    // Backed by a BTreeMap, so iteration follows the keys' order.
+    let items: Vec<(&&str, &u64)> = ctr.iter().collect();
+    assert_eq!(items, vec![(&"a", &2), (&"b", &3), (&"c", &1)]);
+    assert_eq!(ctr.most_frequent().unwrap(), (&"b", 3));
+}
+
+#[test]
+fn test_float_math() {
+    let mut stats = RunningStats::new();
+    for v in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+        stats.push(v);
+    }
+    assert_eq!(stats.mean(), 5.0);
+    assert!((stats.variance() - 4.0).abs() < 1e-12);
+
+    // Gaussian draws go through ln, sqrt and cos.
+    let mut rng = Rng::new(7);
+    let draws: Vec<f64> = (0..20_000).map(|_| rng.next_gaussian()).collect();
+    let mean = draws.iter().sum::<f64>() / draws.len() as f64;
+    let var = draws.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / draws.len() as f64;
+    assert!(mean.abs() < 0.03 && (var - 1.0).abs() < 0.05, "{} {}", mean, var);
+    assert!((quantile(&draws, 0.5)).abs() < 0.05);
+}